@@ -0,0 +1,133 @@
+// Timing the content grouping, the hot path, on trees of files built for the occasion: `cargo
+// bench` (or `cargo bench -- identical`, say, for just the cases with that in their names).
+//
+// Each case is a directory of same-sized files, so that every file is shortlisted and compared.
+// The worst case is files that are all different, but only at the end, so that each comparison
+// reads everything (and each file is compared with every other); the best is files that are all
+// the same.  We time whole runs of the program, as it would be used, and report the fastest and
+// the median of a few.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+struct Case {
+    name: &'static str,
+    files: usize,
+    size: usize,
+    // How many different contents the files share out between them.
+    distinct: usize,
+    // Any options to run with, beyond those every case has.
+    args: &'static [&'static str],
+}
+
+const CASES: [Case; 7] = [
+    Case {
+        name: "distinct-4KiB",
+        files: 200,
+        size: 4 << 10,
+        distinct: 200,
+        args: &[],
+    },
+    Case {
+        name: "identical-4KiB",
+        files: 200,
+        size: 4 << 10,
+        distinct: 1,
+        args: &[],
+    },
+    Case {
+        name: "distinct-1MiB",
+        files: 64,
+        size: 1 << 20,
+        distinct: 64,
+        args: &[],
+    },
+    // The same, but reading the files in order of inode number, roughly their order on disk.
+    Case {
+        name: "distinct-1MiB-inode-order",
+        files: 64,
+        size: 1 << 20,
+        distinct: 64,
+        args: &["--inode-order"],
+    },
+    Case {
+        name: "half-1MiB",
+        files: 64,
+        size: 1 << 20,
+        distinct: 32,
+        args: &[],
+    },
+    Case {
+        name: "identical-1MiB",
+        files: 64,
+        size: 1 << 20,
+        distinct: 1,
+        args: &[],
+    },
+    Case {
+        name: "pairs-16MiB",
+        files: 8,
+        size: 16 << 20,
+        distinct: 4,
+        args: &[],
+    },
+];
+
+fn main() {
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let root = env::temp_dir().join(format!("find_dupes-bench-{}", process::id()));
+    for case in CASES
+        .iter()
+        .filter(|case| filter.as_ref().is_none_or(|f| case.name.contains(f)))
+    {
+        let dir = root.join(case.name);
+        build_fixture(&dir, case);
+        let mut times: Vec<Duration> = (0..RUNS).map(|_| time_run(&dir, case.args)).collect();
+        times.sort();
+        let total = (case.files * case.size) as f64;
+        println!(
+            "{:<26} {:>5} files  fastest {:>9.2?}  median {:>9.2?}  {:>8.1} MiB/s",
+            case.name,
+            case.files,
+            times[0],
+            times[RUNS / 2],
+            total / times[0].as_secs_f64() / (1 << 20) as f64
+        );
+        fs::remove_dir_all(&dir).expect("Failed to remove benchmark fixture");
+    }
+    let _ = fs::remove_dir(&root);
+}
+
+// Write the files for a case: `case.distinct` different contents, shared out in turn, each the
+// same but for its last eight bytes.
+fn build_fixture(dir: &Path, case: &Case) {
+    fs::create_dir_all(dir).expect("Failed to create benchmark fixture");
+    let mut content: Vec<u8> = (0..case.size).map(|i| (i % 251) as u8).collect();
+    for i in 0..case.files {
+        let variant = (i % case.distinct) as u64;
+        content[case.size - 8..].copy_from_slice(&variant.to_le_bytes());
+        let path: PathBuf = dir.join(format!("file-{:05}", i));
+        fs::write(path, &content).expect("Failed to write benchmark fixture");
+    }
+}
+
+// How long the program takes to find the duplicates in `dir`, with these options and the report
+// thrown away.
+fn time_run(dir: &Path, args: &[&str]) -> Duration {
+    let start = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_find_dupes"))
+        .args(["--min-size", "1", "--format", "json"])
+        .args(args)
+        .arg(dir)
+        .stdout(Stdio::null())
+        .status()
+        .expect("Failed to run find_dupes");
+    let elapsed = start.elapsed();
+    assert!(status.success(), "find_dupes failed on {:?}", dir);
+    elapsed
+}
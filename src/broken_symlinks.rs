@@ -0,0 +1,40 @@
+// Symlinks whose targets don't exist, for `--find-broken-symlinks`.
+//
+// These are never duplicates of anything, but they're as much in need of cleaning up, so the
+// traversal notes each one it comes across (whether or not it follows symlinks) and we list them
+// after the report.  They're kept in order of path, and each is only noted once, since the sizes
+// of the files in a tree are sometimes found by traversing it an extra time.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Each broken symlink and the target it leads to.
+static BROKEN_SYMLINKS: Mutex<BTreeMap<PathBuf, PathBuf>> = Mutex::new(BTreeMap::new());
+
+pub fn note_broken_symlink(path: &Path, target: PathBuf) {
+    BROKEN_SYMLINKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(path.to_path_buf(), target);
+}
+
+// Every broken symlink noted so far, with its target, in order of path.
+pub fn take_broken_symlinks() -> Vec<(PathBuf, PathBuf)> {
+    std::mem::take(&mut *BROKEN_SYMLINKS.lock().unwrap_or_else(|e| e.into_inner()))
+        .into_iter()
+        .collect()
+}
+
+// Write a line for each broken symlink, with the target it leads to.
+pub fn write_broken_symlinks(
+    dest: &mut impl Write,
+    symlinks: &[(PathBuf, PathBuf)],
+) -> io::Result<()> {
+    writeln!(dest, "Broken symlinks: {}", symlinks.len())?;
+    for (path, target) in symlinks {
+        writeln!(dest, "  {} -> {}", path.display(), target.display())?;
+    }
+    Ok(())
+}
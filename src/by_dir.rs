@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::keep::{is_kept, select_representative, KeepPolicy};
+use crate::summary::frees_space;
+use crate::DedupFile;
+
+// A copy of a file that we could remove, since we'd keep another.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RedundantFile {
+    pub path: PathBuf,
+    pub size: u64,
+    // Where the copy we'd keep lives.
+    pub kept: PathBuf,
+}
+
+// Redundant copies by the directory that holds them, so that they can be cleaned up one directory
+// at a time.
+pub type DirListing = BTreeMap<PathBuf, Vec<RedundantFile>>;
+
+// Reorganise groups of duplicates by directory.  In each group the file chosen by the `keep`
+// policy stays where it is, as do any protected files, and every path to any of the others is
+// redundant.  Directories are in order of their paths, as are the files in each.
+pub fn list_by_dir(groups: &[Vec<DedupFile>], keep: KeepPolicy) -> DirListing {
+    let mut listing = DirListing::new();
+    for group in groups {
+        let kept = select_representative(group, keep);
+        for (i, df) in group.iter().enumerate() {
+            if is_kept(group, i, keep) {
+                continue;
+            }
+            for path in &df.paths {
+                let dir = match path.parent() {
+                    Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+                    _ => PathBuf::from("."),
+                };
+                listing.entry(dir).or_default().push(RedundantFile {
+                    path: path.clone(),
+                    size: df.size,
+                    kept: group[kept].paths[0].clone(),
+                });
+            }
+        }
+    }
+    for files in listing.values_mut() {
+        files.sort();
+    }
+    listing
+}
+
+// How much space we could reclaim under each directory `depth` levels below `root`, for
+// `--savings-by-dir`: each redundant copy (as for `list_by_dir`) counts towards the directory at
+// that depth holding it, or the one directly holding it if it's not that deep.  A file with hard
+// links only frees space once all of them are gone, so it's counted once, by its first path, and
+// its bytes not at all if it has links we haven't found (see `frees_space`).
+// Paths not under the root (or all of them, if there isn't one) are counted from the top.  The
+// result has the number of redundant files and bytes for each directory, most bytes first.
+pub fn savings_by_dir(
+    groups: &[Vec<DedupFile>],
+    keep: KeepPolicy,
+    root: Option<&Path>,
+    depth: usize,
+) -> Vec<(PathBuf, u64, u64)> {
+    let mut savings: BTreeMap<PathBuf, (u64, u64)> = BTreeMap::new();
+    for group in groups {
+        for (i, df) in group.iter().enumerate() {
+            if is_kept(group, i, keep) {
+                continue;
+            }
+            let totals = savings
+                .entry(dir_at_depth(&df.paths[0], root, depth))
+                .or_default();
+            totals.0 += 1;
+            if frees_space(df) {
+                totals.1 += df.size;
+            }
+        }
+    }
+    let mut rows: Vec<(PathBuf, u64, u64)> = savings
+        .into_iter()
+        .map(|(dir, (files, bytes))| (dir, files, bytes))
+        .collect();
+    rows.sort_by(|(dir1, _, bytes1), (dir2, _, bytes2)| {
+        bytes2.cmp(bytes1).then_with(|| dir1.cmp(dir2))
+    });
+    rows
+}
+
+// The directory holding a path, cut off `depth` levels below `root` (or below the top).
+fn dir_at_depth(path: &Path, root: Option<&Path>, depth: usize) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let (mut result, rest) =
+        match root.and_then(|root| dir.strip_prefix(root).ok().map(|rest| (root, rest))) {
+            Some((root, rest)) => (root.to_path_buf(), rest),
+            None => (PathBuf::new(), dir),
+        };
+    let mut components = rest.components().peekable();
+    while let Some(Component::RootDir | Component::Prefix(_)) = components.peek() {
+        result.extend(components.next());
+    }
+    result.extend(components.take(depth));
+    if result.as_os_str().is_empty() {
+        result.push(".");
+    }
+    result
+}
+
+// Write reclaimable space by directory as tab-separated lines of the directory, the number of
+// redundant files and the bytes they take up.
+pub fn write_savings_by_dir(dest: &mut impl Write, rows: &[(PathBuf, u64, u64)]) -> io::Result<()> {
+    for (dir, files, bytes) in rows {
+        writeln!(dest, "{}\t{}\t{}", dir.display(), files, bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_file;
+
+    fn redundant(path: &str, size: u64, kept: &str) -> RedundantFile {
+        RedundantFile {
+            path: PathBuf::from(path),
+            size,
+            kept: PathBuf::from(kept),
+        }
+    }
+
+    #[test]
+    fn redundant_copies_are_listed_by_directory_with_the_copy_kept() {
+        let mut protected = fake_file(&["y/3"], 10, 4);
+        protected.protected = true;
+        let groups = vec![
+            vec![
+                fake_file(&["x/1"], 10, 1),
+                fake_file(&["y/2"], 10, 2),
+                fake_file(&["y/1"], 10, 3),
+                protected,
+            ],
+            vec![
+                fake_file(&["y/big"], 99, 5),
+                fake_file(&["z/big", "z/link"], 99, 6),
+                fake_file(&["top"], 99, 7),
+            ],
+        ];
+        let listing = list_by_dir(&groups, KeepPolicy::First);
+        let expected = DirListing::from([
+            (PathBuf::from("."), vec![redundant("top", 99, "y/big")]),
+            // A protected file is the one kept, if there is one.
+            (PathBuf::from("x"), vec![redundant("x/1", 10, "y/3")]),
+            (
+                PathBuf::from("y"),
+                vec![redundant("y/1", 10, "y/3"), redundant("y/2", 10, "y/3")],
+            ),
+            (
+                PathBuf::from("z"),
+                vec![
+                    redundant("z/big", 99, "y/big"),
+                    redundant("z/link", 99, "y/big"),
+                ],
+            ),
+        ]);
+        assert_eq!(listing, expected);
+    }
+
+    #[test]
+    fn the_keep_policy_decides_what_is_redundant() {
+        let groups = vec![vec![
+            fake_file(&["old/a"], 10, 1),
+            fake_file(&["new/a/deeper"], 10, 2),
+        ]];
+        let listing = list_by_dir(&groups, KeepPolicy::LongestPath);
+        assert_eq!(
+            listing,
+            DirListing::from([(
+                PathBuf::from("old"),
+                vec![redundant("old/a", 10, "new/a/deeper")]
+            )])
+        );
+    }
+
+    #[test]
+    fn savings_are_counted_under_the_directory_at_the_depth_given() {
+        let mut unfound_links = fake_file(&["/r/photos/2021/c"], 1000, 8);
+        unfound_links.nlink = 2;
+        let groups = vec![
+            vec![
+                fake_file(&["/r/photos/2020/a"], 10, 1),
+                fake_file(&["/r/photos/2021/a"], 10, 2),
+                fake_file(&["/r/music/a"], 10, 3),
+                fake_file(&["/elsewhere/deep/a"], 10, 4),
+            ],
+            vec![
+                fake_file(&["/r/music/b"], 100, 5),
+                fake_file(&["/r/photos/2020/b"], 100, 6),
+                fake_file(&["/r/photos/2020/c"], 100, 7),
+            ],
+            vec![fake_file(&["/r/music/c"], 1000, 9), unfound_links],
+        ];
+        let savings = |depth| {
+            savings_by_dir(&groups, KeepPolicy::First, Some(Path::new("/r")), depth)
+                .into_iter()
+                .map(|(dir, files, bytes)| (dir.to_str().unwrap().to_owned(), files, bytes))
+                .collect::<Vec<_>>()
+        };
+        let row = |dir: &str, files, bytes| (dir.to_owned(), files, bytes);
+        assert_eq!(savings(0), [row("/r", 5, 220), row("/", 1, 10)]);
+        assert_eq!(
+            savings(1),
+            [
+                row("/r/photos", 4, 210),
+                row("/elsewhere", 1, 10),
+                row("/r/music", 1, 10)
+            ]
+        );
+        assert_eq!(
+            savings(2),
+            [
+                row("/r/photos/2020", 2, 200),
+                row("/elsewhere/deep", 1, 10),
+                row("/r/music", 1, 10),
+                row("/r/photos/2021", 2, 10)
+            ]
+        );
+
+        // The order groups come in doesn't matter.
+        let reversed: Vec<Vec<DedupFile>> = groups.iter().rev().cloned().collect();
+        assert_eq!(
+            savings_by_dir(&reversed, KeepPolicy::First, Some(Path::new("/r")), 1),
+            savings_by_dir(&groups, KeepPolicy::First, Some(Path::new("/r")), 1)
+        );
+
+        let mut dest = Vec::new();
+        write_savings_by_dir(
+            &mut dest,
+            &savings_by_dir(&groups, KeepPolicy::First, Some(Path::new("/r")), 0),
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(dest).unwrap(), "/r\t5\t220\n/\t1\t10\n");
+    }
+}
@@ -0,0 +1,176 @@
+// Saving and resuming a traversal, for `--resume`.
+//
+// A checkpoint is a JSON Lines file.  The first line says which root it's for and how far we'd
+// got, e.g. `{"root": "/data", "yielded": 120, "entries_seen": 300, ...}`.  Then come the
+// directories still to be read (`{"dir": ..., "ancestors": [[device, inode], ...]}`), those
+// already visited by path (`{"seen_dir": ...}`) and finally every file found so far, one record
+// each in the same form as a file dump.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::dump::{file_from_record, write_file_record};
+use crate::group_by_inode::{TraversalState, TraversalStats};
+use crate::json::{parse_json, write_json_path, JsonValue};
+use crate::DedupFile;
+
+// How many directories to read between checkpoints.  Each one rewrites the whole file list, so
+// not too often.
+pub const CHECKPOINT_INTERVAL: u64 = 1000;
+
+// A saved traversal.
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub root: PathBuf,
+    pub state: TraversalState,
+    pub files: Vec<DedupFile>,
+}
+
+// Save a traversal of `root`.  We write to a temporary file and rename it into place, so that
+// being interrupted part-way through leaves the previous checkpoint intact.
+pub fn write_checkpoint<'a>(
+    path: &Path,
+    root: &Path,
+    state: &TraversalState,
+    files: impl IntoIterator<Item = &'a DedupFile>,
+) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut dest = BufWriter::new(
+        File::create(&tmp_path)
+            .with_context(|| format!("Failed to create checkpoint {:?}", tmp_path))?,
+    );
+    write_state(&mut dest, root, state, files)
+        .and_then(|_| dest.flush())
+        .with_context(|| format!("Failed to write checkpoint {:?}", tmp_path))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to move checkpoint into place at {:?}", path))
+}
+
+fn write_state<'a>(
+    dest: &mut impl Write,
+    root: &Path,
+    state: &TraversalState,
+    files: impl IntoIterator<Item = &'a DedupFile>,
+) -> io::Result<()> {
+    let stats = state.stats;
+    write!(dest, "{{\"root\": ")?;
+    write_json_path(dest, root)?;
+    writeln!(
+        dest,
+        ", \"yielded\": {}, \"entries_seen\": {}, \"files_considered\": {}, \
+         \"files_too_new\": {}, \"files_too_young\": {}, \"files_not_owned\": {}, \
+         \"dirs_read\": {}}}",
+        state.yielded,
+        stats.entries_seen,
+        stats.files_considered,
+        stats.files_too_new,
+        stats.files_too_young,
+        stats.files_not_owned,
+        stats.dirs_read
+    )?;
+    for (dir, ancestors) in &state.dir_queue {
+        write!(dest, "{{\"dir\": ")?;
+        write_json_path(dest, dir)?;
+        write!(dest, ", \"ancestors\": [")?;
+        for (i, (device, inode)) in ancestors.iter().enumerate() {
+            write!(
+                dest,
+                "{}[{}, {}]",
+                if i == 0 { "" } else { ", " },
+                device,
+                inode
+            )?;
+        }
+        writeln!(dest, "]}}")?;
+    }
+    for dir in &state.seen_dir_paths {
+        write!(dest, "{{\"seen_dir\": ")?;
+        write_json_path(dest, dir)?;
+        writeln!(dest, "}}")?;
+    }
+    for df in files {
+        write_file_record(dest, df)?;
+    }
+    Ok(())
+}
+
+// Load a checkpoint, if there is one yet.
+pub fn read_checkpoint(path: &Path) -> Result<Option<Checkpoint>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read checkpoint {:?}", path)),
+    };
+    parse_checkpoint(&content)
+        .map(Some)
+        .with_context(|| format!("Bad checkpoint {:?}", path))
+}
+
+fn parse_checkpoint(content: &str) -> Result<Checkpoint> {
+    let mut lines = content.lines().enumerate();
+    let (_, header) = lines.next().ok_or_else(|| anyhow!("Empty checkpoint"))?;
+    let header = parse_json(header).context("Failed to parse line 1")?;
+    let count = |key| {
+        header
+            .get(key)
+            .and_then(JsonValue::as_u64)
+            .ok_or_else(|| anyhow!("Missing {:?} on line 1", key))
+    };
+    let mut checkpoint = Checkpoint {
+        root: header
+            .get("root")
+            .and_then(JsonValue::as_path)
+            .ok_or_else(|| anyhow!("Missing \"root\" on line 1"))?,
+        state: TraversalState {
+            yielded: count("yielded")?,
+            stats: TraversalStats {
+                entries_seen: count("entries_seen")?,
+                files_considered: count("files_considered")?,
+                files_too_new: count("files_too_new")?,
+                files_too_young: count("files_too_young")?,
+                files_not_owned: count("files_not_owned")?,
+                dirs_read: count("dirs_read")?,
+            },
+            ..TraversalState::default()
+        },
+        files: Vec::new(),
+    };
+
+    for (i, line) in lines {
+        let record = parse_json(line).with_context(|| format!("Failed to parse line {}", i + 1))?;
+        if let Some(dir) = record.get("dir") {
+            checkpoint.state.dir_queue.push(
+                dir_from_record(dir, &record)
+                    .with_context(|| format!("Bad directory record on line {}", i + 1))?,
+            );
+        } else if let Some(dir) = record.get("seen_dir") {
+            let Some(dir) = dir.as_path() else {
+                bail!("Bad directory record on line {}", i + 1);
+            };
+            checkpoint.state.seen_dir_paths.push(dir);
+        } else {
+            let df = file_from_record(&record)
+                .with_context(|| format!("Bad file record on line {}", i + 1))?;
+            checkpoint.files.push(df);
+        }
+    }
+    Ok(checkpoint)
+}
+
+fn dir_from_record(dir: &JsonValue, record: &JsonValue) -> Option<(PathBuf, Vec<(u64, u64)>)> {
+    let ancestors = record
+        .get("ancestors")?
+        .as_array()?
+        .iter()
+        .map(|pair| match pair.as_array()? {
+            [device, inode] => Some((device.as_u64()?, inode.as_u64()?)),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    Some((dir.as_path()?, ancestors))
+}
@@ -0,0 +1,372 @@
+// Settings read from a `--config` file.
+//
+// The file is TOML, but we only need flat `key = value` pairs, so rather than take on a TOML
+// library we parse that subset here: comments, bare or quoted keys, basic and literal strings,
+// integers and (possibly multi-line) arrays.  Tables and the rest of TOML are rejected
+// with an error, as are unknown keys, so that a typo doesn't silently do nothing.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::options::{parse_size_threshold, OutputFormat, SizeThreshold};
+
+// Everything a config file may set.  Anything left out is `None`, so that we can tell it apart
+// from a value that happens to equal the default.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub min_size: Option<SizeThreshold>,
+    pub max_size: Option<SizeThreshold>,
+    pub exclude: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub format: Option<OutputFormat>,
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+enum TomlValue {
+    String(String),
+    Integer(i64),
+    Array(Vec<TomlValue>),
+}
+
+impl TomlValue {
+    fn type_name(&self) -> &'static str {
+        match self {
+            TomlValue::String(_) => "string",
+            TomlValue::Integer(_) => "integer",
+            TomlValue::Array(_) => "array",
+        }
+    }
+}
+
+// Read and parse a config file.
+pub fn read_config(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {:?}", path))?;
+    parse_config(&text).with_context(|| format!("Failed to parse config file {:?}", path))
+}
+
+fn parse_config(text: &str) -> Result<Config> {
+    let mut config = Config::default();
+    let mut seen_keys = HashSet::new();
+    let mut parser = TomlParser {
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    while let Some((key, value)) = parser.next_pair()? {
+        if !seen_keys.insert(key.clone()) {
+            bail!("Duplicate key {:?}", key);
+        }
+        let context = || format!("Bad value for {:?}", key);
+        match key.as_str() {
+            "min_size" => config.min_size = Some(size_value(value).with_context(context)?),
+            "max_size" => config.max_size = Some(size_value(value).with_context(context)?),
+            "exclude" => config.exclude = Some(string_array_value(value).with_context(context)?),
+            "include" => config.include = Some(string_array_value(value).with_context(context)?),
+            "format" => {
+                let name = string_value(value).with_context(context)?;
+                config.format = Some(OutputFormat::from_name(&name).with_context(context)?);
+            }
+            "output" => config.output = Some(string_value(value).with_context(context)?.into()),
+            _ => bail!("Unknown key {:?}", key),
+        }
+    }
+    Ok(config)
+}
+
+fn string_value(value: TomlValue) -> Result<String> {
+    match value {
+        TomlValue::String(s) => Ok(s),
+        v => bail!("Expected a string, got {}", v.type_name()),
+    }
+}
+
+fn string_array_value(value: TomlValue) -> Result<Vec<String>> {
+    match value {
+        TomlValue::Array(values) => values.into_iter().map(string_value).collect(),
+        v => bail!("Expected an array of strings, got {}", v.type_name()),
+    }
+}
+
+// A size may be given as a plain number of bytes or as a string such as "10MiB" or "p90", just
+// like on the command line.
+fn size_value(value: TomlValue) -> Result<SizeThreshold> {
+    match value {
+        TomlValue::Integer(n) => u64::try_from(n)
+            .map(SizeThreshold::Bytes)
+            .map_err(|_| anyhow!("Expected a non-negative size, got {}", n)),
+        TomlValue::String(s) => parse_size_threshold(&s),
+        v => bail!("Expected an integer or a string, got {}", v.type_name()),
+    }
+}
+
+struct TomlParser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl TomlParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, msg: &str) -> anyhow::Error {
+        anyhow!("{} on line {}", msg, self.line)
+    }
+
+    // Skip spaces and tabs, and a comment if there is one, but not the end of the line.
+    fn skip_inline_space(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.bump();
+        }
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    // Skip whitespace, comments and line breaks.
+    fn skip_space(&mut self) {
+        loop {
+            self.skip_inline_space();
+            match self.peek() {
+                Some('\n') | Some('\r') => {
+                    self.bump();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("Expected {:?}", expected))),
+        }
+    }
+
+    // Parse the next `key = value` line, or return `None` at the end of the file.
+    fn next_pair(&mut self) -> Result<Option<(String, TomlValue)>> {
+        self.skip_space();
+        let key = match self.peek() {
+            None => return Ok(None),
+            Some('[') => return Err(self.error("Tables are not supported")),
+            Some('"') => self.basic_string()?,
+            Some('\'') => self.literal_string()?,
+            Some(_) => self.bare_key()?,
+        };
+        self.skip_inline_space();
+        self.expect('=')?;
+        self.skip_inline_space();
+        let value = self.value()?;
+
+        // Nothing else may follow on the same line.
+        self.skip_inline_space();
+        if self.peek() == Some('\r') {
+            self.bump();
+        }
+        match self.peek() {
+            None | Some('\n') => Ok(Some((key, value))),
+            _ => Err(self.error("Expected the end of the line")),
+        }
+    }
+
+    fn bare_key(&mut self) -> Result<String> {
+        let mut key = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|&c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            key.push(c);
+            self.bump();
+        }
+        if key.is_empty() {
+            return Err(self.error("Expected a key"));
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<TomlValue> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(TomlValue::String),
+            Some('\'') => self.literal_string().map(TomlValue::String),
+            Some('[') => self.array(),
+            Some(c) if c == '-' || c == '+' || c.is_ascii_digit() => self.integer(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn array(&mut self) -> Result<TomlValue> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip_space();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(TomlValue::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_space();
+            match self.bump() {
+                Some(',') => {}
+                Some(']') => return Ok(TomlValue::Array(values)),
+                _ => return Err(self.error("Expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn integer(&mut self) -> Result<TomlValue> {
+        let mut digits = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|&c| c.is_ascii_digit() || c == '_' || c == '-' || c == '+')
+        {
+            if c != '_' {
+                digits.push(c);
+            }
+            self.bump();
+        }
+        digits
+            .parse()
+            .map(TomlValue::Integer)
+            .map_err(|_| self.error(&format!("Bad integer {:?}", digits)))
+    }
+
+    // A double-quoted string, with backslash escapes.
+    fn basic_string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error("Unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => s.push(self.unicode_escape(4)?),
+                    Some('U') => s.push(self.unicode_escape(8)?),
+                    _ => return Err(self.error("Bad escape in string")),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, len: usize) -> Result<char> {
+        let hex: String = (0..len).filter_map(|_| self.bump()).collect();
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(&format!("Bad unicode escape {:?}", hex)))
+    }
+
+    // A single-quoted string, taken exactly as written.
+    fn literal_string(&mut self) -> Result<String> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error("Unterminated string")),
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_can_be_set() {
+        let config = parse_config(
+            r#"
+            # Settings for the photo archive.
+            min_size = "1MiB"
+            max_size = 4_000_000_000   # bytes
+            exclude = [
+                "*.tmp",
+                '.cache',   # a literal string
+            ]
+            "include" = ["*.jpg"]
+            format = "json"
+            output = '/tmp/report.json'
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.min_size, Some(SizeThreshold::Bytes(1 << 20)));
+        assert_eq!(config.max_size, Some(SizeThreshold::Bytes(4_000_000_000)));
+        assert_eq!(config.exclude.unwrap(), ["*.tmp", ".cache"]);
+        assert_eq!(config.include.unwrap(), ["*.jpg"]);
+        assert_eq!(config.format, Some(OutputFormat::Json));
+        assert_eq!(config.output, Some(PathBuf::from("/tmp/report.json")));
+    }
+
+    #[test]
+    fn anything_left_out_is_none() {
+        let config = parse_config("min_size = \"p90\"\r\n").unwrap();
+        assert_eq!(config.min_size, Some(SizeThreshold::Percentile(90.0)));
+        assert!(config.max_size.is_none() && config.exclude.is_none() && config.format.is_none());
+        assert!(parse_config("").unwrap().min_size.is_none());
+    }
+
+    #[test]
+    fn strings_have_escapes_and_literal_strings_do_not() {
+        let config =
+            parse_config(r#"exclude = ["tab\there", "\u00e9\U0001F600", 'C:\temp', "quote\"d"]"#)
+                .unwrap();
+        assert_eq!(
+            config.exclude.unwrap(),
+            ["tab\there", "é😀", "C:\\temp", "quote\"d"]
+        );
+    }
+
+    #[test]
+    fn mistakes_are_errors() {
+        let error = |text: &str| format!("{:#}", parse_config(text).unwrap_err());
+        assert_eq!(error("min_sise = 1"), "Unknown key \"min_sise\"");
+        assert_eq!(
+            error("format = \"json\"\nformat = \"dot\""),
+            "Duplicate key \"format\""
+        );
+        assert_eq!(
+            error("[scan]\nmin_size = 1"),
+            "Tables are not supported on line 1"
+        );
+        assert_eq!(
+            error("min_size = -1"),
+            "Bad value for \"min_size\": Expected a non-negative size, got -1"
+        );
+        assert_eq!(
+            error("exclude = \"*.tmp\""),
+            "Bad value for \"exclude\": Expected an array of strings, got string"
+        );
+        assert_eq!(
+            error("output = \"a\" \"b\""),
+            "Expected the end of the line on line 1"
+        );
+        assert_eq!(error("\n\noutput = \"a"), "Unterminated string on line 3");
+        assert!(error("format = \"yaml\"").starts_with("Bad value for \"format\""));
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+static EXPIRED: AtomicBool = AtomicBool::new(false);
+
+// Give the run a time budget, starting now.  Until this is called there's no limit.
+pub fn set_deadline(budget: Duration) {
+    *DEADLINE.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now().checked_add(budget);
+}
+
+// True iff the time budget has been spent, in which case the caller should stop starting new work.
+// Callers only ask when they have more to do, so once this is true the results are incomplete.
+pub fn past_deadline() -> bool {
+    if EXPIRED.load(Ordering::Relaxed) {
+        return true;
+    }
+    let expired = DEADLINE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some_and(|deadline| Instant::now() >= deadline);
+    if expired {
+        EXPIRED.store(true, Ordering::Relaxed);
+    }
+    expired
+}
+
+// True iff anything stopped early because the time budget ran out.
+pub fn was_truncated() -> bool {
+    EXPIRED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,209 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::json::{parse_json, JsonValue};
+use crate::json_report::OUTPUT_FORMAT_VERSION;
+use crate::relative::normalise_path;
+
+// A group of duplicates as saved in a JSON report, reduced to the set of its paths (including hard
+// links).  Two groups are the same iff they have the same paths.
+pub type PathGroup = BTreeSet<PathBuf>;
+
+// How the groups of duplicates differ between two reports.
+#[derive(Debug, Default)]
+pub struct ReportDiff {
+    // Groups which don't overlap with any in the old report.
+    pub new: Vec<PathGroup>,
+    // Groups which don't overlap with any in the new report, e.g. because they've been cleaned up.
+    pub resolved: Vec<PathGroup>,
+    // Groups which share some but not all of their paths with groups in the other report, as
+    // (old, new) pairs.  A group which has been split or merged appears in more than one pair.
+    pub changed: Vec<(PathGroup, PathGroup)>,
+}
+
+// Load the groups from a report written with `--format json` (with or without `--flat-json`).
+// Reports from before `format_version` was added have the same structure as version 1, but we
+// can't know what a later version means.  With `normalise`, paths are normalised lexically (see
+// `normalise_path`), so that differently-spelt paths to the same file count as the same path.
+pub fn read_report(path: &Path, normalise: bool) -> Result<Vec<PathGroup>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read report {:?}", path))?;
+    let report =
+        parse_json(&content).with_context(|| format!("Failed to parse report {:?}", path))?;
+    if let Some(version) = report.get("format_version").and_then(JsonValue::as_u64) {
+        if version > OUTPUT_FORMAT_VERSION {
+            bail!(
+                "Report {:?} is in format version {}, but this version of find_dupes only \
+                 understands up to {}",
+                path,
+                version,
+                OUTPUT_FORMAT_VERSION
+            );
+        }
+    }
+    let groups =
+        groups_from_report(&report).with_context(|| format!("Bad JSON report {:?}", path))?;
+    if !normalise {
+        return Ok(groups);
+    }
+    Ok(groups
+        .into_iter()
+        .map(|group| group.iter().map(|path| normalise_path(path)).collect())
+        .collect())
+}
+
+fn groups_from_report(report: &JsonValue) -> Option<Vec<PathGroup>> {
+    report
+        .get("groups")?
+        .as_array()?
+        .iter()
+        .map(|group| {
+            if let Some(paths) = group.get("paths") {
+                return paths.as_array()?.iter().map(JsonValue::as_path).collect();
+            }
+            let mut paths = PathGroup::new();
+            for file in group.get("files")?.as_array()? {
+                for path in file.get("paths")?.as_array()? {
+                    paths.insert(path.as_path()?);
+                }
+            }
+            Some(paths)
+        })
+        .collect()
+}
+
+// Work out which groups are new, resolved or changed between two reports.  Groups present in both
+// are left out.
+pub fn diff_reports(old: &[PathGroup], new: &[PathGroup]) -> ReportDiff {
+    let mut diff = ReportDiff::default();
+    for new_group in new {
+        if old.contains(new_group) {
+            continue;
+        }
+        let overlapping: Vec<&PathGroup> = old
+            .iter()
+            .filter(|old_group| !old_group.is_disjoint(new_group))
+            .collect();
+        if overlapping.is_empty() {
+            diff.new.push(new_group.clone());
+        }
+        for old_group in overlapping {
+            diff.changed.push((old_group.clone(), new_group.clone()));
+        }
+    }
+    for old_group in old {
+        if new.iter().all(|new_group| new_group.is_disjoint(old_group)) {
+            diff.resolved.push(old_group.clone());
+        }
+    }
+    diff
+}
+
+// Write a human-readable summary of the differences.
+pub fn write_report_diff(dest: &mut impl Write, diff: &ReportDiff) -> io::Result<()> {
+    writeln!(dest, "New groups: {}", diff.new.len())?;
+    for group in &diff.new {
+        writeln!(dest, "  + {}", format_group(group))?;
+    }
+    writeln!(dest, "Resolved groups: {}", diff.resolved.len())?;
+    for group in &diff.resolved {
+        writeln!(dest, "  - {}", format_group(group))?;
+    }
+    writeln!(dest, "Changed groups: {}", diff.changed.len())?;
+    for (old_group, new_group) in &diff.changed {
+        writeln!(dest, "  - {}", format_group(old_group))?;
+        writeln!(dest, "  + {}", format_group(new_group))?;
+    }
+    dest.flush()
+}
+
+fn format_group(group: &PathGroup) -> String {
+    group
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const OLD_REPORT: &str = r#"{"format_version": 1, "groups": [
+      {"size": 6, "files": [{"paths": ["/data/a", "/data/a-link"]}, {"paths": ["/data/b"]}]},
+      {"size": 9, "files": [{"paths": ["/data/c"]}, {"paths": ["/data/d"]}]},
+      {"size": 4, "files": [{"paths": ["/data/e"]}, {"paths": ["/data/f"]}]}
+    ]}"#;
+    // The first group is the same, but with `--flat-json` and in another order.  /data/d has been
+    // removed and another copy of /data/e made, and there's a new group.
+    const NEW_REPORT: &str = r#"{"format_version": 1, "groups": [
+      {"size": 1, "paths": ["/data/x", "/data/./y"]},
+      {"size": 4, "paths": ["/data/e", "/data/f", "/data/g"]},
+      {"size": 6, "paths": ["/data/b", "/data/a-link", "/data/a"]}
+    ]}"#;
+
+    fn group(paths: &[&str]) -> PathGroup {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn read(dir: &TempDir, name: &str, report: &str, normalise: bool) -> Result<Vec<PathGroup>> {
+        read_report(&dir.write(name, report), normalise)
+    }
+
+    #[test]
+    fn groups_are_new_resolved_or_changed() {
+        let dir = TempDir::new();
+        let old = read(&dir, "old.json", OLD_REPORT, false).unwrap();
+        let new = read(&dir, "new.json", NEW_REPORT, true).unwrap();
+        let diff = diff_reports(&old, &new);
+        assert_eq!(diff.new, [group(&["/data/x", "/data/y"])]);
+        assert_eq!(diff.resolved, [group(&["/data/c", "/data/d"])]);
+        assert_eq!(
+            diff.changed,
+            [(
+                group(&["/data/e", "/data/f"]),
+                group(&["/data/e", "/data/f", "/data/g"])
+            )]
+        );
+
+        let mut dest = Vec::new();
+        write_report_diff(&mut dest, &diff).unwrap();
+        assert_eq!(
+            String::from_utf8(dest).unwrap(),
+            "New groups: 1\n  + /data/x, /data/y\n\
+             Resolved groups: 1\n  - /data/c, /data/d\n\
+             Changed groups: 1\n  - /data/e, /data/f\n  + /data/e, /data/f, /data/g\n"
+        );
+    }
+
+    #[test]
+    fn a_split_group_pairs_with_each_part() {
+        let old = [group(&["/a", "/b", "/c", "/d"])];
+        let new = [group(&["/a", "/b"]), group(&["/c", "/d"])];
+        let diff = diff_reports(&old, &new);
+        assert!(diff.new.is_empty() && diff.resolved.is_empty());
+        assert_eq!(diff.changed.len(), 2);
+        assert!(diff_reports(&new, &new).changed.is_empty());
+    }
+
+    #[test]
+    fn reports_we_cannot_understand_are_errors() {
+        let dir = TempDir::new();
+        let later = r#"{"format_version": 99, "groups": []}"#;
+        let error = read(&dir, "later.json", later, false).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("format version 99"),
+            "{:#}",
+            error
+        );
+        assert!(read(&dir, "bad.json", r#"{"groups": [{"files": 1}]}"#, false).is_err());
+        assert!(read(&dir, "old.json", r#"{"groups": []}"#, false)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+// Finding whole directories that are copies of each other, for `--find-dir-dupes`.
+//
+// Two directories are the same if they hold entries with the same names, each file having the
+// same content as its namesake and each subdirectory being the same in turn.  Content is known
+// only for the files in groups of duplicates, so a directory holding anything else (a file with
+// no duplicate, or too small to be considered, a symlink or a special file) can't be the same as
+// another.  That's also what makes this cheap: only the directories above duplicate files are
+// looked at, and since their files have already been compared, only their listings are read.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::blake3::Hasher;
+use crate::errors::{ErrorLog, ScanError};
+use crate::progress::Phase;
+use crate::DedupFile;
+
+// A directory that's the same as another, with how much is in it.
+#[derive(Clone, Debug)]
+pub struct DirMatch {
+    pub copy: PathBuf,
+    pub original: PathBuf,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+// What we know of a directory's tree: a digest of its structure and content, and how many files
+// and bytes are in it.
+#[derive(Clone, Copy)]
+struct DirSummary {
+    digest: [u8; 32],
+    files: u64,
+    bytes: u64,
+}
+
+struct DirScan<'a> {
+    // The index of the group each path to a duplicate file is in, and the file's size.
+    groups_by_path: HashMap<&'a Path, (usize, u64)>,
+    summaries: HashMap<PathBuf, Option<DirSummary>>,
+    errors: &'a ErrorLog,
+}
+
+impl DirScan<'_> {
+    // The summary of a directory's tree, or `None` if it holds anything we don't know the
+    // content of (or can't read).
+    fn summarise(&mut self, dir: &Path) -> Option<DirSummary> {
+        if let Some(summary) = self.summaries.get(dir) {
+            return *summary;
+        }
+        let summary = self.read_summary(dir);
+        self.summaries.insert(dir.to_path_buf(), summary);
+        summary
+    }
+
+    fn read_summary(&mut self, dir: &Path) -> Option<DirSummary> {
+        let entries = fs::read_dir(dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.and_then(|e| Ok((e.file_name(), e.file_type()?))))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(|e| self.errors.record(ScanError::new(dir, Phase::Traverse, &e)))
+            .ok()?;
+        // Entries are hashed in order of name, so that the order the directory lists them in
+        // doesn't matter.
+        let entries: BTreeMap<_, _> = entries.into_iter().collect();
+        let mut hasher = Hasher::new();
+        let (mut files, mut bytes) = (0, 0);
+        for (name, file_type) in entries {
+            let path = dir.join(&name);
+            hasher.update(name.as_bytes());
+            if file_type.is_dir() {
+                let child = self.summarise(&path)?;
+                hasher.update(b"\0d");
+                hasher.update(&child.digest);
+                files += child.files;
+                bytes += child.bytes;
+            } else if file_type.is_file() {
+                let &(group, size) = self.groups_by_path.get(path.as_path())?;
+                hasher.update(b"\0f");
+                hasher.update(&(group as u64).to_le_bytes());
+                files += 1;
+                bytes += size;
+            } else {
+                return None;
+            }
+        }
+        Some(DirSummary {
+            digest: hasher.finalize(),
+            files,
+            bytes,
+        })
+    }
+}
+
+// Find the directories under `root` (or anywhere, without one) that are the same as another,
+// going by the final groups of duplicates.  Each is matched with the first of its copies in order
+// of path.  Where two directories are the same, so are their subdirectories, and only the
+// topmost are listed.  Directories with no files in them at all aren't listed.  Directories we
+// can't read are noted in `errors`.
+pub fn find_dir_dupes(
+    groups: &[Vec<DedupFile>],
+    root: Option<&Path>,
+    errors: &ErrorLog,
+) -> Vec<DirMatch> {
+    let mut scan = DirScan {
+        groups_by_path: HashMap::new(),
+        summaries: HashMap::new(),
+        errors,
+    };
+    let mut candidates: HashSet<&Path> = HashSet::new();
+    for (i, group) in groups.iter().enumerate() {
+        for df in group {
+            for path in &df.paths {
+                scan.groups_by_path.insert(path, (i, df.size));
+                let ancestors = path.ancestors().skip(1);
+                for dir in ancestors.take_while(|dir| root.is_none_or(|root| dir.starts_with(root)))
+                {
+                    if dir == Path::new("") || !candidates.insert(dir) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut by_digest: HashMap<[u8; 32], Vec<PathBuf>> = HashMap::new();
+    let mut candidates: Vec<&Path> = candidates.into_iter().collect();
+    candidates.sort();
+    for dir in candidates {
+        if let Some(summary) = scan.summarise(dir).filter(|s| s.files > 0) {
+            by_digest
+                .entry(summary.digest)
+                .or_default()
+                .push(dir.to_path_buf());
+        }
+    }
+    let matched: HashSet<PathBuf> = by_digest
+        .values()
+        .filter(|dirs| dirs.len() > 1)
+        .flatten()
+        .cloned()
+        .collect();
+
+    let mut matches = Vec::new();
+    for dirs in by_digest.into_values().filter(|dirs| dirs.len() > 1) {
+        let nested = dirs
+            .iter()
+            .all(|dir| dir.parent().is_some_and(|parent| matched.contains(parent)));
+        if nested {
+            continue;
+        }
+        let summary =
+            scan.summaries[&dirs[0]].expect("Failed to find summary of matched directory");
+        for copy in &dirs[1..] {
+            matches.push(DirMatch {
+                copy: copy.clone(),
+                original: dirs[0].clone(),
+                files: summary.files,
+                bytes: summary.bytes,
+            });
+        }
+    }
+    matches.sort_by(|m1, m2| {
+        m1.original
+            .cmp(&m2.original)
+            .then_with(|| m1.copy.cmp(&m2.copy))
+    });
+    matches
+}
+
+// Write a line for each directory that's the same as another.
+pub fn write_dir_dupes(dest: &mut impl Write, matches: &[DirMatch]) -> io::Result<()> {
+    writeln!(dest, "Copies of other directories: {}", matches.len())?;
+    for m in matches {
+        writeln!(
+            dest,
+            "  {} is the same as {} ({} files, {} bytes)",
+            m.copy.display(),
+            m.original.display(),
+            m.files,
+            m.bytes
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file_record, TempDir};
+
+    #[test]
+    fn identical_subtrees_match_but_one_differing_by_a_file_does_not() {
+        let dir = TempDir::new();
+        for tree in ["a", "b", "c"] {
+            dir.write(&format!("{}/x", tree), "same x");
+        }
+        for tree in ["a", "b"] {
+            dir.write(&format!("{}/sub/y", tree), "same y");
+        }
+        dir.write("c/sub/y", "diff y");
+        // The groups of duplicates as the content phase would find them.
+        let group = |names: &[&str]| -> Vec<DedupFile> {
+            names
+                .iter()
+                .map(|name| file_record(&dir.path().join(name)))
+                .collect()
+        };
+        let groups = [
+            group(&["a/x", "b/x", "c/x"]),
+            group(&["a/sub/y", "b/sub/y"]),
+        ];
+        let errors = ErrorLog::default();
+
+        let matches = find_dir_dupes(&groups, Some(dir.path()), &errors);
+        // Only the topmost of the matching directories are listed, not `b/sub` as well.
+        assert_eq!(matches.len(), 1, "{:?}", matches);
+        assert_eq!(matches[0].copy, dir.path().join("b"));
+        assert_eq!(matches[0].original, dir.path().join("a"));
+        assert_eq!((matches[0].files, matches[0].bytes), (2, 12));
+        assert!(errors.take().is_empty());
+
+        let mut dest = Vec::new();
+        write_dir_dupes(&mut dest, &matches).unwrap();
+        assert_eq!(
+            String::from_utf8(dest).unwrap(),
+            format!(
+                "Copies of other directories: 1\n  {} is the same as {} (2 files, 12 bytes)\n",
+                dir.path().join("b").display(),
+                dir.path().join("a").display()
+            )
+        );
+
+        // With something extra in it, `b` is no longer the same, though its `sub` still is.
+        dir.write("b/extra", "extra");
+        let matches = find_dir_dupes(&groups, Some(dir.path()), &errors);
+        assert_eq!(matches.len(), 1, "{:?}", matches);
+        assert_eq!(matches[0].copy, dir.path().join("b/sub"));
+        assert_eq!(matches[0].original, dir.path().join("a/sub"));
+    }
+}
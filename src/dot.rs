@@ -0,0 +1,193 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::keep::{select_representative, KeepPolicy};
+use crate::options::Options;
+use crate::summary::format_bytes;
+use crate::DedupFile;
+
+// Write the report as a Graphviz DOT graph, for visualising which files duplicate which.  Each
+// file on disk is a node, labelled with its name and with all its paths in the tooltip.  Each
+// group of duplicates is a cluster, in which the file we'd keep (by the `keep` policy, or the
+// first file by default) is highlighted and joined by an edge to each of the others.  Groups are
+// written as they arrive, so this works in low-memory mode too.
+pub fn write_dupes_dot<G: AsRef<[DedupFile]>>(
+    dest: &mut impl Write,
+    dupes: impl IntoIterator<Item = G>,
+    options: &Options,
+) -> io::Result<()> {
+    writeln!(dest, "graph duplicates {{")?;
+    writeln!(dest, "  node [shape=box];")?;
+    for (i, group) in dupes.into_iter().enumerate() {
+        write_cluster(dest, i, group.as_ref(), options)?;
+    }
+    writeln!(dest, "}}")
+}
+
+fn write_cluster(
+    dest: &mut impl Write,
+    index: usize,
+    group: &[DedupFile],
+    options: &Options,
+) -> io::Result<()> {
+    let kept = select_representative(group, options.keep.unwrap_or(KeepPolicy::First));
+    writeln!(dest, "  subgraph cluster_{} {{", index)?;
+    writeln!(
+        dest,
+        "    label={};",
+        dot_string(&if options.pretty_sizes {
+            format_bytes(group[0].size)
+        } else {
+            format!("{} bytes", group[0].size)
+        })
+    )?;
+    for (j, df) in group.iter().enumerate() {
+        let tooltip = df
+            .paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+        writeln!(
+            dest,
+            "    g{}_{} [label={}, tooltip={}{}];",
+            index,
+            j,
+            dot_string(&file_name(&df.paths[0])),
+            dot_string(&tooltip),
+            if j == kept { ", style=bold" } else { "" }
+        )?;
+    }
+    for j in (0..group.len()).filter(|&j| j != kept) {
+        writeln!(dest, "    g{}_{} -- g{}_{};", index, kept, index, j)?;
+    }
+    writeln!(dest, "  }}")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Quote a string for DOT, escaping as required.  Newlines become DOT's own `\n`.
+fn dot_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fake_file, options};
+    use std::collections::HashSet;
+
+    fn dot_report(groups: &[Vec<DedupFile>], args: &[&str]) -> String {
+        let mut dest = Vec::new();
+        write_dupes_dot(&mut dest, groups, &options(&[args, &["/"]].concat())).unwrap();
+        String::from_utf8(dest).unwrap()
+    }
+
+    // Check that a graph as we write it is well-formed: quoted strings are closed, braces balance
+    // (outside of strings), every statement ends properly, and every edge joins declared nodes.
+    fn assert_well_formed(dot: &str) {
+        let mut depth = 0;
+        let mut nodes = HashSet::new();
+        for line in dot.lines() {
+            let mut in_string = false;
+            let mut escaped = false;
+            for c in line.chars() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' if in_string => escaped = true,
+                    '"' => in_string = !in_string,
+                    '{' if !in_string => depth += 1,
+                    '}' if !in_string => depth -= 1,
+                    _ => {}
+                }
+                assert!(depth >= 0, "{}", dot);
+            }
+            assert!(!in_string, "unclosed string in {:?}", line);
+            let statement = line.trim();
+            assert!(
+                statement.ends_with(';') || statement.ends_with('{') || statement == "}",
+                "{:?}",
+                line
+            );
+            if let Some((node, _)) = statement.split_once(" [") {
+                assert!(nodes.insert(node.to_string()), "{:?} declared twice", node);
+            } else if let Some((from, to)) = statement.split_once(" -- ") {
+                assert!(nodes.contains(from), "{:?}", line);
+                assert!(nodes.contains(to.trim_end_matches(';')), "{:?}", line);
+            }
+        }
+        assert_eq!(depth, 0, "{}", dot);
+    }
+
+    #[test]
+    fn each_group_is_a_cluster_around_the_file_kept() {
+        let groups = vec![
+            vec![
+                fake_file(&["/d/a", "/d/link"], 10, 1),
+                fake_file(&["/d/b"], 10, 2),
+                fake_file(&["/e/c"], 10, 3),
+            ],
+            vec![
+                fake_file(&["/d/x"], 2000, 4),
+                fake_file(&["/d/yy"], 2000, 5),
+            ],
+        ];
+        let dot = dot_report(&groups, &["--keep", "longest-path"]);
+        assert_eq!(
+            dot,
+            r#"graph duplicates {
+  node [shape=box];
+  subgraph cluster_0 {
+    label="10 bytes";
+    g0_0 [label="a", tooltip="/d/a\n/d/link", style=bold];
+    g0_1 [label="b", tooltip="/d/b"];
+    g0_2 [label="c", tooltip="/e/c"];
+    g0_0 -- g0_1;
+    g0_0 -- g0_2;
+  }
+  subgraph cluster_1 {
+    label="2000 bytes";
+    g1_0 [label="x", tooltip="/d/x"];
+    g1_1 [label="yy", tooltip="/d/yy", style=bold];
+    g1_1 -- g1_0;
+  }
+}
+"#
+        );
+        assert_well_formed(&dot);
+    }
+
+    #[test]
+    fn awkward_names_are_quoted_so_the_graph_stays_well_formed() {
+        let groups = vec![
+            vec![
+                fake_file(&["/d/say \"hi\""], 10, 1),
+                fake_file(&["/d/back\\slash"], 10, 2),
+                fake_file(&["/d/{braces}; -- g0_0"], 10, 3),
+            ],
+            vec![fake_file(&["/d/x"], 2000, 4), fake_file(&["/d/y"], 2000, 5)],
+        ];
+        for args in [&[][..], &["--pretty-sizes"]] {
+            let dot = dot_report(&groups, args);
+            assert_well_formed(&dot);
+            assert!(dot.contains(r#"label="say \"hi\"""#), "{}", dot);
+            assert!(dot.contains(r#"label="back\\slash""#), "{}", dot);
+        }
+        assert_well_formed(&dot_report(&[], &[]));
+    }
+}
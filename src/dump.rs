@@ -0,0 +1,100 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::errors::keep_going_or_fail;
+use crate::json::{parse_json, write_json_path, write_json_string, JsonValue};
+use crate::DedupFile;
+
+// Write a file's vital stats as one line of JSON, for a JSON Lines dump of everything we
+// considered.  Dumps from several machines can later be merged to look for duplicates across them.
+pub fn write_file_record(dest: &mut impl Write, df: &DedupFile) -> io::Result<()> {
+    write!(dest, "{{\"paths\": [")?;
+    for (i, path) in df.paths.iter().enumerate() {
+        if i > 0 {
+            write!(dest, ", ")?;
+        }
+        write_json_path(dest, path)?;
+    }
+    write!(
+        dest,
+        "], \"size\": {}, \"alloc_size\": {}, \"device\": {}, \"inode\": {}, \"nlink\": {}, \
+         \"mtime\": {}, \"mode\": {}, \"uid\": {}, \"gid\": {}",
+        df.size, df.alloc_size, df.device, df.inode, df.nlink, df.mtime, df.mode, df.uid, df.gid
+    )?;
+    if let Some(hash) = &df.hash {
+        write!(dest, ", \"hash\": ")?;
+        write_json_string(dest, hash)?;
+    }
+    writeln!(dest, "}}")
+}
+
+// Convert one parsed record back into a file.
+pub fn file_from_record(record: &JsonValue) -> Option<DedupFile> {
+    let paths: Vec<PathBuf> = record
+        .get("paths")?
+        .as_array()?
+        .iter()
+        .map(|p| p.as_path())
+        .collect::<Option<_>>()?;
+    if paths.is_empty() {
+        return None;
+    }
+    let size = record.get("size")?.as_u64()?;
+    // Dumps from before these were recorded don't have them.
+    let record_u32 = |key| {
+        record
+            .get(key)
+            .and_then(|v| v.as_u64())
+            .and_then(|n| u32::try_from(n).ok())
+            .unwrap_or(0)
+    };
+    Some(DedupFile {
+        paths,
+        size,
+        alloc_size: record
+            .get("alloc_size")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(size),
+        device: record.get("device")?.as_u64()?,
+        inode: record.get("inode")?.as_u64()?,
+        nlink: record.get("nlink").and_then(|v| v.as_u64()).unwrap_or(1),
+        mtime: record.get("mtime").and_then(|v| v.as_i64()).unwrap_or(0),
+        mode: record_u32("mode"),
+        uid: record_u32("uid"),
+        gid: record_u32("gid"),
+        hash: record
+            .get("hash")
+            .and_then(|v| v.as_str())
+            .map(str::to_owned),
+        chunk_hashes: None,
+        protected: false,
+    })
+}
+
+// Load a JSON Lines file dump, as written by `write_file_record`.  With `keep_going`, bad records
+// are skipped with a warning.
+pub fn read_dump(path: &Path, keep_going: bool) -> Result<Vec<DedupFile>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read dump {:?}", path))?;
+    let mut files = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let df = parse_json(line)
+            .with_context(|| format!("Failed to parse line {} of dump {:?}", i + 1, path))
+            .and_then(|record| {
+                file_from_record(&record).with_context(|| {
+                    format!("Bad file record on line {} of dump {:?}", i + 1, path)
+                })
+            });
+        match df {
+            Ok(df) => files.push(df),
+            Err(e) => keep_going_or_fail(e, keep_going)?,
+        }
+    }
+    Ok(files)
+}
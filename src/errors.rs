@@ -0,0 +1,156 @@
+// Which problems stop a run, and which we can work around.
+//
+// Fatal problems mean we can't produce a trustworthy result at all, so we always stop with an
+// error.  These are bad arguments, config files or `--skip-hashes` lists, failure to create or
+// write the report (or an index, dump, diff or checkpoint), a checkpoint that can't be read or is
+// for another root, failure to spill to or read back from temporary files in low-memory mode, and
+// a `PATH`, `--relative-to` or `--protect` directory that can't be canonicalised (e.g. it doesn't
+// exist).
+//
+// Continuable problems affect only part of the input, and the rest can still be checked.  The
+// commonest is a file or directory we can't read while traversing or comparing (e.g. permission
+// denied, or it disappeared during the scan).  Any big tree has a few of those, so we always skip
+// them, noting each in an `ErrorLog`, and warn about them on stderr once the report is written (or
+// list them in the report, with `--report-errors`).  The others are things we were pointed at by
+// name, so their absence is more likely a mistake:
+//
+//   - a dump file (for `--merge-indexes`) that can't be read, or a bad record within one
+//   - an `--exclude-device` path that doesn't exist (so there's nothing on it to exclude)
+//
+// These stop the run by default.  With `--keep-going` we warn about each on stderr and carry on
+// without it.
+
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::Result;
+
+use crate::progress::Phase;
+
+// A path we couldn't read, in which phase of the scan, and why.
+#[derive(Clone, Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub phase: Phase,
+    pub message: String,
+}
+
+impl ScanError {
+    pub fn new(path: &Path, phase: Phase, err: &io::Error) -> ScanError {
+        let message = match err.kind() {
+            ErrorKind::PermissionDenied => "permission denied".to_owned(),
+            ErrorKind::NotFound => "disappeared during scan".to_owned(),
+            _ => format!("I/O error: {}", err),
+        };
+        ScanError {
+            path: path.to_path_buf(),
+            phase,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ErrorLogInner {
+    errors: Vec<ScanError>,
+    seen: HashSet<PathBuf>,
+}
+
+// Somewhere for the traversal and comparison stages to note what they couldn't read, so that we
+// can report it at the end rather than silently producing an incomplete result.  Clones share the
+// same log, and may be used from several threads at once.  Each path is only recorded once, since
+// e.g. an unreadable file will fail in the same way every time we try to compare it with another.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorLog(Arc<Mutex<ErrorLogInner>>);
+
+impl ErrorLog {
+    // A panic elsewhere while holding the lock can't leave the log inconsistent, so carry on.
+    fn lock(&self) -> MutexGuard<'_, ErrorLogInner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(&self, error: ScanError) {
+        let mut inner = self.lock();
+        if inner.seen.insert(error.path.clone()) {
+            inner.errors.push(error);
+        }
+    }
+
+    // Everything recorded so far, in the order we found it.
+    pub fn take(&self) -> Vec<ScanError> {
+        std::mem::take(&mut self.lock().errors)
+    }
+}
+
+// Deal with a continuable problem: with `keep_going`, warn and carry on, otherwise give up.
+pub fn keep_going_or_fail(err: anyhow::Error, keep_going: bool) -> Result<()> {
+    if keep_going {
+        eprintln!("Warning: {:#}", err);
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn error(path: &str) -> ScanError {
+        let err = io::Error::from(ErrorKind::PermissionDenied);
+        ScanError::new(Path::new(path), Phase::Traverse, &err)
+    }
+
+    #[test]
+    fn errors_from_many_threads_are_all_collected_once_each() {
+        let errors = ErrorLog::default();
+        thread::scope(|scope| {
+            for t in 0..8 {
+                let errors = errors.clone();
+                scope.spawn(move || {
+                    for i in 0..100 {
+                        errors.record(error(&format!("/t{}/f{}", t, i)));
+                        // Every thread also fails on the same file.
+                        errors.record(error("/shared"));
+                    }
+                });
+            }
+        });
+        let mut paths: Vec<PathBuf> = errors.take().into_iter().map(|e| e.path).collect();
+        assert_eq!(paths.len(), 801);
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 801);
+        assert!(errors.take().is_empty());
+    }
+
+    #[test]
+    fn errors_keep_their_order_and_say_what_went_wrong() {
+        let errors = ErrorLog::default();
+        errors.record(error("/b"));
+        let gone = io::Error::from(ErrorKind::NotFound);
+        errors.record(ScanError::new(Path::new("/a"), Phase::Compare, &gone));
+        errors.record(error("/b"));
+        let taken = errors.take();
+        let summary: Vec<(&Path, Phase, &str)> = taken
+            .iter()
+            .map(|e| (e.path.as_path(), e.phase, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Path::new("/b"), Phase::Traverse, "permission denied"),
+                (Path::new("/a"), Phase::Compare, "disappeared during scan"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_going_turns_failures_into_warnings() {
+        assert!(keep_going_or_fail(anyhow::anyhow!("bad dump"), true).is_ok());
+        assert!(keep_going_or_fail(anyhow::anyhow!("bad dump"), false).is_err());
+    }
+}
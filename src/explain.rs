@@ -0,0 +1,88 @@
+// A trace of how files were grouped, for `--explain`.
+//
+// Each decision is written to stderr as it's made, as a JSON object on a line of its own (like
+// `--progress-json`, so the two can share a stream), with an `event` saying what happened:
+//
+//   - `singleton`: a file was dropped because nothing else could match it.  Its `stage` is `size`
+//     if no other file had its size, or `content` if none had its content.
+//   - `bucket`: the files of one `size` (how many in `files`) are about to be compared.
+//   - `compare`: two `files` of that `size` were compared, and found `equal` or not.
+//   - `bucket_done`: comparing the files of a `size` is finished, after this many `comparisons`,
+//     leaving this many `groups` of duplicates.
+//
+// Writing the trace is no reason to stop, so errors are ignored.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::json::write_json_path;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_explain() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn explaining() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn explain_singleton(path: &Path, stage: &str) {
+    emit(|dest| {
+        write!(
+            dest,
+            "{{\"event\":\"singleton\",\"stage\":\"{}\",\"path\":",
+            stage
+        )?;
+        write_json_path(dest, path)?;
+        write!(dest, "}}")
+    });
+}
+
+pub fn explain_bucket(size: u64, files: usize) {
+    emit(|dest| {
+        write!(
+            dest,
+            "{{\"event\":\"bucket\",\"size\":{},\"files\":{}}}",
+            size, files
+        )
+    });
+}
+
+pub fn explain_comparison(size: u64, path1: &Path, path2: &Path, equal: bool) {
+    emit(|dest| {
+        write!(
+            dest,
+            "{{\"event\":\"compare\",\"size\":{},\"files\":[",
+            size
+        )?;
+        write_json_path(dest, path1)?;
+        write!(dest, ",")?;
+        write_json_path(dest, path2)?;
+        write!(dest, "],\"equal\":{}}}", equal)
+    });
+}
+
+pub fn explain_bucket_done(size: u64, comparisons: usize, groups: usize) {
+    emit(|dest| {
+        write!(
+            dest,
+            "{{\"event\":\"bucket_done\",\"size\":{},\"comparisons\":{},\"groups\":{}}}",
+            size, comparisons, groups
+        )
+    });
+}
+
+// Write one event on a line of its own, if we're explaining.  The line is put together first so
+// that it reaches stderr in one piece.
+fn emit(event: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) {
+    if !explaining() {
+        return;
+    }
+    let mut line = Vec::new();
+    if event(&mut line).is_ok() {
+        line.push(b'\n');
+        let _ = io::stderr().lock().write_all(&line);
+    }
+}
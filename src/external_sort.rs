@@ -0,0 +1,351 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::explain::explain_singleton;
+use crate::DedupFile;
+
+// How many files to hold in memory before sorting them and spilling them to disk.
+pub const DEFAULT_RUN_LEN: usize = 1_000_000;
+
+static NEXT_SPILL_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// Group files by size without holding them all in memory at once.  This is a classic external
+// merge sort: we sort fixed-size runs of files by (size, device, inode) in memory and spill each
+// run to a temporary file, then merge the runs back together.  Because the merged stream is in
+// order, hard links to the same file end up next to each other (so we can consolidate them) and
+// files of the same size end up next to each other (so we can emit them as a group).  Peak
+// memory is then roughly one run plus the largest group of same-size files.
+//
+// Note that if a file changes size in between visiting two of its hard links, the two won't be
+// adjacent and so won't be consolidated.  The content comparison will still find them to be the
+// same, so the worst case is a redundant comparison.  Files without a usable inode number (see
+// `group_by_inode`) are never consolidated.
+pub struct ExternalSizeGroups {
+    runs: Vec<RunReader>,
+    heap: BinaryHeap<Reverse<(SortKey, usize)>>,
+    heads: Vec<Option<DedupFile>>,
+    _spill_dir: Option<SpillDir>,
+}
+
+type SortKey = (u64, u64, u64);
+
+fn sort_key(f: &DedupFile) -> SortKey {
+    (f.size, f.device, f.inode)
+}
+
+impl ExternalSizeGroups {
+    // Pop the smallest file off the merged runs, refilling from the run it came from.
+    fn pop_file(&mut self) -> io::Result<Option<DedupFile>> {
+        let Some(Reverse((_, run))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let f = self.heads[run].take();
+        self.advance(run)?;
+        Ok(f)
+    }
+
+    // Read the next file from a run onto the heap.
+    fn advance(&mut self, run: usize) -> io::Result<()> {
+        if let Some(f) = self.runs[run].next_file()? {
+            self.heap.push(Reverse((sort_key(&f), run)));
+            self.heads[run] = Some(f);
+        }
+        Ok(())
+    }
+
+    // Peek at the sort key of the next file in the merged runs.
+    fn peek_key(&self) -> Option<SortKey> {
+        self.heap.peek().map(|Reverse((key, _))| *key)
+    }
+
+    // Pop the next file, having consolidated any hard links to it.
+    fn pop_consolidated(&mut self) -> io::Result<Option<DedupFile>> {
+        let Some(mut f) = self.pop_file()? else {
+            return Ok(None);
+        };
+        while f.inode != 0 && self.peek_key() == Some(sort_key(&f)) {
+            if let Some(link) = self.pop_file()? {
+                f.paths.extend(link.paths);
+                f.nlink = link.nlink;
+                f.alloc_size = link.alloc_size;
+                f.mtime = link.mtime;
+                f.mode = link.mode;
+                f.uid = link.uid;
+                f.gid = link.gid;
+            }
+        }
+        Ok(Some(f))
+    }
+
+    fn next_group(&mut self) -> io::Result<Option<Vec<DedupFile>>> {
+        while let Some(f) = self.pop_consolidated()? {
+            let size = f.size;
+            let mut group = vec![f];
+            while self.peek_key().map(|(s, _, _)| s) == Some(size) {
+                if let Some(g) = self.pop_consolidated()? {
+                    group.push(g);
+                }
+            }
+            if group.len() > 1 {
+                return Ok(Some(group));
+            }
+            explain_singleton(&group[0].paths[0], "size");
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for ExternalSizeGroups {
+    type Item = io::Result<Vec<DedupFile>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_group().transpose()
+    }
+}
+
+// A temporary directory holding spilled runs, removed when we're done with it.  Each is numbered,
+// so that more than one sort can spill at a time.
+struct SpillDir {
+    path: PathBuf,
+    count: usize,
+}
+
+impl SpillDir {
+    fn new() -> io::Result<SpillDir> {
+        let path = env::temp_dir().join(format!(
+            "find_dupes-{}-{}",
+            process::id(),
+            NEXT_SPILL_DIR.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir(&path)?;
+        Ok(SpillDir { path, count: 0 })
+    }
+
+    // Sort a run and write it to a new file in the spill directory.
+    fn spill(&mut self, run: &mut Vec<DedupFile>) -> io::Result<PathBuf> {
+        run.sort_unstable_by_key(sort_key);
+        let run_path = self.path.join(format!("run-{}", self.count));
+        self.count += 1;
+        let mut dest = BufWriter::new(File::create(&run_path)?);
+        for f in run.drain(..) {
+            write_file(&mut dest, &f)?;
+        }
+        dest.flush()?;
+        Ok(run_path)
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// A sorted run of files, either still in memory or spilled to disk.
+enum RunReader {
+    Memory(std::vec::IntoIter<DedupFile>),
+    Disk(BufReader<File>),
+}
+
+impl RunReader {
+    fn next_file(&mut self) -> io::Result<Option<DedupFile>> {
+        match self {
+            RunReader::Memory(iter) => Ok(iter.next()),
+            RunReader::Disk(src) => read_file(src),
+        }
+    }
+}
+
+// Serialise a file's vital stats as a fixed-size header followed by its length-prefixed paths.
+// Traversal never knows a file's hash, so we don't bother storing it.
+fn write_file(dest: &mut impl Write, f: &DedupFile) -> io::Result<()> {
+    for n in [
+        f.size,
+        f.alloc_size,
+        f.device,
+        f.inode,
+        f.nlink,
+        f.paths.len() as u64,
+    ] {
+        dest.write_all(&n.to_le_bytes())?;
+    }
+    dest.write_all(&f.mtime.to_le_bytes())?;
+    for n in [f.mode, f.uid, f.gid] {
+        dest.write_all(&n.to_le_bytes())?;
+    }
+    for path in &f.paths {
+        let bytes = path.as_os_str().as_bytes();
+        dest.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        dest.write_all(bytes)?;
+    }
+    Ok(())
+}
+
+fn read_u64(src: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    src.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_u32(src: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    src.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+// Deserialise a file written by `write_file`, or `None` at the end of the run.
+fn read_file(src: &mut impl Read) -> io::Result<Option<DedupFile>> {
+    let size = match read_u64(src) {
+        Ok(n) => n,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let alloc_size = read_u64(src)?;
+    let device = read_u64(src)?;
+    let inode = read_u64(src)?;
+    let nlink = read_u64(src)?;
+    let path_count = read_u64(src)?;
+    let mtime = read_u64(src)? as i64;
+    let mode = read_u32(src)?;
+    let uid = read_u32(src)?;
+    let gid = read_u32(src)?;
+    let mut paths = Vec::new();
+    for _ in 0..path_count {
+        let mut bytes = vec![0; read_u64(src)? as usize];
+        src.read_exact(&mut bytes)?;
+        paths.push(PathBuf::from(OsStr::from_bytes(&bytes)));
+    }
+    Ok(Some(DedupFile {
+        paths,
+        size,
+        alloc_size,
+        device,
+        inode,
+        nlink,
+        mtime,
+        mode,
+        uid,
+        gid,
+        hash: None,
+        chunk_hashes: None,
+        protected: false,
+    }))
+}
+
+// Sort files into groups by size, spilling to disk every `run_len` files.  If there are fewer
+// files than that, nothing touches the disk.
+pub fn group_by_size_external(
+    files: impl Iterator<Item = DedupFile>,
+    run_len: usize,
+) -> io::Result<ExternalSizeGroups> {
+    let mut spill_dir: Option<SpillDir> = None;
+    let mut run_paths: Vec<PathBuf> = Vec::new();
+    let mut run: Vec<DedupFile> = Vec::new();
+
+    for f in files {
+        run.push(f);
+        if run.len() >= run_len {
+            let dir = match &mut spill_dir {
+                Some(dir) => dir,
+                None => spill_dir.insert(SpillDir::new()?),
+            };
+            run_paths.push(dir.spill(&mut run)?);
+        }
+    }
+
+    let mut runs: Vec<RunReader> = Vec::new();
+    for run_path in &run_paths {
+        runs.push(RunReader::Disk(BufReader::new(File::open(run_path)?)));
+    }
+    if !run.is_empty() {
+        run.sort_unstable_by_key(sort_key);
+        runs.push(RunReader::Memory(run.into_iter()));
+    }
+
+    let mut groups = ExternalSizeGroups {
+        heads: (0..runs.len()).map(|_| None).collect(),
+        runs,
+        heap: BinaryHeap::new(),
+        _spill_dir: spill_dir,
+    };
+    for i in 0..groups.runs.len() {
+        groups.advance(i)?;
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_file;
+
+    // A group's size and the inode number and paths of each of its files, in order.
+    type Group = (u64, Vec<(u64, Vec<PathBuf>)>);
+
+    fn grouped(files: &[DedupFile], run_len: usize) -> Vec<Group> {
+        group_by_size_external(files.iter().cloned(), run_len)
+            .unwrap()
+            .map(|group| {
+                let group = group.unwrap();
+                let files = group.iter().map(|f| (f.inode, f.paths.clone())).collect();
+                (group[0].size, files)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spilled_runs_merge_into_the_same_groups_as_one_in_memory() {
+        // Out of order by size, with two links to inode 5 seen far apart, as a traversal might
+        // see them, and a file of a size no other has.
+        let files = [
+            fake_file(&["/s30-6"], 30, 6),
+            fake_file(&["/s20-5a"], 20, 5),
+            fake_file(&["/s10-2"], 10, 2),
+            fake_file(&["/s40"], 40, 9),
+            fake_file(&["/s30-8"], 30, 8),
+            fake_file(&["/s20-3"], 20, 3),
+            fake_file(&["/s10-1"], 10, 1),
+            fake_file(&["/s30-7"], 30, 7),
+            fake_file(&["/s20-5b"], 20, 5),
+        ];
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let expected = vec![
+            (10, vec![(1, paths(&["/s10-1"])), (2, paths(&["/s10-2"]))]),
+            (
+                20,
+                vec![(3, paths(&["/s20-3"])), (5, paths(&["/s20-5a", "/s20-5b"]))],
+            ),
+            (
+                30,
+                vec![
+                    (6, paths(&["/s30-6"])),
+                    (7, paths(&["/s30-7"])),
+                    (8, paths(&["/s30-8"])),
+                ],
+            ),
+        ];
+        // Runs of two spill four times, leaving one file in memory.
+        assert_eq!(grouped(&files, 2), expected);
+        assert_eq!(grouped(&files, 1), expected);
+        assert_eq!(grouped(&files, DEFAULT_RUN_LEN), expected);
+    }
+
+    #[test]
+    fn the_spilled_runs_are_removed_once_the_groups_are_done_with() {
+        let files: Vec<DedupFile> = (1..=4).map(|inode| fake_file(&["/f"], 10, inode)).collect();
+        let groups = group_by_size_external(files.into_iter(), 1).unwrap();
+        let spill_dir = groups._spill_dir.as_ref().map(|dir| dir.path.clone());
+        let spill_dir = spill_dir.expect("Nothing was spilled");
+        assert_eq!(fs::read_dir(&spill_dir).unwrap().count(), 4);
+        drop(groups);
+        assert!(!spill_dir.exists());
+    }
+}
@@ -1,11 +1,264 @@
-use std::fs::File;
-use std::io::{self, Read};
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
 use std::path::Path;
 
+use crate::deadline::past_deadline;
+use crate::errors::{ErrorLog, ScanError};
+use crate::explain::{
+    explain_bucket, explain_bucket_done, explain_comparison, explain_singleton, explaining,
+};
+use crate::hash::hash_content_twice;
+use crate::open_files::{open_limited, LimitedFile};
+use crate::options::{GroupKey, Options};
+use crate::progress::Phase;
+use crate::sample::splitmix64;
 use crate::DedupFile;
 
 const BUFFER_LEN: usize = 1024 * 1024; // 1 MiB
 
+// Tunables for how file content is compared.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompareOptions {
+    // Treat ASCII letters as equal regardless of case.  Only sensible for text files.
+    pub ignore_case: bool,
+    // If either of a pair of files isn't available locally (e.g. loaded from another machine's
+    // file dump), compare their recorded hashes instead.
+    pub hash_fallback: bool,
+    // Bytes at the start and end of each file to leave out of the comparison, e.g. a fixed-size
+    // metadata block added by a backup tool.
+    pub ignore_header: u64,
+    pub ignore_trailer: u64,
+    // Consider files the same once this many bytes (after any header) match.
+    pub limit: Option<u64>,
+    // Compare only this many blocks of each file, at the same pseudo-random offsets in each.
+    pub sample_blocks: Option<u64>,
+}
+
+impl CompareOptions {
+    pub fn new(options: &Options) -> CompareOptions {
+        CompareOptions {
+            ignore_case: options.ignore_case_content,
+            hash_fallback: options.merging_dumps(),
+            ignore_header: options.ignore_header,
+            ignore_trailer: options.ignore_trailer,
+            limit: options.compare_limit,
+            sample_blocks: options.sample_blocks,
+        }
+    }
+}
+
+// A way of sorting a group of candidates of the same size into groups with the same content.  The
+// groups returned include those of one file; files that can't be read are noted as errors and
+// left out or kept apart.  Which comparator is used depends on the options (see
+// `select_comparator`), and another strategy belongs here as another implementation.
+pub trait ContentComparator: fmt::Debug {
+    fn group(&self, candidates: Vec<DedupFile>) -> Vec<Vec<DedupFile>>;
+
+    // How many pairs of files this has compared so far, for `--explain`.
+    fn comparisons(&self) -> usize {
+        0
+    }
+}
+
+// Where comparators read the content of files from: the filesystem, unless a test has content of
+// its own to offer.
+pub trait Contents: fmt::Debug {
+    type Source: Read + Seek;
+
+    fn open(&self, path: &Path) -> io::Result<Self::Source>;
+}
+
+// The files themselves, opened subject to the limit on open files (and any `--io-limit`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Disk;
+
+impl Contents for Disk {
+    type Source = LimitedFile;
+
+    fn open(&self, path: &Path) -> io::Result<LimitedFile> {
+        open_limited(path)
+    }
+}
+
+// The comparator the options call for: none with `--assume-unchanged`, hashes with
+// `--double-hash`, and otherwise the files themselves (perhaps only as far as `--compare-limit`,
+// or in the blocks sampled by `--sample-blocks`).
+pub fn select_comparator(options: &Options, errors: &ErrorLog) -> Box<dyn ContentComparator> {
+    let compare = CompareOptions::new(options);
+    if options.assume_unchanged {
+        Box::new(SizeOnlyComparator)
+    } else if options.double_hash {
+        Box::new(DoubleHashComparator::new(Disk, compare, errors))
+    } else {
+        Box::new(ExactComparator::new(Disk, compare, errors))
+    }
+}
+
+// Compare files byte by byte, or by their recorded digests if they're from file dumps and not
+// available here to read.
+#[derive(Debug)]
+pub struct ExactComparator<C: Contents> {
+    contents: C,
+    options: CompareOptions,
+    errors: ErrorLog,
+    comparisons: Cell<usize>,
+}
+
+impl<C: Contents> ExactComparator<C> {
+    pub fn new(contents: C, options: CompareOptions, errors: &ErrorLog) -> ExactComparator<C> {
+        ExactComparator {
+            contents,
+            options,
+            errors: errors.clone(),
+            comparisons: Cell::new(0),
+        }
+    }
+
+    // True iff two files have the same content, by whatever means are available to us.  If we
+    // can't read one of them, we note it and treat them as different.
+    fn files_match(&self, df1: &DedupFile, df2: &DedupFile) -> bool {
+        self.comparisons.set(self.comparisons.get() + 1);
+        if self.options.hash_fallback && !(df1.is_local() && df2.is_local()) {
+            return match (&df1.hash, &df2.hash) {
+                (Some(hash1), Some(hash2)) => hash1 == hash2,
+                _ => false,
+            };
+        }
+        let (path1, path2) = (&df1.paths[0], &df2.paths[0]);
+        match compare_bytes(&self.contents, path1, path2, df1.size, self.options) {
+            Ok(same) => same,
+            Err(e) => {
+                self.errors.record(e);
+                false
+            }
+        }
+    }
+}
+
+impl<C: Contents> ContentComparator for ExactComparator<C> {
+    // The algorithm here works like this: Consider a stack of coloured dinner plates.  To group
+    // them by colour:
+    //
+    //   1. If the stack is empty, then finish.
+    //   2. Pick up a plate from the stack.
+    //   3. If there is a group of plates that is the same colour as this plate, add this plate to
+    //      that group then go back to step 1.
+    //   4. Place the plate as a new group to the right of the existing groups.
+    //   5. Go back to step 1.
+    //
+    fn group(&self, candidates: Vec<DedupFile>) -> Vec<Vec<DedupFile>> {
+        let mut groups: Vec<Vec<DedupFile>> = Vec::new();
+        let size = candidates.first().map_or(0, |df| df.size);
+
+        'candidate: for candidate in candidates {
+            for group in &mut groups {
+                let same = self.files_match(&candidate, &group[0]);
+                explain_comparison(size, &candidate.paths[0], &group[0].paths[0], same);
+                if same {
+                    group.push(candidate);
+                    continue 'candidate;
+                }
+            }
+            groups.push(vec![candidate]);
+        }
+        groups
+    }
+
+    fn comparisons(&self) -> usize {
+        self.comparisons.get()
+    }
+}
+
+// With `--double-hash`, each file is read once to compute both its BLAKE3 digest and its FNV-1a
+// hash, and files are grouped by the pair, without comparing them byte-by-byte.  In a group of n
+// files that's n reads rather than up to n - 1 for each distinct content among them.
+#[derive(Debug)]
+pub struct DoubleHashComparator<C: Contents> {
+    contents: C,
+    options: CompareOptions,
+    errors: ErrorLog,
+}
+
+impl<C: Contents> DoubleHashComparator<C> {
+    pub fn new(contents: C, options: CompareOptions, errors: &ErrorLog) -> DoubleHashComparator<C> {
+        DoubleHashComparator {
+            contents,
+            options,
+            errors: errors.clone(),
+        }
+    }
+}
+
+impl<C: Contents> ContentComparator for DoubleHashComparator<C> {
+    fn group(&self, candidates: Vec<DedupFile>) -> Vec<Vec<DedupFile>> {
+        group_by_digests(candidates, &self.errors, |df| {
+            let path = &df.paths[0];
+            open_region(&self.contents, path, self.options).and_then(|file| {
+                hash_content_twice(
+                    &mut file.take(compared_len(df.size, self.options)),
+                    self.options.ignore_case,
+                )
+                .map_err(|e| ScanError::new(path, Phase::Compare, &e))
+            })
+        })
+    }
+}
+
+// With `--assume-unchanged`, files aren't read at all: the same size will do.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeOnlyComparator;
+
+impl ContentComparator for SizeOnlyComparator {
+    fn group(&self, candidates: Vec<DedupFile>) -> Vec<Vec<DedupFile>> {
+        vec![candidates]
+    }
+}
+
+// How sure we are that the files in a group of duplicates really have the same content, going by
+// how they were matched: byte-by-byte all the way through (perhaps ignoring case, whitespace or a
+// header and trailer, if asked), by digests of their content (with `--double-hash`, or from file
+// dumps for files that aren't here), only as far as `--compare-limit`, or only in the blocks
+// sampled with `--sample-blocks`.  With `--assume-unchanged` they weren't compared at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confidence {
+    Bytes,
+    Hashes,
+    Prefix,
+    Sampled,
+    Unconfirmed,
+}
+
+impl Confidence {
+    pub fn of(group: &[DedupFile], options: &Options) -> Confidence {
+        if options.assume_unchanged {
+            Confidence::Unconfirmed
+        } else if options.is_partial_match(group[0].size) {
+            Confidence::Prefix
+        } else if options.is_sampled(group[0].size) {
+            Confidence::Sampled
+        } else if options.double_hash
+            || (options.merging_dumps() && !group.iter().all(|df| df.is_local()))
+        {
+            Confidence::Hashes
+        } else {
+            Confidence::Bytes
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Confidence::Bytes => "byte-confirmed",
+            Confidence::Hashes => "hash-confirmed",
+            Confidence::Prefix => "prefix-confirmed",
+            Confidence::Sampled => "sampled",
+            Confidence::Unconfirmed => "unconfirmed",
+        }
+    }
+}
+
 // Group a list of files by their content.  We assume that the candidates have already been
 // shortlisted, i.e. there are no duplicates (by inode) and all are the same size.
 //
@@ -20,8 +273,9 @@ const BUFFER_LEN: usize = 1024 * 1024; // 1 MiB
 //
 #[derive(Debug)]
 pub struct GroupByContentIter {
-    input_queue: Vec<Vec<DedupFile>>,
-    output_queue: Vec<Vec<DedupFile>>,
+    input_queue: VecDeque<Vec<DedupFile>>,
+    output_queue: VecDeque<Vec<DedupFile>>,
+    comparator: Box<dyn ContentComparator>,
 }
 
 impl Iterator for GroupByContentIter {
@@ -29,14 +283,20 @@ impl Iterator for GroupByContentIter {
 
     fn next(&mut self) -> Option<Self::Item> {
         while !(self.input_queue.is_empty() && self.output_queue.is_empty()) {
-            while let Some(output_group) = self.output_queue.pop() {
+            while let Some(output_group) = self.output_queue.pop_front() {
                 if !output_group.is_empty() {
                     return Some(output_group);
                 }
             }
 
-            if let Some(input_group) = self.input_queue.pop() {
-                self.output_queue.append(&mut regroup(input_group));
+            // Groups already found are worth reporting, but we start on no more once we've run out
+            // of time.
+            if past_deadline() {
+                return None;
+            }
+            if let Some(input_group) = self.input_queue.pop_front() {
+                self.output_queue
+                    .extend(regroup(input_group, self.comparator.as_ref()));
             }
         }
 
@@ -44,59 +304,872 @@ impl Iterator for GroupByContentIter {
     }
 }
 
-fn regroup(mut candidates: Vec<DedupFile>) -> Vec<Vec<DedupFile>> {
-    // The algorithm here works like this: Consider a stack of coloured dinner plates.  To group
-    // them by colour:
-    //
-    //   1. If the stack is empty, then finish.
-    //   2. Pick up a plate from the stack.
-    //   3. If there is a group of plates that is the same colour as this plate, add this plate to
-    //      that group then go back to step 1.
-    //   4. Place the plate as a new group to the right of the existing groups.
-    //   5. Go back to step 1.
-    //
-    let mut groups: Vec<Vec<DedupFile>> = Vec::new();
+// Sort a group of candidates of the same size into groups of files with the same content, with
+// whichever comparator the options call for, and drop any file that's left on its own.
+fn regroup(candidates: Vec<DedupFile>, comparator: &dyn ContentComparator) -> Vec<Vec<DedupFile>> {
+    let size = candidates.first().map_or(0, |df| df.size);
+    explain_bucket(size, candidates.len());
+    let comparisons = comparator.comparisons();
+    let mut groups = comparator.group(candidates);
+    explain_unmatched(&groups);
+    groups.retain(|g| g.len() > 1);
+    explain_bucket_done(size, comparator.comparisons() - comparisons, groups.len());
+    groups
+}
 
-    'candidate: while let Some(candidate) = candidates.pop() {
-        for group in &mut groups {
-            if let Ok(true) = compare_file_bytes(&candidate.paths[0], &group[0].paths[0]) {
-                group.push(candidate);
-                continue 'candidate;
-            }
+// With `--explain`, note the files that turned out to have no duplicates.
+fn explain_unmatched(groups: &[Vec<DedupFile>]) {
+    if explaining() {
+        for group in groups.iter().filter(|g| g.len() == 1) {
+            explain_singleton(&group[0].paths[0], "content");
+        }
+    }
+}
+
+// Group files by what `digests` makes of each, which must all be the same for files to be put
+// together.  Files it fails for are noted in `errors` and left out.
+fn group_by_digests<D: Ord>(
+    candidates: Vec<DedupFile>,
+    errors: &ErrorLog,
+    digests: impl Fn(&DedupFile) -> Result<D, ScanError>,
+) -> Vec<Vec<DedupFile>> {
+    let mut groups: BTreeMap<D, Vec<DedupFile>> = BTreeMap::new();
+    for df in candidates {
+        match digests(&df) {
+            Ok(digests) => groups.entry(digests).or_default().push(df),
+            Err(e) => errors.record(e),
         }
-        groups.push(vec![candidate]);
     }
+    groups.into_values().collect()
+}
 
-    groups.retain(|g| g.len() > 1);
-    groups
+// Compare the content of two files of the given size, or just the region between any header and
+// trailer we've been asked to ignore, or just the start of that up to the limit if there is one.
+// With `--sample-blocks`, a region bigger than the blocks to sample is only compared in those.
+pub fn compare_file_bytes(
+    path1: &Path,
+    path2: &Path,
+    size: u64,
+    options: CompareOptions,
+) -> Result<bool, ScanError> {
+    compare_bytes(&Disk, path1, path2, size, options)
+}
+
+fn compare_bytes<C: Contents>(
+    contents: &C,
+    path1: &Path,
+    path2: &Path,
+    size: u64,
+    options: CompareOptions,
+) -> Result<bool, ScanError> {
+    let len = compared_len(size, options);
+    let mut file1 = open_region(contents, path1, options)?;
+    let mut file2 = open_region(contents, path2, options)?;
+    compare_regions(&mut file1, &mut file2, len, options).map_err(|e| match e {
+        ReadFailure::First(e) => ScanError::new(path1, Phase::Compare, &e),
+        ReadFailure::Second(e) => ScanError::new(path2, Phase::Compare, &e),
+    })
+}
+
+// Compare `len` bytes of two sources each already past any header, or just the blocks sampled
+// from them with `--sample-blocks`.
+fn compare_regions(
+    src1: &mut (impl Read + Seek),
+    src2: &mut (impl Read + Seek),
+    len: u64,
+    options: CompareOptions,
+) -> Result<bool, ReadFailure> {
+    let Some(offsets) = options
+        .sample_blocks
+        .and_then(|blocks| sample_offsets(len, blocks))
+    else {
+        return compare_content(src1, len, src2, len, options.ignore_case);
+    };
+    for offset in offsets {
+        let start = SeekFrom::Start(options.ignore_header + offset);
+        src1.seek(start).map_err(ReadFailure::First)?;
+        src2.seek(start).map_err(ReadFailure::Second)?;
+        let same = compare_content(
+            src1,
+            SAMPLE_BLOCK_LEN,
+            src2,
+            SAMPLE_BLOCK_LEN,
+            options.ignore_case,
+        )?;
+        if !same {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Where to sample `blocks` blocks in a region of `len` bytes, for `--sample-blocks`, or `None` if
+// the region isn't bigger than that, in which case it's compared in full.  The region is divided
+// into as many equal stretches as there are blocks, with a block at a pseudo-random place in each,
+// so the samples are spread through the file.  The places depend only on the length, so the files
+// being compared are sampled at the same offsets, and the same files at the same offsets every
+// time.
+fn sample_offsets(len: u64, blocks: u64) -> Option<Vec<u64>> {
+    if len <= blocks.saturating_mul(SAMPLE_BLOCK_LEN) {
+        return None;
+    }
+    let stretch = len / blocks;
+    Some(
+        (0..blocks)
+            .map(|i| {
+                i * stretch + splitmix64(len ^ splitmix64(i)) % (stretch - SAMPLE_BLOCK_LEN + 1)
+            })
+            .collect(),
+    )
+}
+
+// Where two files first differ, for `--diff-offsets`: `None` if they're the same all the way
+// through, or the offset of the first byte that differs.  If one is the start of the other, that's
+// the length of the shorter.  As in a scan, any header and trailer we've been asked to ignore are
+// skipped (but offsets are still from the start of the file) and case is ignored if asked, but
+// there's no `--compare-limit`: the point is to find the difference wherever it is.
+pub fn first_file_difference(
+    path1: &Path,
+    path2: &Path,
+    options: CompareOptions,
+) -> Result<Option<u64>, ScanError> {
+    let open = |path: &Path| -> Result<_, ScanError> {
+        let size = fs::metadata(path)
+            .map_err(|e| ScanError::new(path, Phase::Compare, &e))?
+            .len();
+        let file = open_region(&Disk, path, options)?;
+        let len = size.saturating_sub(options.ignore_header + options.ignore_trailer);
+        Ok((file, len))
+    };
+    let (mut file1, len1) = open(path1)?;
+    let (mut file2, len2) = open(path2)?;
+    let offset = first_difference(&mut file1, len1, &mut file2, len2, options.ignore_case)
+        .map_err(|e| match e {
+            ReadFailure::First(e) => ScanError::new(path1, Phase::Compare, &e),
+            ReadFailure::Second(e) => ScanError::new(path2, Phase::Compare, &e),
+        })?;
+    Ok(offset.map(|offset| offset + options.ignore_header))
+}
+
+// Where two sources first differ, reading `len1` and `len2` bytes from them respectively (or up
+// to the end, if that comes first): `None` if they're the same, or the offset of the first byte
+// that differs, which is the length of the shorter if one is the start of the other.
+pub fn first_difference(
+    src1: &mut impl Read,
+    len1: u64,
+    src2: &mut impl Read,
+    len2: u64,
+    ignore_case: bool,
+) -> Result<Option<u64>, ReadFailure> {
+    let mut src1 = src1.take(len1);
+    let mut src2 = src2.take(len2);
+    let mut buf1 = vec![0; BUFFER_LEN];
+    let mut buf2 = vec![0; BUFFER_LEN];
+    let mut offset = 0;
+
+    loop {
+        let read_count1 = read_full(&mut src1, &mut buf1).map_err(ReadFailure::First)?;
+        let read_count2 = read_full(&mut src2, &mut buf2).map_err(ReadFailure::Second)?;
+
+        if ignore_case {
+            buf1[..read_count1].make_ascii_lowercase();
+            buf2[..read_count2].make_ascii_lowercase();
+        }
+
+        let common = read_count1.min(read_count2);
+        if let Some(i) = (0..common).find(|&i| buf1[i] != buf2[i]) {
+            return Ok(Some(offset + i as u64));
+        }
+        if read_count1 != read_count2 {
+            return Ok(Some(offset + common as u64));
+        }
+        if read_count1 < BUFFER_LEN {
+            return Ok(None);
+        }
+        offset += read_count1 as u64;
+    }
 }
 
-// Compare the content of two files.
-fn compare_file_bytes(path1: &Path, path2: &Path) -> io::Result<bool> {
-    let mut file1 = File::open(path1)?;
-    let mut file2 = File::open(path2)?;
-    let mut buf1 = [0; BUFFER_LEN];
-    let mut buf2 = [0; BUFFER_LEN];
+// The size of each block compared with `--sample-blocks`.
+pub const SAMPLE_BLOCK_LEN: u64 = 64 * 1024; // 64 KiB
+
+// How much of a file of the given size is compared.
+pub fn compared_len(size: u64, options: CompareOptions) -> u64 {
+    let len = size.saturating_sub(options.ignore_header + options.ignore_trailer);
+    options.limit.map_or(len, |limit| len.min(limit))
+}
+
+// Open a file ready to read the region to be compared, i.e. past any header.
+fn open_region<C: Contents>(
+    contents: &C,
+    path: &Path,
+    options: CompareOptions,
+) -> Result<C::Source, ScanError> {
+    let mut file = contents
+        .open(path)
+        .map_err(|e| ScanError::new(path, Phase::Compare, &e))?;
+    if options.ignore_header > 0 {
+        file.seek(SeekFrom::Start(options.ignore_header))
+            .map_err(|e| ScanError::new(path, Phase::Compare, &e))?;
+    }
+    Ok(file)
+}
+
+// Which of two sources we failed to read from.
+#[derive(Debug)]
+pub enum ReadFailure {
+    First(io::Error),
+    Second(io::Error),
+}
+
+// True iff two sources have the same content, reading `len1` and `len2` bytes from them
+// respectively (or up to the end, if that comes first).  Sources of different lengths are
+// different without reading anything.  This is the heart of the comparison, whatever the sources
+// are: files, or anything else we can read.
+pub fn compare_content(
+    src1: &mut impl Read,
+    len1: u64,
+    src2: &mut impl Read,
+    len2: u64,
+    ignore_case: bool,
+) -> Result<bool, ReadFailure> {
+    if len1 != len2 {
+        return Ok(false);
+    }
+    let mut src1 = src1.take(len1);
+    let mut src2 = src2.take(len2);
+    let mut buf1 = vec![0; BUFFER_LEN];
+    let mut buf2 = vec![0; BUFFER_LEN];
 
     loop {
-        let read_count1 = file1.read(&mut buf1)?;
-        let read_count2 = file2.read(&mut buf2)?;
+        let read_count1 = read_full(&mut src1, &mut buf1).map_err(ReadFailure::First)?;
+        let read_count2 = read_full(&mut src2, &mut buf2).map_err(ReadFailure::Second)?;
 
-        if read_count1 != read_count2 || buf1 != buf2 {
+        if ignore_case {
+            buf1[..read_count1].make_ascii_lowercase();
+            buf2[..read_count2].make_ascii_lowercase();
+        }
+
+        if buf1[..read_count1] != buf2[..read_count2] {
             return Ok(false);
         }
 
-        if read_count1 != BUFFER_LEN {
-            break;
+        if read_count1 < BUFFER_LEN {
+            return Ok(true);
         }
     }
+}
 
-    Ok(true)
+// Fill as much of a buffer as we can, stopping early only at the end of the source.  A single
+// `read` may return less than it could (e.g. from a pipe), which mustn't look like a difference.
+fn read_full(src: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match src.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read_count) => filled += read_count,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
 }
 
-pub fn group_by_content(groups_by_size: Vec<Vec<DedupFile>>) -> GroupByContentIter {
+// Group each of a list of groups of candidates by content, taking them in turn, so that (like the
+// candidates in each) they're compared in the order given.
+pub fn group_by_content(
+    groups_by_size: Vec<Vec<DedupFile>>,
+    options: &Options,
+    errors: &ErrorLog,
+) -> GroupByContentIter {
     GroupByContentIter {
-        input_queue: groups_by_size,
-        output_queue: Vec::new(),
+        input_queue: groups_by_size.into(),
+        output_queue: VecDeque::new(),
+        comparator: select_comparator(options, errors),
+    }
+}
+
+// With `--compare-metadata`, or a `--group-key` other than `content`, the same content isn't
+// enough: split a group of duplicates so that the files in each also have the same modification
+// time, permissions and owner, and/or whatever the key asks for.  Names and extensions are those
+// of the first path to each file.  Any file left without a match is dropped.  Otherwise, the
+// group is returned as it is.
+pub fn split_by_key(group: Vec<DedupFile>, options: &Options) -> Vec<Vec<DedupFile>> {
+    if !options.compare_metadata && options.group_key == GroupKey::Content {
+        return vec![group];
+    }
+    let same_key = |df1: &DedupFile, df2: &DedupFile| {
+        (!options.compare_metadata || same_metadata(df1, df2))
+            && match options.group_key {
+                GroupKey::Content => true,
+                GroupKey::ContentName => df1.paths[0].file_name() == df2.paths[0].file_name(),
+                GroupKey::ContentExt => lowercase_extension(df1) == lowercase_extension(df2),
+                GroupKey::ContentMtime => df1.mtime == df2.mtime,
+            }
+    };
+    let mut splits: Vec<Vec<DedupFile>> = Vec::new();
+    for df in group {
+        match splits.iter_mut().find(|split| same_key(&split[0], &df)) {
+            Some(split) => split.push(df),
+            None => splits.push(vec![df]),
+        }
+    }
+    splits.retain(|split| split.len() > 1);
+    splits
+}
+
+fn lowercase_extension(df: &DedupFile) -> Option<String> {
+    df.paths[0]
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+}
+
+fn same_metadata(df1: &DedupFile, df2: &DedupFile) -> bool {
+    df1.mtime == df2.mtime && df1.mode == df2.mode && df1.uid == df2.uid && df1.gid == df2.gid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fake_file, file_record, options, TempDir};
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    // A source that gives out no more than a few bytes at a time, as a pipe might.
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(7);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    // A source that can't be read at all.
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    // Files kept in memory for comparators to read, by path.
+    #[derive(Debug, Default)]
+    struct InMemory(BTreeMap<PathBuf, Vec<u8>>);
+
+    impl Contents for InMemory {
+        type Source = Cursor<Vec<u8>>;
+
+        fn open(&self, path: &Path) -> io::Result<Cursor<Vec<u8>>> {
+            let content = self.0.get(path).ok_or(ErrorKind::NotFound)?;
+            Ok(Cursor::new(content.clone()))
+        }
+    }
+
+    // Files with this content, numbered from inode 1 in turn, and the records of them.  A file
+    // with no content given is recorded (with the size of the first) but isn't there to read.
+    fn in_memory(contents: &[Option<&str>]) -> (InMemory, Vec<DedupFile>) {
+        let size = contents[0].map_or(0, |content| content.len() as u64);
+        let mut files = InMemory::default();
+        let mut records = Vec::new();
+        for (i, content) in contents.iter().enumerate() {
+            let path = format!("/mem/{}", i + 1);
+            if let Some(content) = content {
+                files
+                    .0
+                    .insert(PathBuf::from(&path), content.as_bytes().to_vec());
+            }
+            records.push(fake_file(&[&path], size, i as u64 + 1));
+        }
+        (files, records)
+    }
+
+    // The inode numbers in each group, in order, so that groups can be compared without regard to
+    // the order they were put together in.
+    fn inode_groups(groups: Vec<Vec<DedupFile>>) -> Vec<Vec<u64>> {
+        let mut groups: Vec<Vec<u64>> = groups
+            .iter()
+            .map(|group| group.iter().map(|df| df.inode).collect())
+            .collect();
+        for group in &mut groups {
+            group.sort();
+        }
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn exact_comparator_groups_files_with_the_same_bytes() {
+        let contents = [Some("same"), Some("SAME"), Some("sack"), Some("same"), None];
+        let errors = ErrorLog::default();
+        let (files, records) = in_memory(&contents);
+        let exact = ExactComparator::new(files, CompareOptions::default(), &errors);
+        assert_eq!(
+            inode_groups(exact.group(records.clone())),
+            [vec![1, 4], vec![2], vec![3], vec![5]]
+        );
+        assert!(exact.comparisons() > 0);
+        // The file that isn't there is kept apart, and noted once however often it was tried.
+        let missing = errors.take();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].path, Path::new("/mem/5"));
+
+        let (files, _) = in_memory(&contents);
+        let loose = CompareOptions {
+            ignore_case: true,
+            limit: Some(2),
+            ..CompareOptions::default()
+        };
+        let exact = ExactComparator::new(files, loose, &errors);
+        assert_eq!(
+            inode_groups(exact.group(records)),
+            [vec![1, 2, 3, 4], vec![5]]
+        );
+    }
+
+    #[test]
+    fn double_hash_comparator_groups_files_with_the_same_digests() {
+        let errors = ErrorLog::default();
+        let (files, records) = in_memory(&[Some("same"), Some("diff"), Some("same"), None]);
+        let hashing = DoubleHashComparator::new(files, CompareOptions::default(), &errors);
+        // The file that isn't there is left out altogether.
+        assert_eq!(inode_groups(hashing.group(records)), [vec![1, 3], vec![2]]);
+        assert_eq!(hashing.comparisons(), 0);
+        assert_eq!(errors.take().len(), 1);
+    }
+
+    #[test]
+    fn size_only_comparator_groups_everything_it_is_given_unread() {
+        let (_, records) = in_memory(&[Some("same"), Some("diff"), None]);
+        assert_eq!(
+            inode_groups(SizeOnlyComparator.group(records)),
+            [vec![1, 2, 3]]
+        );
+    }
+
+    fn same(data1: &[u8], data2: &[u8]) -> bool {
+        let (len1, len2) = (data1.len() as u64, data2.len() as u64);
+        compare_content(
+            &mut Cursor::new(data1),
+            len1,
+            &mut Cursor::new(data2),
+            len2,
+            false,
+        )
+        .unwrap()
+    }
+
+    fn difference(data1: &[u8], data2: &[u8]) -> Option<u64> {
+        let (len1, len2) = (data1.len() as u64, data2.len() as u64);
+        first_difference(
+            &mut Cursor::new(data1),
+            len1,
+            &mut Cursor::new(data2),
+            len2,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn sources_in_memory_compare_by_content() {
+        assert!(same(b"", b""));
+        assert!(same(b"hello", b"hello"));
+        assert!(!same(b"hello", b"hellp"));
+        assert!(!same(b"hello", b"hello!"));
+        // More than one buffer's worth, differing only in the last byte.
+        let big = vec![b'x'; BUFFER_LEN * 2 + 3];
+        let mut other = big.clone();
+        assert!(same(&big, &other));
+        *other.last_mut().unwrap() = b'y';
+        assert!(!same(&big, &other));
+    }
+
+    #[test]
+    fn only_the_lengths_given_are_compared() {
+        let (mut src1, mut src2) = (Cursor::new(b"same, then a"), Cursor::new(b"same, then b"));
+        assert!(compare_content(&mut src1, 10, &mut src2, 10, false).unwrap());
+        let (mut src1, mut src2) = (Cursor::new(b"same"), Cursor::new(b"same"));
+        assert!(!compare_content(&mut src1, 4, &mut src2, 5, false).unwrap());
+    }
+
+    #[test]
+    fn short_reads_are_not_differences() {
+        let data: Vec<u8> = (0..BUFFER_LEN as u32 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let len = data.len() as u64;
+        let mut trickle = Trickle(Cursor::new(data.clone()));
+        assert!(compare_content(&mut Cursor::new(&data), len, &mut trickle, len, false).unwrap());
+        let mut trickle = Trickle(Cursor::new(data.clone()));
+        assert_eq!(
+            first_difference(&mut trickle, len, &mut Cursor::new(&data), len, false).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn failures_say_which_source_failed() {
+        let mut good = Cursor::new(b"data");
+        assert!(matches!(
+            compare_content(&mut good, 4, &mut Broken, 4, false),
+            Err(ReadFailure::Second(_))
+        ));
+        assert!(matches!(
+            first_difference(&mut Broken, 4, &mut good, 4, false),
+            Err(ReadFailure::First(_))
+        ));
+    }
+
+    #[test]
+    fn the_first_difference_is_where_the_sources_part() {
+        assert_eq!(difference(b"hello", b"hello"), None);
+        assert_eq!(difference(b"hello", b"jello"), Some(0));
+        assert_eq!(difference(b"hello", b"help!"), Some(3));
+        // One is the start of the other.
+        assert_eq!(difference(b"hello", b"hello, world"), Some(5));
+        assert_eq!(difference(b"", b"x"), Some(0));
+        let big = vec![b'x'; BUFFER_LEN + 10];
+        let mut other = big.clone();
+        other[BUFFER_LEN + 4] = b'y';
+        assert_eq!(difference(&big, &other), Some(BUFFER_LEN as u64 + 4));
+        // Ignoring case, letters match whatever their case.
+        let (mut src1, mut src2) = (Cursor::new(b"Hello!"), Cursor::new(b"hELLO?"));
+        assert_eq!(
+            first_difference(&mut src1, 6, &mut src2, 6, true).unwrap(),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn files_first_differ_where_their_content_does() {
+        let dir = TempDir::new();
+        let content: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let (mut early, mut late) = (content.clone(), content.clone());
+        early[3] ^= 1;
+        late[99_998] ^= 1;
+        let original = dir.write("original", &content);
+        let copy = dir.write("copy", &content);
+        let early = dir.write("early", early);
+        let late = dir.write("late", late);
+        let short = dir.write("short", &content[..5000]);
+        let exact = CompareOptions::default();
+
+        let offset =
+            |path: &Path, options| first_file_difference(&original, path, options).unwrap();
+        assert_eq!(offset(&copy, exact), None);
+        assert_eq!(offset(&early, exact), Some(3));
+        assert_eq!(offset(&late, exact), Some(99_998));
+        // Where one is shorter, that's where they part.
+        assert_eq!(offset(&short, exact), Some(5000));
+        // Offsets are from the start of the file even with a header left out, and differences in
+        // the header or trailer aren't differences.
+        let ignoring = |header, trailer| CompareOptions {
+            ignore_header: header,
+            ignore_trailer: trailer,
+            ..exact
+        };
+        assert_eq!(offset(&late, ignoring(10, 0)), Some(99_998));
+        assert_eq!(offset(&early, ignoring(10, 0)), None);
+        assert_eq!(offset(&late, ignoring(0, 2)), None);
+        assert!(first_file_difference(&original, &dir.path().join("missing"), exact).is_err());
+    }
+
+    #[test]
+    fn sampled_blocks_miss_differences_outside_them() {
+        let len: u64 = 1 << 20;
+        let offsets = sample_offsets(len, 4).unwrap();
+        assert_eq!(sample_offsets(len, 4).unwrap(), offsets);
+        // One block in each quarter of the file.
+        for (i, &offset) in offsets.iter().enumerate() {
+            let stretch = len / 4;
+            assert!(offset >= i as u64 * stretch);
+            assert!(offset + SAMPLE_BLOCK_LEN <= (i as u64 + 1) * stretch);
+        }
+        assert_eq!(sample_offsets(4 * SAMPLE_BLOCK_LEN, 4), None);
+
+        let dir = TempDir::new();
+        let content: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let unsampled = (0..len)
+            .find(|&i| {
+                offsets
+                    .iter()
+                    .all(|&offset| i < offset || i >= offset + SAMPLE_BLOCK_LEN)
+            })
+            .unwrap();
+        let with_change = |at: u64| {
+            let mut changed = content.clone();
+            changed[at as usize] ^= 1;
+            changed
+        };
+        let original = dir.write("original", &content);
+        let outside = dir.write("outside", with_change(unsampled));
+        let inside = dir.write("inside", with_change(offsets[2] + 7));
+        let sampled = CompareOptions {
+            sample_blocks: Some(4),
+            ..CompareOptions::default()
+        };
+
+        // The known limitation: a difference between the blocks goes unseen.
+        assert!(compare_file_bytes(&original, &outside, len, sampled).unwrap());
+        assert!(!compare_file_bytes(&original, &outside, len, CompareOptions::default()).unwrap());
+        assert!(!compare_file_bytes(&original, &inside, len, sampled).unwrap());
+    }
+
+    #[test]
+    fn ignore_case_content_matches_files_differing_only_in_case() {
+        let dir = TempDir::new();
+        let lower = dir.write("lower.txt", "hello, world\n");
+        let mixed = dir.write("mixed.txt", "Hello, World\n");
+        let other = dir.write("other.txt", "Hello, Wxrld\n");
+        let exact = CompareOptions::default();
+        let ignore_case = CompareOptions {
+            ignore_case: true,
+            ..exact
+        };
+        assert!(!compare_file_bytes(&lower, &mixed, 13, exact).unwrap());
+        assert!(compare_file_bytes(&lower, &mixed, 13, ignore_case).unwrap());
+        assert!(!compare_file_bytes(&lower, &other, 13, ignore_case).unwrap());
+    }
+
+    #[test]
+    fn files_differing_only_in_their_trailers_match_when_it_is_ignored() {
+        let dir = TempDir::new();
+        let a = dir.write("a", "same body\n--stamp 0001");
+        let b = dir.write("b", "same body\n--stamp 0002");
+        let c = dir.write("c", "some body\n--stamp 0001");
+        let exact = CompareOptions::default();
+        let ignore_trailer = CompareOptions {
+            ignore_trailer: 13,
+            ..exact
+        };
+        assert_eq!(compared_len(23, ignore_trailer), 10);
+        assert!(!compare_file_bytes(&a, &b, 23, exact).unwrap());
+        assert!(compare_file_bytes(&a, &b, 23, ignore_trailer).unwrap());
+        assert!(!compare_file_bytes(&a, &c, 23, ignore_trailer).unwrap());
+
+        let files = [a, b, c].map(|path| file_record(&path));
+        let errors = ErrorLog::default();
+        let options = options(&["--ignore-trailer", "13", "/"]);
+        let groups: Vec<Vec<DedupFile>> =
+            group_by_content(vec![files.to_vec()], &options, &errors).collect();
+        assert_eq!(groups.len(), 1);
+        let mut names: Vec<&OsStr> = groups[0]
+            .iter()
+            .map(|df| df.paths[0].file_name().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn files_differing_only_in_their_headers_match_when_it_is_ignored() {
+        let dir = TempDir::new();
+        let a = dir.write("a", "v1|same body");
+        let b = dir.write("b", "v2|same body");
+        let c = dir.write("c", "v1|some body");
+        let ignore_header = CompareOptions {
+            ignore_header: 3,
+            ..CompareOptions::default()
+        };
+        assert_eq!(compared_len(12, ignore_header), 9);
+        assert!(compare_file_bytes(&a, &b, 12, ignore_header).unwrap());
+        assert!(!compare_file_bytes(&a, &c, 12, ignore_header).unwrap());
+    }
+
+    #[test]
+    fn compare_limit_stops_once_that_many_bytes_match() {
+        let dir = TempDir::new();
+        let a = dir.write("a", "12345678 then a");
+        let b = dir.write("b", "12345678 then b");
+        let c = dir.write("c", "1234567x then a");
+        let limited = CompareOptions {
+            limit: Some(8),
+            ..CompareOptions::default()
+        };
+        assert!(!compare_file_bytes(&a, &b, 15, CompareOptions::default()).unwrap());
+        assert!(compare_file_bytes(&a, &b, 15, limited).unwrap());
+        assert!(!compare_file_bytes(&a, &c, 15, limited).unwrap());
+        // The limit counts from the end of any header.
+        let after_header = CompareOptions {
+            ignore_header: 9,
+            limit: Some(6),
+            ..CompareOptions::default()
+        };
+        assert!(compare_file_bytes(&a, &c, 15, after_header).unwrap());
+        assert!(!compare_file_bytes(&a, &b, 15, after_header).unwrap());
+
+        let files = [a, b, c].map(|path| file_record(&path));
+        let options = options(&["--compare-limit", "8", "/"]);
+        let groups: Vec<Vec<DedupFile>> =
+            group_by_content(vec![files.to_vec()], &options, &ErrorLog::default()).collect();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+        assert_eq!(Confidence::of(&groups[0], &options), Confidence::Prefix);
+        // Files no bigger than the limit are compared in full, so their matches are certain.
+        assert_eq!(
+            Confidence::of(&[fake_file(&["/x"], 8, 1)], &options),
+            Confidence::Bytes
+        );
+    }
+
+    #[test]
+    fn each_mode_of_comparison_has_its_confidence() {
+        let group = [
+            fake_file(&["/a"], 1 << 20, 1),
+            fake_file(&["/b"], 1 << 20, 2),
+        ];
+        let confidence = |args: &[&str]| {
+            let options = options(&[args, &["/"]].concat());
+            Confidence::of(&group, &options).name()
+        };
+        assert_eq!(confidence(&[]), "byte-confirmed");
+        assert_eq!(confidence(&["--double-hash"]), "hash-confirmed");
+        assert_eq!(confidence(&["--compare-limit", "4096"]), "prefix-confirmed");
+        assert_eq!(confidence(&["--sample-blocks", "4"]), "sampled");
+        assert_eq!(confidence(&["--assume-unchanged"]), "unconfirmed");
+        // Files from dumps that aren't here to compare are matched by their recorded digests.
+        let merging = options(&["--merge-indexes", "a.dump", "b.dump"]);
+        assert_eq!(Confidence::of(&group, &merging), Confidence::Hashes);
+        // Going by the blocks sampled only falls short for files bigger than those all together.
+        assert_eq!(confidence(&["--sample-blocks", "16"]), "byte-confirmed");
+    }
+
+    #[test]
+    fn each_group_key_splits_a_group_by_what_it_names() {
+        let group: Vec<DedupFile> = [
+            ("/x/a.JPG", 0),
+            ("/y/a.jpg", 0),
+            ("/y/b.jpg", 5),
+            ("/z/a.JPG", 5),
+            ("/z/c.png", 5),
+        ]
+        .iter()
+        .zip(1..)
+        .map(|(&(path, mtime), inode)| DedupFile {
+            mtime,
+            ..fake_file(&[path], 10, inode)
+        })
+        .collect();
+        let split = |key: &str| -> Vec<Vec<u64>> {
+            split_by_key(group.clone(), &options(&["--group-key", key, "/"]))
+                .iter()
+                .map(|split| split.iter().map(|df| df.inode).collect())
+                .collect()
+        };
+        assert_eq!(split("content"), [[1, 2, 3, 4, 5]]);
+        assert_eq!(split("content+name"), [vec![1, 4]]);
+        assert_eq!(split("content+ext"), [vec![1, 2, 3, 4]]);
+        assert_eq!(split("content+mtime"), [vec![1, 2], vec![3, 4, 5]]);
+    }
+
+    #[test]
+    fn double_hash_groups_files_only_if_both_hashes_match() {
+        let dir = TempDir::new();
+        let files = [("a", "same"), ("b", "same"), ("c", "diff")]
+            .map(|(name, content)| file_record(&dir.write(name, content)));
+        let errors = ErrorLog::default();
+        let options = options(&["--double-hash", "/"]);
+        let groups: Vec<Vec<u64>> = group_by_content(vec![files.to_vec()], &options, &errors)
+            .map(|group| group.iter().map(|df| df.inode).collect())
+            .collect();
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].contains(&files[0].inode) && groups[0].contains(&files[1].inode));
+        assert!(errors.take().is_empty());
+
+        // Neither hash is enough on its own: made-up digests that collide in one but not the other
+        // (the first for files 1 and 2, the second for 3 and 4) keep the files apart.
+        let files: Vec<DedupFile> = (1..=4).map(|inode| fake_file(&["/f"], 4, inode)).collect();
+        let digests = |df: &DedupFile| match df.inode {
+            1 | 2 => Ok(("collision".to_owned(), df.inode)),
+            _ => Ok((format!("digest {}", df.inode), 0)),
+        };
+        let groups: Vec<Vec<u64>> = group_by_digests(files, &errors, digests)
+            .iter()
+            .map(|group| group.iter().map(|df| df.inode).collect())
+            .collect();
+        assert_eq!(groups, [[1], [2], [3], [4]]);
+    }
+
+    #[test]
+    fn assume_unchanged_groups_files_without_reading_them() {
+        // Files that aren't there at all: any attempt to read them would be noted as an error.
+        let dir = TempDir::new();
+        let files: Vec<DedupFile> = (1..=3)
+            .map(|inode| DedupFile {
+                paths: vec![dir.path().join(format!("missing-{}", inode))],
+                ..fake_file(&[], 4096, inode)
+            })
+            .collect();
+        let errors = ErrorLog::default();
+        let assumed = options(&["--assume-unchanged", "/"]);
+        let groups: Vec<Vec<DedupFile>> =
+            group_by_content(vec![files.clone()], &assumed, &errors).collect();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 3);
+        assert_eq!(
+            Confidence::of(&groups[0], &assumed),
+            Confidence::Unconfirmed
+        );
+        assert!(errors.take().is_empty());
+
+        // Comparing them means reading them, which fails.
+        let groups: Vec<Vec<DedupFile>> =
+            group_by_content(vec![files], &options(&["/"]), &errors).collect();
+        assert!(groups.is_empty());
+        assert!(!errors.take().is_empty());
+    }
+
+    #[test]
+    fn compare_metadata_splits_identical_files_with_different_mtimes() {
+        let dir = TempDir::new();
+        let paths = ["a", "b", "c"].map(|name| dir.write(name, "same"));
+        let day_ago = SystemTime::now() - Duration::from_secs(86400);
+        File::options()
+            .write(true)
+            .open(&paths[0])
+            .and_then(|f| f.set_modified(day_ago))
+            .unwrap();
+        let files = paths.map(|path| file_record(&path));
+        let errors = ErrorLog::default();
+
+        let groups: Vec<Vec<DedupFile>> =
+            group_by_content(vec![files.to_vec()], &options(&["/"]), &errors).collect();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 3);
+        assert_eq!(split_by_key(groups[0].clone(), &options(&["/"])).len(), 1);
+
+        let options = options(&["--compare-metadata", "/"]);
+        let groups: Vec<Vec<DedupFile>> = group_by_content(vec![files.to_vec()], &options, &errors)
+            .flat_map(|group| split_by_key(group, &options))
+            .collect();
+        assert_eq!(groups.len(), 1);
+        let mut names: Vec<&OsStr> = groups[0]
+            .iter()
+            .map(|df| df.paths[0].file_name().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["b", "c"]);
+    }
+
+    #[test]
+    fn compare_metadata_needs_the_same_mode_and_owner_too() {
+        let options = options(&["--compare-metadata", "/"]);
+        let base = fake_file(&["a"], 10, 1);
+        let mut mode = fake_file(&["b"], 10, 2);
+        mode.mode = 0o600;
+        let mut uid = fake_file(&["c"], 10, 3);
+        uid.uid = 1000;
+        let mut gid = fake_file(&["d"], 10, 4);
+        gid.gid = 1000;
+        let group = vec![base, mode, uid, gid, fake_file(&["e"], 10, 5)];
+        let splits = split_by_key(group, &options);
+        assert_eq!(splits.len(), 1);
+        let inodes: Vec<u64> = splits[0].iter().map(|df| df.inode).collect();
+        assert_eq!(inodes, [1, 5]);
     }
 }
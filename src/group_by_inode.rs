@@ -1,56 +1,447 @@
 use std::collections::HashSet;
 use std::fs::{self, DirEntry, Metadata};
+use std::io::ErrorKind;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::broken_symlinks::note_broken_symlink;
+use crate::deadline::past_deadline;
+use crate::errors::{ErrorLog, ScanError};
+use crate::glob::matches_any;
+use crate::options::{Options, SymlinkPolicy};
+use crate::progress::{self, Phase};
+use crate::sample::{file_key, path_key, Sampler};
 
 // Vital stats of a file.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DedupFile {
     pub paths: Vec<PathBuf>,
     pub size: u64,
+    // Space actually allocated on disk, which may be much less than `size` for sparse files.
+    pub alloc_size: u64,
     pub device: u64,
+    // Zero if the filesystem's inode numbers can't be trusted to tell files apart (see
+    // `group_by_inode`), in which case the file is identified by its path alone.
     pub inode: u64,
     pub nlink: u64,
+    pub mtime: i64,
+    // Permission bits (not the file type, which is always a regular file) and owner.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    // The BLAKE3 digest of the content, if we know it (e.g. it was loaded from a file dump).
+    pub hash: Option<String>,
+    // BLAKE3 digests of each fixed-size chunk of the content, with `--chunk-hashes`.
+    pub chunk_hashes: Option<Vec<String>>,
+    // Under one of the `--protect` directories, so never to be removed.
+    pub protected: bool,
+}
+
+// What tells one file on disk apart from another.  Normally that's its device and inode numbers,
+// which all its hard links share.  Without a usable inode number it's the path, so hard links to
+// the same file are taken to be separate files.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FileIdentity {
+    Inode(u64, u64),
+    Path(PathBuf),
+}
+
+impl DedupFile {
+    pub fn identity(&self) -> FileIdentity {
+        if self.inode == 0 {
+            FileIdentity::Path(self.paths[0].clone())
+        } else {
+            FileIdentity::Inode(self.device, self.inode)
+        }
+    }
+
+    // True iff this file is on the local filesystem, as opposed to e.g. a record loaded from a
+    // dump made on another machine where the same path may well be a different file.  We check
+    // that the path still leads to the same inode with the same size.
+    pub fn is_local(&self) -> bool {
+        fs::metadata(&self.paths[0])
+            .map(|m| {
+                m.dev() == self.device
+                    && (self.inode == 0 || m.ino() == self.inode)
+                    && m.len() == self.size
+            })
+            .unwrap_or(false)
+    }
+}
+
+// Counts of what we found while traversing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraversalStats {
+    // Directory entries of any kind that we looked at.
+    pub entries_seen: u64,
+    // Regular files that passed our filters.
+    pub files_considered: u64,
+    // Files that would have passed, but were created or changed after a `--snapshot` began.
+    pub files_too_new: u64,
+    // Files that would have passed, but were modified less than `--min-age` ago.
+    pub files_too_young: u64,
+    // Files that would have passed, but belong to someone else (with `--owned-only`).
+    pub files_not_owned: u64,
+    // Directories whose entries we've read (or tried to).
+    pub dirs_read: u64,
+}
+
+// How far a traversal has got, so that it can be saved and picked up again later (see
+// `checkpoint`).  This doesn't include the files already found, which the caller has.
+#[derive(Clone, Debug, Default)]
+pub struct TraversalState {
+    // Directories still to be read, each with the device and inode numbers of itself and its
+    // ancestors, innermost first.
+    pub dir_queue: Vec<(PathBuf, Vec<(u64, u64)>)>,
+    pub seen_dir_paths: Vec<PathBuf>,
+    pub stats: TraversalStats,
+    pub yielded: u64,
+}
+
+// A directory waiting to be read, with the device and inode numbers of its ancestors (and itself)
+// as a chain shared with its siblings.
+struct QueuedDir {
+    path: PathBuf,
+    ancestors: Option<Rc<AncestorDir>>,
+}
+
+struct AncestorDir {
+    device: u64,
+    inode: u64,
+    parent: Option<Rc<AncestorDir>>,
+}
+
+impl AncestorDir {
+    // True iff this directory or one of its ancestors has these device and inode numbers.
+    fn contains(chain: &Option<Rc<AncestorDir>>, device: u64, inode: u64) -> bool {
+        let mut dir = chain.as_deref();
+        while let Some(d) = dir {
+            if d.device == device && d.inode == inode {
+                return true;
+            }
+            dir = d.parent.as_deref();
+        }
+        false
+    }
 }
 
 pub struct GroupByInodeIter {
     min_size: u64,
+    max_size: Option<u64>,
+    exclude_empty_files: bool,
+    // Header and trailer bytes to be ignored when comparing, which files must be bigger than.
+    ignored_len: u64,
+    by_alloc_size: bool,
+    excluded_devices: HashSet<u64>,
+    // Devices whose inode numbers we don't trust, and whether to trust any at all.
+    unreliable_inode_devices: HashSet<u64>,
+    no_inode: bool,
+    symlinks: SymlinkPolicy,
+    // Note symlinks whose targets don't exist, for `--find-broken-symlinks`.
+    find_broken_symlinks: bool,
+    // The root with any symlinks resolved, to tell which symlinks lead within it.
+    canonical_root: Option<PathBuf>,
+    strict_containment: bool,
+    excludes: Vec<String>,
+    includes: Vec<String>,
+    limit: Option<u64>,
+    sampler: Option<Sampler>,
+    // When a `--snapshot` began, as seconds and nanoseconds since the epoch.
+    snapshot_start: Option<(i64, i64)>,
+    // Files modified after this (for `--min-age`), in the same form, are skipped.
+    modified_cutoff: Option<(i64, i64)>,
+    // If set, only this user's files are considered.
+    owner: Option<u32>,
+    yielded: u64,
     file_queue: Vec<DedupFile>,
-    dir_queue: Vec<PathBuf>,
-    seen_dirs: HashSet<(u64, u64)>,
+    dir_queue: Vec<QueuedDir>,
+    // To avoid going round in circles, we don't descend into a directory that is its own ancestor
+    // (e.g. through a bind mount of an ancestor).  Other directories we reach by more than one
+    // path, such as a bind mount of a sibling, are traversed under each, so that the files in them
+    // are consolidated with all their paths.  Without usable inode numbers we can only tell
+    // directories apart by their real paths (with symlinks resolved), so we just never visit the
+    // same real path twice.
+    seen_dir_paths: HashSet<PathBuf>,
+    stats: TraversalStats,
+    errors: ErrorLog,
 }
 
 impl GroupByInodeIter {
+    // True iff the metadata's inode number can be used to tell it apart from other files.
+    fn has_reliable_inode(&self, metadata: &Metadata) -> bool {
+        !self.no_inode
+            && metadata.ino() != 0
+            && !self.unreliable_inode_devices.contains(&metadata.dev())
+    }
+
     // True iff the metadata belongs to a directory we would like to traverse.
-    fn is_wanted_dir(&self, metadata: &Metadata) -> bool {
-        metadata.is_dir() && !self.seen_dirs.contains(&(metadata.dev(), metadata.ino()))
+    fn is_wanted_dir(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        ancestors: &Option<Rc<AncestorDir>>,
+    ) -> bool {
+        let seen = if self.has_reliable_inode(metadata) {
+            AncestorDir::contains(ancestors, metadata.dev(), metadata.ino())
+        } else {
+            self.seen_dir_paths.contains(&real_path(path))
+        };
+        metadata.is_dir() && !self.excluded_devices.contains(&metadata.dev()) && !seen
     }
 
-    // True iff the metadata belongs to a file we would like to consider.
-    fn is_wanted_file(&self, metadata: &Metadata) -> bool {
-        metadata.is_file() && metadata.len() >= self.min_size
+    // True iff the metadata belongs to a file we would like to consider.  The size limits are
+    // inclusive.  Empty files are a special case: they're all "duplicates" of each other, so unless
+    // asked otherwise we skip them even if `min_size` is zero.
+    fn is_wanted_file(&self, path: &Path, name: &str, metadata: &Metadata) -> bool {
+        let size = if self.by_alloc_size {
+            alloc_size(metadata)
+        } else {
+            metadata.len()
+        };
+        metadata.is_file()
+            && size >= self.min_size
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+            && !(self.exclude_empty_files && metadata.len() == 0)
+            && (self.ignored_len == 0 || metadata.len() > self.ignored_len)
+            && !self.excluded_devices.contains(&metadata.dev())
+            && (self.includes.is_empty() || matches_any(&self.includes, name))
+            && self.is_sampled(path, metadata)
     }
 
-    // Push a file/directory to the appropriate queue (if we want to).
-    fn push_child(&mut self, path: &Path, metadata: &Metadata) {
-        if self.is_wanted_dir(metadata) {
-            self.dir_queue.push(path.to_path_buf());
-        } else if self.is_wanted_file(metadata) {
+    // True iff the file is in our random sample of the tree, if we're only taking a sample.
+    fn is_sampled(&self, path: &Path, metadata: &Metadata) -> bool {
+        let Some(sampler) = &self.sampler else {
+            return true;
+        };
+        let key = if self.has_reliable_inode(metadata) {
+            file_key(metadata.dev(), metadata.ino())
+        } else {
+            path_key(path)
+        };
+        sampler.includes(key)
+    }
+
+    // True iff a file's content or metadata changed after the snapshot began, so that it may not
+    // be in the state it was in at that instant.
+    fn is_too_new(&self, metadata: &Metadata) -> bool {
+        self.snapshot_start.is_some_and(|start| {
+            (metadata.mtime(), metadata.mtime_nsec()) > start
+                || (metadata.ctime(), metadata.ctime_nsec()) > start
+        })
+    }
+
+    // The metadata to go by for a directory entry: its own, or with a symlink we follow, its
+    // target's.  `None` means a symlink we don't follow, including one whose target is missing.
+    fn resolve_symlink(&self, path: &Path, metadata: Metadata) -> Option<Metadata> {
+        if !metadata.is_symlink() {
+            return Some(metadata);
+        }
+        match self.symlinks {
+            SymlinkPolicy::Skip => None,
+            SymlinkPolicy::Follow => fs::metadata(path).ok(),
+            SymlinkPolicy::WithinRoot => {
+                let target = fs::canonicalize(path).ok()?;
+                let root = self.canonical_root.as_ref()?;
+                if target.starts_with(root) {
+                    fs::metadata(&target).ok()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    // With `--find-broken-symlinks`, note a symlink if its target doesn't exist (unless its name is
+    // excluded, like anything else).
+    fn check_symlink_target(&self, path: &Path) {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        if matches_any(&self.excludes, &name) {
+            return;
+        }
+        if let Err(e) = fs::metadata(path) {
+            if e.kind() == ErrorKind::NotFound {
+                match fs::read_link(path) {
+                    Ok(target) => note_broken_symlink(path, target),
+                    Err(e) => self
+                        .errors
+                        .record(ScanError::new(path, Phase::Traverse, &e)),
+                }
+            }
+        }
+    }
+
+    // True iff a file's real path is under the root, so that it's safe to act on.  Only a symlink
+    // below the root can lead outside it, so unless we follow them there's nothing to check.
+    fn is_contained(&self, path: &Path) -> bool {
+        if !self.strict_containment || self.symlinks == SymlinkPolicy::Skip {
+            return true;
+        }
+        match fs::canonicalize(path) {
+            Ok(real_path) => self
+                .canonical_root
+                .as_ref()
+                .is_some_and(|root| real_path.starts_with(root)),
+            Err(e) => {
+                self.errors
+                    .record(ScanError::new(path, Phase::Traverse, &e));
+                false
+            }
+        }
+    }
+
+    // True iff a file was modified too recently to be left alone by whoever's working on it.
+    fn is_too_young(&self, metadata: &Metadata) -> bool {
+        self.modified_cutoff
+            .is_some_and(|cutoff| (metadata.mtime(), metadata.mtime_nsec()) > cutoff)
+    }
+
+    // Push a file/directory to the appropriate queue (if we want to).  Excluded names are skipped
+    // whatever they are, so excluding a directory prunes everything beneath it.
+    fn push_child(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        ancestors: &Option<Rc<AncestorDir>>,
+    ) {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        if matches_any(&self.excludes, &name) {
+            return;
+        }
+        if self.is_wanted_dir(path, metadata, ancestors) {
+            let ancestors = if self.has_reliable_inode(metadata) {
+                Some(Rc::new(AncestorDir {
+                    device: metadata.dev(),
+                    inode: metadata.ino(),
+                    parent: ancestors.clone(),
+                }))
+            } else {
+                self.seen_dir_paths.insert(real_path(path));
+                ancestors.clone()
+            };
+            self.dir_queue.push(QueuedDir {
+                path: path.to_path_buf(),
+                ancestors,
+            });
+        } else if self.is_wanted_file(path, &name, metadata) && self.is_contained(path) {
+            if self.is_too_new(metadata) {
+                self.stats.files_too_new += 1;
+                return;
+            }
+            if self.is_too_young(metadata) {
+                self.stats.files_too_young += 1;
+                return;
+            }
+            if self.owner.is_some_and(|uid| metadata.uid() != uid) {
+                self.stats.files_not_owned += 1;
+                return;
+            }
+            self.stats.files_considered += 1;
             self.file_queue.push(DedupFile {
                 paths: vec![path.to_path_buf()],
                 size: metadata.len(),
+                alloc_size: alloc_size(metadata),
                 device: metadata.dev(),
-                inode: metadata.ino(),
+                inode: if self.has_reliable_inode(metadata) {
+                    metadata.ino()
+                } else {
+                    0
+                },
                 nlink: metadata.nlink(),
+                mtime: metadata.mtime(),
+                mode: metadata.mode() & 0o7777,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                hash: None,
+                chunk_hashes: None,
+                protected: false,
             });
         }
     }
 
-    // Read a directory's children, ignoring failures.
-    fn read_dir_optimistically(path: &Path) -> Vec<DirEntry> {
+    // What we've found so far.
+    pub fn stats(&self) -> TraversalStats {
+        self.stats
+    }
+
+    // True iff every file found so far has been yielded, so that the traversal's state together
+    // with those files is all there is to know.
+    pub fn is_between_dirs(&self) -> bool {
+        self.file_queue.is_empty()
+    }
+
+    // Where we've got to.  This should only be saved while `is_between_dirs`, since files waiting
+    // to be yielded aren't included.
+    pub fn state(&self) -> TraversalState {
+        let dir_queue = self
+            .dir_queue
+            .iter()
+            .map(|dir| {
+                let mut ancestors = Vec::new();
+                let mut ancestor = dir.ancestors.as_deref();
+                while let Some(a) = ancestor {
+                    ancestors.push((a.device, a.inode));
+                    ancestor = a.parent.as_deref();
+                }
+                (dir.path.clone(), ancestors)
+            })
+            .collect();
+        TraversalState {
+            dir_queue,
+            seen_dir_paths: self.seen_dir_paths.iter().cloned().collect(),
+            stats: self.stats,
+            yielded: self.yielded,
+        }
+    }
+
+    // Carry on from a saved state rather than starting at the root.
+    pub fn resume(mut self, state: TraversalState) -> GroupByInodeIter {
+        self.dir_queue = state
+            .dir_queue
+            .into_iter()
+            .map(|(path, ancestors)| QueuedDir {
+                path,
+                ancestors: ancestors
+                    .into_iter()
+                    .rev()
+                    .fold(None, |parent, (device, inode)| {
+                        Some(Rc::new(AncestorDir {
+                            device,
+                            inode,
+                            parent,
+                        }))
+                    }),
+            })
+            .collect();
+        self.seen_dir_paths = state.seen_dir_paths.into_iter().collect();
+        self.stats = state.stats;
+        self.yielded = state.yielded;
+        self
+    }
+
+    // Read a directory's children, noting but otherwise ignoring failures.
+    fn read_dir_optimistically(&self, path: &Path) -> Vec<DirEntry> {
         match fs::read_dir(path) {
-            Err(_) => Vec::new(),
-            Ok(read_dir) => read_dir.filter_map(|d| d.ok()).collect(),
+            Err(e) => {
+                self.errors
+                    .record(ScanError::new(path, Phase::Traverse, &e));
+                Vec::new()
+            }
+            Ok(read_dir) => read_dir
+                .filter_map(|d| {
+                    d.map_err(|e| {
+                        self.errors
+                            .record(ScanError::new(path, Phase::Traverse, &e))
+                    })
+                    .ok()
+                })
+                .collect(),
         }
     }
 }
@@ -59,22 +450,51 @@ impl Iterator for GroupByInodeIter {
     type Item = DedupFile;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // Stop early if we've been asked for a sample rather than the whole tree.
+        if self.limit.is_some_and(|limit| self.yielded >= limit) {
+            return None;
+        }
+
         while !(self.file_queue.is_empty() && self.dir_queue.is_empty()) {
+            // Or if we've run out of time.
+            if past_deadline() {
+                return None;
+            }
+
             // If we have some files from a previous dir read, return those first.  This results in
             // a breadth-first traversal of the filesystem hierarchy.
             let f = self.file_queue.pop();
             if f.is_some() {
+                self.yielded += 1;
+                progress::file_found();
                 return f;
             }
 
             // If we have a candidate directory from a previous dir read, push its children onto
             // the queues.
-            if let Some(dir_path) = self.dir_queue.pop() {
-                for child_entry in GroupByInodeIter::read_dir_optimistically(&dir_path) {
-                    if let Ok(child_metadata) = child_entry.metadata() {
-                        self.push_child(&child_entry.path(), &child_metadata);
-                        // Don't return a result here -- do that on the next iteration of the
-                        // outer loop.
+            if let Some(dir) = self.dir_queue.pop() {
+                self.stats.dirs_read += 1;
+                progress::dir_read();
+                for child_entry in self.read_dir_optimistically(&dir.path) {
+                    self.stats.entries_seen += 1;
+                    let child_path = child_entry.path();
+                    match child_entry.metadata() {
+                        Ok(child_metadata) => {
+                            if self.find_broken_symlinks && child_metadata.is_symlink() {
+                                self.check_symlink_target(&child_path);
+                            }
+                            if let Some(child_metadata) =
+                                self.resolve_symlink(&child_path, child_metadata)
+                            {
+                                self.push_child(&child_path, &child_metadata, &dir.ancestors);
+                            }
+                            // Don't return a result here -- do that on the next iteration of the
+                            // outer loop.
+                        }
+                        Err(e) => {
+                            self.errors
+                                .record(ScanError::new(&child_path, Phase::Traverse, &e))
+                        }
                     }
                 }
             }
@@ -86,14 +506,453 @@ impl Iterator for GroupByInodeIter {
     }
 }
 
+// The path with any symlinks resolved, or as it is if we can't resolve them.
+fn real_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// The space allocated to a file on disk.  `st_blocks` is in 512-byte units regardless of the
+// filesystem's block size.
+fn alloc_size(metadata: &Metadata) -> u64 {
+    metadata.blocks() * 512
+}
+
 // Recursively descend through a filesystem hierarchy, collecting information about only regular
-// files.
-pub fn group_by_inode(root: &Path, min_size: u64) -> GroupByInodeIter {
-    let root_absolute = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+// files.  If `by_alloc_size` is set, `min_size` and `max_size` apply to the space allocated on
+// disk rather than the logical size.  Anything on one of the `excluded_devices`, or whose name
+// matches one of the `excludes` patterns, is skipped.  If there are any `includes` patterns, only
+// files whose names match one of them are considered.  If there's a `limit`, we stop after
+// yielding that many files, and if there's a `sample_rate`, we only consider a random sample of
+// the files.  With a `snapshot_start`, files modified (or whose inode changed) after it are
+// skipped, so that the result reflects the tree as it was at that instant.  With a
+// `modified_cutoff`, files modified after it are skipped too, and with an `owner`, files belonging
+// to anyone else.  With `find_broken_symlinks`, symlinks whose targets don't exist are noted (see
+// `broken_symlinks`).  Anything we can't read is noted in `errors`.
+//
+// Every path we find is `root` joined with the names of the directories and file below it, so
+// the paths share whatever form `root` has (canonical or not; see `PathStyle`).  That's so even
+// when we follow symlinks, since the path goes through the symlink rather than its target.  The
+// target is what gets checked, so a file reached through a symlink as well as directly counts as
+// one file with two paths, like a hard link, and a symlink to a directory we're already in isn't
+// followed.  With `strict_containment`, a file whose real path is outside the root (reached
+// through a symlink to somewhere else) is skipped, so that nothing acting on the report can
+// touch unrelated data.
+//
+// Some filesystems (FAT, and some FUSE mounts) make up inode numbers, which may be zero or shared
+// by unrelated files.  On those, and everywhere with `no_inode`, files are identified by path:
+// hard links aren't recognised as such (so each is compared as a separate file), and directories
+// are only recognised as already visited if we reach them by the same real path.
+pub fn group_by_inode(root: &Path, options: &Options, errors: &ErrorLog) -> GroupByInodeIter {
     GroupByInodeIter {
-        min_size,
+        min_size: options.min_size,
+        max_size: options.max_size,
+        exclude_empty_files: options.exclude_empty_files,
+        ignored_len: options.ignore_header + options.ignore_trailer,
+        by_alloc_size: options.by_alloc_size,
+        excluded_devices: options.excluded_devices.clone(),
+        unreliable_inode_devices: unreliable_inode_devices(),
+        no_inode: options.no_inode,
+        symlinks: options.symlinks,
+        find_broken_symlinks: options.find_broken_symlinks,
+        canonical_root: fs::canonicalize(root).ok(),
+        strict_containment: options.strict_containment,
+        excludes: options.excludes.clone(),
+        includes: options.includes.clone(),
+        limit: options.limit,
+        snapshot_start: options.snapshot_start,
+        modified_cutoff: options.modified_cutoff,
+        owner: options.owner,
+        sampler: options.sample_rate.map(|rate| {
+            Sampler::new(
+                rate,
+                options
+                    .shuffle_seed
+                    .expect("Failed to find a seed despite --sample-rate"),
+            )
+        }),
+        yielded: 0,
         file_queue: Vec::new(),
-        dir_queue: vec![root_absolute],
-        seen_dirs: HashSet::new(),
+        dir_queue: vec![QueuedDir {
+            path: root.to_path_buf(),
+            ancestors: fs::metadata(root).ok().map(|metadata| {
+                Rc::new(AncestorDir {
+                    device: metadata.dev(),
+                    inode: metadata.ino(),
+                    parent: None,
+                })
+            }),
+        }],
+        seen_dir_paths: HashSet::from([real_path(root)]),
+        stats: TraversalStats::default(),
+        errors: errors.clone(),
+    }
+}
+
+// Filesystem types known not to have stable, unique inode numbers.
+const UNRELIABLE_INODE_FS_TYPES: [&str; 5] = ["vfat", "msdos", "exfat", "fuse", "fuseblk"];
+
+// The devices of mounted filesystems whose inode numbers we shouldn't trust, as listed in Linux's
+// `/proc/self/mountinfo`.  Elsewhere we can't tell, and rely on spotting zero inode numbers.
+fn unreliable_inode_devices() -> HashSet<u64> {
+    let Ok(mountinfo) = fs::read_to_string("/proc/self/mountinfo") else {
+        return HashSet::new();
+    };
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            // The third field is the device as "major:minor", and the filesystem type follows the
+            // "-" separator after the optional fields.
+            let mut fields = line.split(' ');
+            let (major, minor) = fields.nth(2)?.split_once(':')?;
+            let fs_type = fields.skip_while(|&f| f != "-").nth(1)?;
+            if !(UNRELIABLE_INODE_FS_TYPES.contains(&fs_type) || fs_type.starts_with("fuse.")) {
+                return None;
+            }
+            Some(make_dev(major.parse().ok()?, minor.parse().ok()?))
+        })
+        .collect()
+}
+
+// Combine major and minor device numbers into a `st_dev`, as glibc's `makedev` does.
+fn make_dev(major: u64, minor: u64) -> u64 {
+    ((major & 0xfff) << 8) | ((major & !0xfff) << 32) | (minor & 0xff) | ((minor & !0xff) << 12)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fake_file, options, Mount, TempDir};
+    use std::os::unix::fs::{chown, symlink};
+    use std::time::{Duration, SystemTime};
+
+    // The names of the files found below `root` with these options, relative to it, in order.
+    // Unless the options say otherwise, files of any (non-zero) size are found.
+    fn names_found(root: &Path, args: &[&str]) -> Vec<PathBuf> {
+        let min_size: &[&str] = if args.contains(&"--min-size") {
+            &[]
+        } else {
+            &["--min-size", "1"]
+        };
+        let options = options(&[args, min_size, &[root.to_str().unwrap()]].concat());
+        let mut names: Vec<PathBuf> = group_by_inode(root, &options, &ErrorLog::default())
+            .flat_map(|df| df.paths)
+            .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn nothing_is_found_on_an_excluded_device() {
+        let dir = TempDir::new();
+        dir.write("a", "x");
+        dir.write("sub/b", "x");
+        let this_device = dir.path().to_str().unwrap();
+        assert!(names_found(dir.path(), &["--exclude-device", this_device]).is_empty());
+        // Excluding some other device (or several) leaves everything here alone.
+        let others = ["--exclude-device", "/proc", "--exclude-device", "/dev"];
+        assert_eq!(
+            names_found(dir.path(), &others),
+            ["a", "sub/b"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn no_more_than_limit_files_are_considered() {
+        let dir = TempDir::new();
+        for name in ["a", "b", "sub/c", "sub/d", "sub/deeper/e"] {
+            dir.write(name, "x");
+        }
+        for limit in 1..7 {
+            let found = names_found(dir.path(), &["--limit", &limit.to_string()]);
+            assert_eq!(found.len(), limit.min(5), "--limit {}", limit);
+        }
+        assert_eq!(names_found(dir.path(), &[]).len(), 5);
+    }
+
+    #[test]
+    fn the_same_shuffle_seed_samples_the_same_files() {
+        let dir = TempDir::new();
+        for i in 0..100 {
+            dir.write(&format!("sub{}/file{}", i % 7, i), "x");
+        }
+        let sampled = |seed: &str| {
+            names_found(
+                dir.path(),
+                &["--sample-rate", "0.5", "--shuffle-seed", seed],
+            )
+        };
+        let sample = sampled("42");
+        assert_eq!(sample, sampled("42"));
+        assert_ne!(sample, sampled("43"));
+        assert!((25..75).contains(&sample.len()), "{}", sample.len());
+    }
+
+    #[test]
+    fn files_created_or_changed_after_a_snapshot_began_are_left_out() {
+        let dir = TempDir::new();
+        dir.write("old", "x");
+        dir.write("changed", "x");
+        // File times are only as fine as the kernel's clock tick, which may be several ms.
+        let pause = || std::thread::sleep(std::time::Duration::from_millis(50));
+        pause();
+        let options = options(&[
+            "--snapshot",
+            "--min-size",
+            "1",
+            dir.path().to_str().unwrap(),
+        ]);
+        pause();
+        dir.write("new", "x");
+        dir.write("changed", "y");
+
+        let mut traversal = group_by_inode(dir.path(), &options, &ErrorLog::default());
+        let found: Vec<PathBuf> = (&mut traversal).flat_map(|df| df.paths).collect();
+        assert_eq!(found, [dir.path().join("old")]);
+        assert_eq!(traversal.stats().files_too_new, 2);
+    }
+
+    #[test]
+    fn empty_files_are_excluded_unless_asked_for_and_min_size_allows() {
+        let dir = TempDir::new();
+        dir.write("empty1", "");
+        dir.write("empty2", "");
+        dir.write("full", "x");
+        let found = |min_size, exclude_empty| {
+            let exclude_empty = format!("--exclude-empty-files={}", exclude_empty);
+            names_found(dir.path(), &["--min-size", min_size, &exclude_empty])
+        };
+        let all = ["empty1", "empty2", "full"].map(PathBuf::from);
+        assert_eq!(found("0", true), [PathBuf::from("full")]);
+        assert_eq!(found("0", false), all);
+        assert_eq!(found("1", true), [PathBuf::from("full")]);
+        assert_eq!(found("1", false), [PathBuf::from("full")]);
+        // Empty files are left out by default, whatever the minimum size.
+        assert_eq!(
+            names_found(dir.path(), &["--min-size", "0"]),
+            [PathBuf::from("full")]
+        );
+    }
+
+    #[test]
+    fn files_without_an_inode_number_are_told_apart_by_path() {
+        assert_ne!(
+            fake_file(&["a"], 1, 0).identity(),
+            fake_file(&["b"], 1, 0).identity()
+        );
+        assert_eq!(
+            fake_file(&["a"], 1, 0).identity(),
+            FileIdentity::Path(PathBuf::from("a"))
+        );
+        // With an inode number, two paths can be the same file.
+        assert_eq!(
+            fake_file(&["a"], 1, 7).identity(),
+            fake_file(&["b"], 1, 7).identity()
+        );
+    }
+
+    #[test]
+    fn no_inode_makes_hard_links_separate_files() {
+        let dir = TempDir::new();
+        let a = dir.write("a", "x");
+        fs::hard_link(&a, dir.path().join("b")).unwrap();
+        let identities = |args: &[&str]| {
+            let options =
+                options(&[args, &["--min-size", "1", dir.path().to_str().unwrap()]].concat());
+            group_by_inode(dir.path(), &options, &ErrorLog::default())
+                .map(|df| df.identity())
+                .collect::<HashSet<_>>()
+                .len()
+        };
+        assert_eq!(identities(&[]), 1);
+        assert_eq!(identities(&["--no-inode"]), 2);
+    }
+
+    #[test]
+    fn within_root_follows_only_symlinks_that_stay_inside() {
+        let dir = TempDir::new();
+        let outside = TempDir::new();
+        dir.write("a", "x");
+        dir.write("sub/b", "x");
+        let c = outside.write("c", "x");
+        symlink("sub", dir.path().join("dir_in")).unwrap();
+        symlink("sub/b", dir.path().join("file_in")).unwrap();
+        symlink(outside.path(), dir.path().join("dir_out")).unwrap();
+        symlink(&c, dir.path().join("file_out")).unwrap();
+        // A symlink that leads back in by way of outside still stays inside.
+        symlink(dir.path().join("sub"), outside.path().join("back")).unwrap();
+        symlink(outside.path().join("back"), dir.path().join("roundabout")).unwrap();
+
+        let found = |policy| names_found(dir.path(), &["--symlinks", policy]);
+        assert_eq!(found("skip"), ["a", "sub/b"].map(PathBuf::from));
+        assert_eq!(
+            found("within-root"),
+            ["a", "dir_in/b", "file_in", "roundabout/b", "sub/b"].map(PathBuf::from)
+        );
+        assert_eq!(
+            found("follow"),
+            [
+                "a",
+                "dir_in/b",
+                "dir_out/back/b",
+                "dir_out/c",
+                "file_in",
+                "file_out",
+                "roundabout/b",
+                "sub/b"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn strict_containment_leaves_out_files_whose_real_paths_are_outside() {
+        let dir = TempDir::new();
+        let outside = TempDir::new();
+        dir.write("a", "x");
+        outside.write("c", "x");
+        outside.write("sub/d", "x");
+        symlink(outside.path().join("c"), dir.path().join("file_out")).unwrap();
+        symlink(outside.path().join("sub"), dir.path().join("dir_out")).unwrap();
+        symlink(dir.path().join("a"), outside.path().join("back")).unwrap();
+        symlink(outside.path().join("back"), dir.path().join("roundabout")).unwrap();
+
+        let args = ["--symlinks", "follow"];
+        assert_eq!(
+            names_found(dir.path(), &args),
+            ["a", "dir_out/d", "file_out", "roundabout"].map(PathBuf::from)
+        );
+        assert_eq!(
+            names_found(dir.path(), &[&args[..], &["--strict-containment"]].concat()),
+            ["a", "roundabout"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn a_queued_directory_that_vanishes_is_noted_but_an_empty_one_is_not() {
+        let dir = TempDir::new();
+        dir.write("a", "x");
+        dir.write("gone/b", "x");
+        dir.write("kept/c", "x");
+        fs::create_dir(dir.path().join("empty")).unwrap();
+        let errors = ErrorLog::default();
+        let mut files = group_by_inode(dir.path(), &options(&["--min-size", "1", "/"]), &errors);
+
+        // Reading the root queues its directories, which are read only once its files are done.
+        assert!(files.next().unwrap().paths[0].ends_with("a"));
+        fs::remove_dir_all(dir.path().join("gone")).unwrap();
+        let rest: Vec<PathBuf> = files.flat_map(|df| df.paths).collect();
+        assert_eq!(rest, [dir.path().join("kept/c")]);
+        let errors = errors.take();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, dir.path().join("gone"));
+        assert_eq!(errors[0].message, "disappeared during scan");
+    }
+
+    // There's only one root, but it may also be reachable from inside the tree, under another
+    // spelling: it counts as seen from the start, so it isn't scanned again.
+    #[test]
+    fn the_root_under_another_spelling_is_not_scanned_twice() {
+        let dir = TempDir::new();
+        dir.write("a", "x");
+        dir.write("sub/b", "x");
+        symlink(dir.path(), dir.path().join("sub/again")).unwrap();
+        let root = dir.path().join("sub/..");
+        for args in [&[][..], &["--no-inode"]] {
+            let args = [args, &["--symlinks", "follow", "--paths", "as-given"]].concat();
+            let options =
+                options(&[&args[..], &["--min-size", "1", root.to_str().unwrap()]].concat());
+            let files: Vec<DedupFile> =
+                group_by_inode(&root, &options, &ErrorLog::default()).collect();
+            let mut paths: Vec<PathBuf> = files.iter().flat_map(|df| df.paths.clone()).collect();
+            paths.sort();
+            assert_eq!(paths, [root.join("a"), root.join("sub/b")], "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn min_age_leaves_out_files_modified_since() {
+        let dir = TempDir::new();
+        dir.write("new", "x");
+        let old = dir.write("old", "x");
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 3600);
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .and_then(|f| f.set_modified(two_hours_ago))
+            .unwrap();
+        assert_eq!(
+            names_found(dir.path(), &[]),
+            ["new", "old"].map(PathBuf::from)
+        );
+        assert_eq!(
+            names_found(dir.path(), &["--min-age", "1h"]),
+            [PathBuf::from("old")]
+        );
+        assert!(names_found(dir.path(), &["--min-age", "3h"]).is_empty());
+    }
+
+    #[test]
+    fn owned_only_leaves_out_other_users_files() {
+        let dir = TempDir::new();
+        dir.write("mine", "x");
+        let theirs = dir.write("theirs", "x");
+        // Only root can give a file away, so that's who this needs to run as.
+        if chown(&theirs, Some(65534), Some(65534)).is_err() {
+            eprintln!("Skipping test: can't change the owner of test files");
+            return;
+        }
+        assert_eq!(
+            names_found(dir.path(), &[]),
+            ["mine", "theirs"].map(PathBuf::from)
+        );
+        assert_eq!(
+            names_found(dir.path(), &["--owned-only"]),
+            [PathBuf::from("mine")]
+        );
+    }
+
+    #[test]
+    fn no_inode_still_stops_at_symlink_loops() {
+        let dir = TempDir::new();
+        dir.write("a", "x");
+        dir.write("sub/b", "x");
+        symlink("..", dir.path().join("sub/loop")).unwrap();
+        for args in [&[][..], &["--no-inode"]] {
+            let found = names_found(dir.path(), &[args, &["--symlinks", "follow"]].concat());
+            assert_eq!(found, ["a", "sub/b"].map(PathBuf::from), "{:?}", args);
+        }
+    }
+
+    // A bind mount is the usual way to bring another filesystem into a tree, and so the case
+    // `--exclude-device` is for.  This one bind-mounts a directory of /dev/shm (a tmpfs, on most
+    // Linux systems) into the tree, so it needs root: run it with
+    // `sudo -E cargo test -- --ignored`.  To try the same by hand:
+    //
+    //     mkdir -p tree/mnt /dev/shm/elsewhere && mount --bind /dev/shm/elsewhere tree/mnt
+    //     find_dupes --exclude-device tree/mnt tree
+    #[test]
+    #[ignore = "needs root, to bind-mount a directory of /dev/shm"]
+    fn exclude_device_skips_a_bind_mount() {
+        let dir = TempDir::new();
+        dir.write("a", "x");
+        let source = PathBuf::from(format!("/dev/shm/find_dupes-test-{}", std::process::id()));
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("b"), "x").unwrap();
+        let mount_point = dir.path().join("mnt");
+        fs::create_dir(&mount_point).unwrap();
+        let mount = Mount::bind(&source, &mount_point).expect("Failed to bind-mount");
+
+        assert_eq!(
+            names_found(dir.path(), &[]),
+            ["a", "mnt/b"].map(PathBuf::from)
+        );
+        let excluded = mount.path().to_str().unwrap();
+        assert_eq!(
+            names_found(dir.path(), &["--exclude-device", excluded]),
+            [PathBuf::from("a")]
+        );
+        drop(mount);
+        fs::remove_dir_all(&source).unwrap();
     }
 }
@@ -0,0 +1,257 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::deadline::past_deadline;
+use crate::errors::{ErrorLog, ScanError};
+use crate::group_by_content::CompareOptions;
+use crate::open_files::open_limited;
+use crate::options::Options;
+use crate::progress::Phase;
+use crate::DedupFile;
+
+const BUFFER_LEN: usize = 64 * 1024; // 64 KiB
+
+// Group a list of files by their content after normalising away differences in line endings
+// (CRLF vs LF) and trailing whitespace on each line.  Such files usually differ in size, so
+// unlike `group_by_content` this doesn't expect the candidates to have been grouped by size.
+//
+// Instead, we read each file once to find the length and a (non-cryptographic) hash of its
+// normalised content, and bucket by those.  Since the hash is only used in memory we don't need
+// it to be stable or collision-resistant: each bucket is then compared properly, in the same manner
+// as `group_by_content::regroup`.  Anything we can't read is noted in `errors`.
+pub fn group_by_normalised_content(
+    files: Vec<DedupFile>,
+    options: &Options,
+    errors: &ErrorLog,
+) -> Vec<Vec<DedupFile>> {
+    let options = CompareOptions::new(options);
+    let mut buckets: HashMap<(u64, u64), Vec<DedupFile>> = HashMap::new();
+    for f in files {
+        if past_deadline() {
+            break;
+        }
+        match normalised_digest(&f.paths[0], options) {
+            Ok(key) => buckets.entry(key).or_default().push(f),
+            Err(e) => errors.record(ScanError::new(&f.paths[0], Phase::Compare, &e)),
+        }
+    }
+
+    let mut groups: Vec<Vec<DedupFile>> = Vec::new();
+    for bucket in buckets.into_values().filter(|b| b.len() > 1) {
+        if past_deadline() {
+            break;
+        }
+        groups.append(&mut regroup_normalised(bucket, options, errors));
+    }
+    groups
+}
+
+fn regroup_normalised(
+    mut candidates: Vec<DedupFile>,
+    options: CompareOptions,
+    errors: &ErrorLog,
+) -> Vec<Vec<DedupFile>> {
+    let mut groups: Vec<Vec<DedupFile>> = Vec::new();
+
+    'candidate: while let Some(candidate) = candidates.pop() {
+        for group in &mut groups {
+            match compare_normalised(&candidate.paths[0], &group[0].paths[0], options) {
+                Ok(true) => {
+                    group.push(candidate);
+                    continue 'candidate;
+                }
+                Ok(false) => {}
+                Err(e) => errors.record(e),
+            }
+        }
+        groups.push(vec![candidate]);
+    }
+
+    groups.retain(|g| g.len() > 1);
+    groups
+}
+
+// The length and hash of a file's normalised content.
+fn normalised_digest(path: &Path, options: CompareOptions) -> io::Result<(u64, u64)> {
+    let mut src = NormalisingReader::new(open_limited(path)?, options);
+    let mut buf = vec![0; BUFFER_LEN];
+    let mut len: u64 = 0;
+    let mut hasher = DefaultHasher::new();
+
+    loop {
+        let read_count = src.read(&mut buf)?;
+        if read_count == 0 {
+            break;
+        }
+        len += read_count as u64;
+        hasher.write(&buf[..read_count]);
+    }
+
+    Ok((len, hasher.finish()))
+}
+
+// Compare the normalised content of two files.
+fn compare_normalised(
+    path1: &Path,
+    path2: &Path,
+    options: CompareOptions,
+) -> Result<bool, ScanError> {
+    let open = |path| {
+        open_limited(path)
+            .map(|f| NormalisingReader::new(f, options))
+            .map_err(|e| ScanError::new(path, Phase::Compare, &e))
+    };
+    let mut src1 = open(path1)?;
+    let mut src2 = open(path2)?;
+    let mut buf1 = vec![0; BUFFER_LEN];
+    let mut buf2 = vec![0; BUFFER_LEN];
+
+    loop {
+        let read_count1 = fill_buf(&mut src1, &mut buf1)
+            .map_err(|e| ScanError::new(path1, Phase::Compare, &e))?;
+        let read_count2 = fill_buf(&mut src2, &mut buf2)
+            .map_err(|e| ScanError::new(path2, Phase::Compare, &e))?;
+
+        if read_count1 != read_count2 || buf1[..read_count1] != buf2[..read_count2] {
+            return Ok(false);
+        }
+
+        if read_count1 != BUFFER_LEN {
+            break;
+        }
+    }
+
+    Ok(true)
+}
+
+// Read until the buffer is full or we reach the end of the source.  The normalised stream is
+// ragged (whitespace is held back or dropped), so a single `read` may come up short.
+fn fill_buf(src: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let read_count = src.read(&mut buf[filled..])?;
+        if read_count == 0 {
+            break;
+        }
+        filled += read_count;
+    }
+    Ok(filled)
+}
+
+// Wraps a reader, converting CRLF line endings to LF and stripping trailing spaces and tabs from
+// each line (including the last).
+struct NormalisingReader<R: Read> {
+    inner: R,
+    options: CompareOptions,
+    // Whitespace we've seen but can't yet emit, since it might turn out to be trailing.
+    pending: Vec<u8>,
+    output: Vec<u8>,
+    output_pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> NormalisingReader<R> {
+    fn new(inner: R, options: CompareOptions) -> NormalisingReader<R> {
+        NormalisingReader {
+            inner,
+            options,
+            pending: Vec::new(),
+            output: Vec::new(),
+            output_pos: 0,
+            eof: false,
+        }
+    }
+
+    // Read and normalise another chunk of the inner reader into the output buffer.
+    fn refill(&mut self) -> io::Result<()> {
+        let mut chunk = [0; BUFFER_LEN];
+        let read_count = self.inner.read(&mut chunk)?;
+        if read_count == 0 {
+            // Anything still pending is trailing whitespace on the last line.
+            self.eof = true;
+            return Ok(());
+        }
+
+        self.output.clear();
+        self.output_pos = 0;
+        for &b in &chunk[..read_count] {
+            match b {
+                b' ' | b'\t' | b'\r' => self.pending.push(b),
+                b'\n' => {
+                    self.pending.clear();
+                    self.output.push(b);
+                }
+                _ => {
+                    self.output.append(&mut self.pending);
+                    self.output.push(b);
+                }
+            }
+        }
+        if self.options.ignore_case {
+            self.output.make_ascii_lowercase();
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for NormalisingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_pos == self.output.len() && !self.eof {
+            self.refill()?;
+        }
+        let available = &self.output[self.output_pos..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.output_pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file_record, options, TempDir};
+
+    fn normalise(content: &[u8]) -> Vec<u8> {
+        let mut normalised = Vec::new();
+        NormalisingReader::new(content, CompareOptions::default())
+            .read_to_end(&mut normalised)
+            .unwrap();
+        normalised
+    }
+
+    #[test]
+    fn crlf_and_trailing_whitespace_are_normalised_away() {
+        assert_eq!(normalise(b"one\r\ntwo \t\r\nthree  "), b"one\ntwo\nthree");
+        assert_eq!(normalise(b"one\ntwo\n"), b"one\ntwo\n");
+        // Whitespace within a line stays.
+        assert_eq!(normalise(b"a \r b\r\n"), b"a \r b\n");
+    }
+
+    #[test]
+    fn crlf_and_lf_copies_are_grouped_and_others_are_not() {
+        let dir = TempDir::new();
+        let files = [
+            dir.write("crlf.txt", "one\r\ntwo\r\n"),
+            dir.write("lf.txt", "one\ntwo\n"),
+            dir.write("trailing.txt", "one  \ntwo\t\n"),
+            dir.write("other.txt", "one\r\ntoo\r\n"),
+        ];
+        let options = options(&["--ignore-whitespace", "."]);
+        let groups = group_by_normalised_content(
+            files.iter().map(|path| file_record(path)).collect(),
+            &options,
+            &ErrorLog::default(),
+        );
+        assert_eq!(groups.len(), 1);
+        let mut grouped: Vec<_> = groups[0].iter().map(|df| df.paths[0].clone()).collect();
+        grouped.sort();
+        assert_eq!(
+            grouped,
+            [files[0].clone(), files[1].clone(), files[2].clone()]
+        );
+    }
+}
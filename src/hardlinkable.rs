@@ -0,0 +1,148 @@
+// Which groups of duplicates we could actually replace with hard links.  That means every file is
+// on the same device (a hard link can't cross filesystems), and we have permission to write to
+// every file.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::process;
+
+use crate::DedupFile;
+
+// Who we're running as, for working out which files we may write to.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    uid: u32,
+    gids: HashSet<u32>,
+}
+
+impl Credentials {
+    // Our effective user and groups.  On Linux we read them from `/proc`; elsewhere, std gives us
+    // no way to ask, so we create a temporary file and see who owns it.  That only tells us our
+    // effective group, not any supplementary ones, so files writable only through one of those
+    // are taken to be unwritable.
+    pub fn current() -> io::Result<Credentials> {
+        match fs::read_to_string("/proc/self/status") {
+            Ok(status) => Ok(credentials_from_status(&status)),
+            Err(_) => credentials_from_probe(),
+        }
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    // True iff the permission bits of a file let us write to it.
+    pub fn can_write(&self, df: &DedupFile) -> bool {
+        if self.uid == 0 {
+            return true;
+        }
+        if df.uid == self.uid {
+            df.mode & 0o200 != 0
+        } else if self.gids.contains(&df.gid) {
+            df.mode & 0o020 != 0
+        } else {
+            df.mode & 0o002 != 0
+        }
+    }
+}
+
+// The effective user and group, and the supplementary groups, from `/proc/self/status`.  Its
+// `Uid` and `Gid` lines list the real, effective, saved and filesystem IDs, in that order.
+fn credentials_from_status(status: &str) -> Credentials {
+    let mut uid = u32::MAX;
+    let mut gids = HashSet::new();
+    for line in status.lines() {
+        let Some((key, values)) = line.split_once(':') else {
+            continue;
+        };
+        let mut ids = values.split_whitespace().filter_map(|id| id.parse().ok());
+        match key {
+            "Uid" => uid = ids.nth(1).unwrap_or(u32::MAX),
+            "Gid" => gids.extend(ids.nth(1)),
+            "Groups" => gids.extend(ids),
+            _ => {}
+        }
+    }
+    Credentials { uid, gids }
+}
+
+fn credentials_from_probe() -> io::Result<Credentials> {
+    let probe_path = env::temp_dir().join(format!("find_dupes-probe-{}", process::id()));
+    let metadata = File::create(&probe_path)?.metadata();
+    let _ = fs::remove_file(&probe_path);
+    let metadata = metadata?;
+    Ok(Credentials {
+        uid: metadata.uid(),
+        gids: HashSet::from([metadata.gid()]),
+    })
+}
+
+// True iff every file in a group is on the same device, and we may write to all of them.
+pub fn is_hardlinkable(group: &[DedupFile], credentials: &Credentials) -> bool {
+    group
+        .iter()
+        .all(|df| df.device == group[0].device && credentials.can_write(df))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_file;
+
+    fn user(uid: u32, gids: &[u32]) -> Credentials {
+        Credentials {
+            uid,
+            gids: gids.iter().copied().collect(),
+        }
+    }
+
+    fn owned_file(inode: u64, uid: u32, gid: u32, mode: u32) -> DedupFile {
+        let mut df = fake_file(&["/f"], 10, inode);
+        df.uid = uid;
+        df.gid = gid;
+        df.mode = mode;
+        df
+    }
+
+    #[test]
+    fn groups_spanning_devices_are_not_hardlinkable() {
+        let root = user(0, &[0]);
+        let mut elsewhere = fake_file(&["/other/b"], 10, 2);
+        elsewhere.device = 2;
+        let same_device = [fake_file(&["/a"], 10, 1), fake_file(&["/b"], 10, 2)];
+        assert!(is_hardlinkable(&same_device, &root));
+        assert!(!is_hardlinkable(
+            &[fake_file(&["/a"], 10, 1), elsewhere],
+            &root
+        ));
+    }
+
+    #[test]
+    fn every_file_must_be_writable_by_owner_group_or_anyone() {
+        let me = user(1000, &[100, 200]);
+        assert!(me.can_write(&owned_file(1, 1000, 0, 0o644)));
+        assert!(!me.can_write(&owned_file(2, 1000, 100, 0o444)));
+        assert!(me.can_write(&owned_file(3, 0, 200, 0o664)));
+        assert!(!me.can_write(&owned_file(4, 0, 300, 0o664)));
+        assert!(me.can_write(&owned_file(5, 0, 300, 0o666)));
+        // The owner's bits apply to the owner, even if the group's or anyone's would allow more.
+        assert!(!me.can_write(&owned_file(6, 1000, 100, 0o466)));
+        assert!(user(0, &[0]).can_write(&owned_file(7, 1000, 100, 0o444)));
+
+        let group = [owned_file(1, 1000, 0, 0o644), owned_file(4, 0, 300, 0o664)];
+        assert!(!is_hardlinkable(&group, &me));
+        assert!(is_hardlinkable(&group[..1], &me));
+    }
+
+    #[test]
+    fn credentials_are_the_effective_ids_and_supplementary_groups() {
+        let status = "Name:\tfind_dupes\nUid:\t1000\t1001\t1000\t1001\n\
+                      Gid:\t100\t101\t100\t101\nGroups:\t10 20 \n";
+        let credentials = credentials_from_status(status);
+        assert_eq!(credentials.uid(), 1001);
+        assert_eq!(credentials.gids, HashSet::from([101, 10, 20]));
+    }
+}
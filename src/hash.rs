@@ -0,0 +1,162 @@
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use crate::blake3::Hasher;
+use crate::open_files::open_limited;
+
+const BUFFER_LEN: usize = 1024 * 1024; // 1 MiB
+
+// Render a digest as lowercase hex, the way `b3sum` and friends do.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The BLAKE3 digest of a file's content, as hex.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    hash_content(&mut open_limited(path)?)
+}
+
+// The BLAKE3 digest of everything we can read from a source, as hex.
+pub fn hash_content(src: &mut impl Read) -> io::Result<String> {
+    let mut buf = vec![0; BUFFER_LEN];
+    let mut hasher = Hasher::new();
+
+    loop {
+        let read_count = src.read(&mut buf)?;
+        if read_count == 0 {
+            break;
+        }
+        hasher.update(&buf[..read_count]);
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+// A 64-bit FNV-1a hash.  It's no use against anyone trying to make collisions, but it has nothing
+// in common with BLAKE3, so it makes a cheap second opinion.  It's also quick to take of a path.
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// The BLAKE3 digest (as hex) and the FNV-1a hash of everything we can read from a source, in a
+// single pass, for `--double-hash`.  With `ignore_case`, ASCII letters are hashed as lowercase.
+pub fn hash_content_twice(src: &mut impl Read, ignore_case: bool) -> io::Result<(String, u64)> {
+    let mut buf = vec![0; BUFFER_LEN];
+    let mut blake3 = Hasher::new();
+    let mut fnv = Fnv1a::new();
+
+    loop {
+        let read_count = match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read_count) => read_count,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let data = &mut buf[..read_count];
+        if ignore_case {
+            data.make_ascii_lowercase();
+        }
+        blake3.update(data);
+        fnv.update(data);
+    }
+
+    Ok((to_hex(&blake3.finalize()), fnv.finish()))
+}
+
+// The BLAKE3 digests of each `chunk_size` bytes of a file's content, as hex.  The last chunk may
+// be shorter, and an empty file has no chunks at all.
+pub fn hash_file_chunks(path: &Path, chunk_size: u64) -> io::Result<Vec<String>> {
+    let mut src = open_limited(path)?;
+    let mut buf = vec![0; BUFFER_LEN];
+    let mut digests = Vec::new();
+
+    loop {
+        let mut hasher = Hasher::new();
+        let mut chunk = (&mut src).take(chunk_size);
+        let mut chunk_len = 0;
+        loop {
+            let read_count = chunk.read(&mut buf)?;
+            if read_count == 0 {
+                break;
+            }
+            hasher.update(&buf[..read_count]);
+            chunk_len += read_count;
+        }
+        if chunk_len == 0 {
+            return Ok(digests);
+        }
+        digests.push(to_hex(&hasher.finalize()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::io::Cursor;
+
+    #[test]
+    fn digests_match_the_published_ones() {
+        let digest = |data: &[u8]| hash_content(&mut Cursor::new(data)).unwrap();
+        assert_eq!(
+            digest(b""),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(
+            digest(b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn fnv1a_gives_the_reference_hashes() {
+        let fnv1a = |data: &[u8]| {
+            let mut hash = Fnv1a::new();
+            hash.update(data);
+            hash.finish()
+        };
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+        // Hashing in pieces is the same as all at once.
+        let mut pieces = Fnv1a::new();
+        pieces.update(b"foo");
+        pieces.update(b"bar");
+        assert_eq!(pieces.finish(), fnv1a(b"foobar"));
+    }
+
+    #[test]
+    fn chunks_cover_the_file_with_only_the_last_one_short() {
+        let dir = TempDir::new();
+        let chunk_size = 1000;
+        for size in [0, 1, 999, 1000, 1001, 3005] {
+            let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            let path = dir.write(&format!("file-{}", size), &content);
+            let digests = hash_file_chunks(&path, chunk_size).unwrap();
+            assert_eq!(
+                digests.len() as u64,
+                (size as u64).div_ceil(chunk_size),
+                "{}",
+                size
+            );
+            for (digest, chunk) in digests.iter().zip(content.chunks(chunk_size as usize)) {
+                assert_eq!(*digest, hash_content(&mut Cursor::new(chunk)).unwrap());
+            }
+        }
+    }
+}
@@ -1,30 +1,164 @@
-use std::io::Write;
+use std::io::{self, Write};
 
+use crate::by_dir::DirListing;
+use crate::group_by_content::Confidence;
+use crate::keep::is_kept;
+use crate::options::Options;
+use crate::summary::format_bytes;
 use crate::DedupFile;
 
-pub fn write_dupes_html(dest: &mut impl Write, dupes: &[Vec<DedupFile>]) {
-    writeln!(dest, "{}", HTML_TOP).unwrap();
-    for group in dupes {
-        dedup_group_to_html_tr(dest, group);
+// Write the report.  If there's a `keep` policy, the file it would keep in each group (and any
+// protected files, which are always kept) is highlighted.
+//
+// Groups we haven't compared byte for byte are noted as such, with how far they were compared:
+// those matched only by digests, only as far as `--compare-limit`, only in the blocks sampled by
+// `--sample-blocks`, or not at all with `--assume-unchanged`.
+//
+// If the groups are in rank order, `group_count` says how many there are, and each is labelled
+// with its rank.  With `--html-detailed`, each file's device, inode number and link count follow
+// its paths, so that existing hard links stand out from copies.
+pub fn write_dupes_html<G: AsRef<[DedupFile]>>(
+    dest: &mut impl Write,
+    dupes: impl IntoIterator<Item = G>,
+    group_count: Option<usize>,
+    options: &Options,
+) -> io::Result<()> {
+    writeln!(dest, "{}", HTML_TOP)?;
+    let size_heading = if options.by_alloc_size {
+        "Allocated size"
+    } else {
+        "Size"
+    };
+    let rank_heading = if group_count.is_some() {
+        "<th>Rank</th>"
+    } else {
+        ""
+    };
+    writeln!(
+        dest,
+        "        <tr>{}<th>Files</th><th>{}</th></tr>",
+        rank_heading, size_heading
+    )?;
+    writeln!(dest, "{}", HTML_TABLE_BODY)?;
+    for (i, group) in dupes.into_iter().enumerate() {
+        let rank = group_count.map(|count| (i + 1, count));
+        dedup_group_to_html_tr(dest, group.as_ref(), rank, options)?;
     }
-    writeln!(dest, "{}", HTML_BOTTOM).unwrap();
+    writeln!(dest, "{}", HTML_BOTTOM)
 }
 
-fn dedup_group_to_html_tr(dest: &mut impl Write, group: &[DedupFile]) {
-    write!(dest, "    <tr><td>").unwrap();
-    for df in group {
+fn dedup_group_to_html_tr(
+    dest: &mut impl Write,
+    group: &[DedupFile],
+    rank: Option<(usize, usize)>,
+    options: &Options,
+) -> io::Result<()> {
+    write!(dest, "    <tr>")?;
+    if let Some((rank, count)) = rank {
+        write!(dest, "<td>Group {} of {}</td>", rank, count)?;
+    }
+    write!(dest, "<td>")?;
+    for (i, df) in group.iter().enumerate() {
+        let class = if options.keep.is_some_and(|policy| is_kept(group, i, policy)) {
+            " class=\"keep\""
+        } else {
+            ""
+        };
         write!(
             dest,
-            "<p><code>{}</code></p>",
+            "<p{}><code>{}</code>{}",
+            class,
             df.paths
                 .iter()
                 .map(|path| path.display().to_string())
                 .collect::<Vec<String>>()
-                .join("</code>, <code>")
+                .join("</code>, <code>"),
+            if df.protected {
+                " <em>(protected)</em>"
+            } else {
+                ""
+            }
+        )?;
+        if options.html_detailed {
+            write!(
+                dest,
+                " <small>(device {}, inode {}, {} {})</small>",
+                df.device,
+                df.inode,
+                df.nlink,
+                if df.nlink == 1 { "link" } else { "links" }
+            )?;
+        }
+        write!(dest, "</p>")?;
+    }
+    let size = if options.by_alloc_size {
+        group[0].alloc_size
+    } else {
+        group[0].size
+    };
+    let note = match Confidence::of(group, options) {
+        Confidence::Bytes => "",
+        Confidence::Hashes => "<p><em>hash-only confirmed</em></p>",
+        Confidence::Prefix => "<p><em>partial match</em></p>",
+        Confidence::Sampled => "<p><em>sampled</em></p>",
+        Confidence::Unconfirmed => "<p><em>unconfirmed</em></p>",
+    };
+    writeln!(
+        dest,
+        "</td><td>{}{}</td></tr>",
+        html_size(size, options),
+        note
+    )
+}
+
+// Write the report reorganised by directory, with a row for each directory holding redundant
+// copies.  Each copy is listed with where the copy we'd keep lives.
+pub fn write_dir_listing_html(
+    dest: &mut impl Write,
+    listing: &DirListing,
+    options: &Options,
+) -> io::Result<()> {
+    writeln!(dest, "{}", HTML_TOP)?;
+    writeln!(
+        dest,
+        "        <tr><th>Directory</th><th>Redundant copies</th><th>Kept copies</th>\
+         <th>Size</th></tr>"
+    )?;
+    writeln!(dest, "{}", HTML_TABLE_BODY)?;
+    for (dir, files) in listing {
+        write!(dest, "    <tr><td><code>{}</code></td>", dir.display())?;
+        for column in [
+            files.iter().map(|f| f.path.display()).collect::<Vec<_>>(),
+            files.iter().map(|f| f.kept.display()).collect(),
+        ] {
+            write!(dest, "<td>")?;
+            for path in column {
+                write!(dest, "<p><code>{}</code></p>", path)?;
+            }
+            write!(dest, "</td>")?;
+        }
+        write!(dest, "<td>")?;
+        for file in files {
+            write!(dest, "<p>{}</p>", html_size(file.size, options))?;
+        }
+        write!(dest, "</td>")?;
+        writeln!(dest, "</tr>")?;
+    }
+    writeln!(dest, "{}", HTML_BOTTOM)
+}
+
+// A size as shown in the report: in bytes, or with `--pretty-sizes` in binary units, with the
+// exact number of bytes as a tooltip.
+fn html_size(bytes: u64, options: &Options) -> String {
+    if options.pretty_sizes {
+        format!(
+            "<span title=\"{} bytes\">{}</span>",
+            bytes,
+            format_bytes(bytes)
         )
-        .unwrap();
+    } else {
+        bytes.to_string()
     }
-    writeln!(dest, "</td><td>{}</td></tr>", group[0].size).unwrap();
 }
 
 const HTML_TOP: &str = "<!doctype html>
@@ -47,16 +181,83 @@ const HTML_TOP: &str = "<!doctype html>
             padding: 0.5em 1em;
             border: 1px solid black;
         }
+
+        .keep {
+            font-weight: bold;
+        }
     </style>
   </head>
   <body>
     <table>
-      <thead>
-        <tr><th>Files</th><th>Size</th></tr>
-      </thead>
+      <thead>";
+
+const HTML_TABLE_BODY: &str = "      </thead>
       <tbody>";
 
 const HTML_BOTTOM: &str = "</tbody>
     </table>
   </body>
 </html>";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fake_file, options};
+
+    fn html_report(groups: &[Vec<DedupFile>], group_count: Option<usize>, args: &[&str]) -> String {
+        let mut dest = Vec::new();
+        write_dupes_html(
+            &mut dest,
+            groups,
+            group_count,
+            &options(&[args, &["/"]].concat()),
+        )
+        .unwrap();
+        String::from_utf8(dest).unwrap()
+    }
+
+    #[test]
+    fn ranked_groups_are_labelled_in_order() {
+        let groups: Vec<Vec<DedupFile>> = (0..3)
+            .map(|i| {
+                vec![
+                    fake_file(&["/x"], 10, 2 * i),
+                    fake_file(&["/y"], 10, 2 * i + 1),
+                ]
+            })
+            .collect();
+        let html = html_report(&groups, Some(3), &["--rank"]);
+        assert!(html.contains("<th>Rank</th>"));
+        let labels: Vec<&str> = html.matches(r"<td>Group ").collect();
+        assert_eq!(labels.len(), 3);
+        let positions: Vec<usize> = (1..=3)
+            .map(|rank| html.find(&format!("<td>Group {} of 3</td>", rank)).unwrap())
+            .collect();
+        assert!(positions.is_sorted());
+        assert!(!html_report(&groups, None, &[]).contains("Rank"));
+    }
+
+    #[test]
+    fn detailed_html_gives_each_file_its_device_inode_and_links() {
+        let groups = vec![vec![
+            fake_file(&["/a", "/a2"], 10, 11),
+            fake_file(&["/b"], 10, 12),
+        ]];
+        let html = html_report(&groups, None, &["--html-detailed"]);
+        assert!(
+            html.contains(
+                "<td><p><code>/a</code>, <code>/a2</code> <small>(device 1, inode 11, 2 links)\
+                 </small></p><p><code>/b</code> <small>(device 1, inode 12, 1 link)</small></p>\
+                 </td><td>10</td>"
+            ),
+            "{}",
+            html
+        );
+        let html = html_report(&groups, None, &[]);
+        assert!(
+            !html.contains("inode") && !html.contains("<small>"),
+            "{}",
+            html
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// Finding files that other processes are writing to, for `--skip-open-files`.
+//
+// Acting on a file while something else is writing it (a database, or a download in progress)
+// could lose data, so such files shouldn't be in a report that will be acted on.  On Linux, each
+// process's open files are listed under `/proc/<pid>/fd`, with the mode each was opened in under
+// `/proc/<pid>/fdinfo`.  We can only see the processes we're allowed to, which for anyone but
+// root means just our own user's.
+
+use std::collections::HashSet;
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+// The device and inode numbers of the regular files that any other process we can see has open
+// for writing (or reading and writing).
+#[cfg(target_os = "linux")]
+pub fn files_open_for_writing() -> HashSet<(u64, u64)> {
+    let mut open = HashSet::new();
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return open;
+    };
+    let own_pid = std::process::id().to_string();
+    for process in processes.flatten() {
+        let pid = process.file_name();
+        let Some(pid) = pid.to_str() else {
+            continue;
+        };
+        if !pid.bytes().all(|b| b.is_ascii_digit()) || pid == own_pid {
+            continue;
+        }
+        let Ok(fds) = fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if !is_open_for_writing(&process.path().join("fdinfo").join(fd.file_name())) {
+                continue;
+            }
+            // The link leads to whatever is open, which may since have been deleted or replaced,
+            // so we go by the inode it leads to rather than its name.
+            if let Ok(metadata) = fs::metadata(fd.path()) {
+                if metadata.is_file() {
+                    open.insert((metadata.dev(), metadata.ino()));
+                }
+            }
+        }
+    }
+    open
+}
+
+// There's no portable way to find out elsewhere, and `--skip-open-files` is refused.
+#[cfg(not(target_os = "linux"))]
+pub fn files_open_for_writing() -> HashSet<(u64, u64)> {
+    HashSet::new()
+}
+
+// True iff an `fdinfo` file says its descriptor was opened for writing, i.e. its access mode (the
+// bottom two bits of its octal `flags`) is `O_WRONLY` or `O_RDWR`.
+#[cfg(target_os = "linux")]
+fn is_open_for_writing(fdinfo: &Path) -> bool {
+    fs::read_to_string(fdinfo)
+        .ok()
+        .and_then(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("flags:"))
+                .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+        })
+        .is_some_and(|flags| flags & 0o3 != 0)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::process::{Child, Command};
+    use std::thread;
+    use std::time::Duration;
+
+    // Have another process open `path` with the shell redirection given (`>>` to write, `<` to
+    // read), and wait until it has.
+    fn hold_open(path: &Path, redirection: &str) -> Child {
+        let ready = path.with_extension("ready");
+        let script = format!("exec 3{}\"$1\"; touch \"$2\"; exec sleep 30", redirection);
+        let child = Command::new("sh")
+            .args(["-c", &script, "sh"])
+            .arg(path)
+            .arg(&ready)
+            .spawn()
+            .unwrap();
+        while !ready.exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        child
+    }
+
+    #[test]
+    fn files_another_process_is_writing_are_found() {
+        let dir = TempDir::new();
+        let id = |path: &Path| {
+            let metadata = fs::metadata(path).unwrap();
+            (metadata.dev(), metadata.ino())
+        };
+        let [written, read, closed] =
+            ["written", "read", "closed"].map(|name| dir.write(name, "x"));
+        let mut holders = [hold_open(&written, ">>"), hold_open(&read, "<")];
+
+        // Files we have open ourselves don't count.
+        let _ours = fs::OpenOptions::new().append(true).open(&closed).unwrap();
+        let open = files_open_for_writing();
+        for holder in &mut holders {
+            holder.kill().unwrap();
+            holder.wait().unwrap();
+        }
+        assert!(open.contains(&id(&written)));
+        assert!(!open.contains(&id(&read)));
+        assert!(!open.contains(&id(&closed)));
+        assert!(!files_open_for_writing().contains(&id(&written)));
+    }
+}
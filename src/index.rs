@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::hash::hash_file;
+use crate::json::{write_json_path, write_json_string};
+use crate::DedupFile;
+
+// An entry in a content-addressed index: the BLAKE3 digest of some content, and every path
+// (including hard links) that has that content.
+pub struct IndexEntry {
+    pub digest: String,
+    pub paths: Vec<PathBuf>,
+}
+
+// Build an index of groups of identical files.  We only hash one file per group, since they're
+// all the same.  Groups we can't read are skipped.
+//
+// Separate groups may still share content (e.g. with `--per-directory`), so entries with the same
+// digest are merged to keep the digests unique.
+pub fn build_index<G: AsRef<[DedupFile]>>(groups: impl IntoIterator<Item = G>) -> Vec<IndexEntry> {
+    let mut entries: Vec<IndexEntry> = Vec::new();
+    let mut entry_by_digest: HashMap<String, usize> = HashMap::new();
+    for group in groups {
+        let group = group.as_ref();
+        let Ok(digest) = hash_file(&group[0].paths[0]) else {
+            continue;
+        };
+        let paths = group.iter().flat_map(|df| df.paths.iter().cloned());
+        match entry_by_digest.get(&digest) {
+            Some(&i) => entries[i].paths.extend(paths),
+            None => {
+                entry_by_digest.insert(digest.clone(), entries.len());
+                entries.push(IndexEntry {
+                    digest,
+                    paths: paths.collect(),
+                });
+            }
+        }
+    }
+    entries
+}
+
+// Write an index as a JSON object mapping each digest to its list of paths.
+pub fn write_index(dest: &mut impl Write, entries: &[IndexEntry]) -> io::Result<()> {
+    write!(dest, "{{")?;
+    for (i, entry) in entries.iter().enumerate() {
+        write!(dest, "{}\n  ", if i == 0 { "" } else { "," })?;
+        write_json_string(dest, &entry.digest)?;
+        write!(dest, ": [")?;
+        for (j, path) in entry.paths.iter().enumerate() {
+            if j > 0 {
+                write!(dest, ", ")?;
+            }
+            write_json_path(dest, path)?;
+        }
+        write!(dest, "]")?;
+    }
+    writeln!(dest, "{}}}", if entries.is_empty() { "" } else { "\n" })?;
+    dest.flush()
+}
@@ -0,0 +1,99 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A token bucket: tokens (bytes) accrue at `rate` per second up to `capacity`, and each read
+// spends as many as it read.  A read may overdraw the bucket (our buffers may well be larger than
+// the whole allowance), in which case the reader sleeps until the debt has been paid off.
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    // Spend `bytes` tokens, returning how long the reader must wait to stay under the rate.
+    fn spend(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+static IO_LIMIT: Mutex<Option<TokenBucket>> = Mutex::new(None);
+
+// Limit the rate at which we read file content to about `bytes_per_sec`, averaged over a second
+// or so.  There's no limit until this is called.
+pub fn set_io_limit(bytes_per_sec: u64) {
+    let rate = bytes_per_sec.max(1) as f64;
+    let mut limit = IO_LIMIT.lock().unwrap_or_else(|e| e.into_inner());
+    *limit = Some(TokenBucket {
+        rate,
+        capacity: rate,
+        tokens: rate,
+        last_refill: Instant::now(),
+    });
+}
+
+// Account for `bytes` just read, sleeping if we're reading too fast.  We don't hold the lock while
+// sleeping: the bucket already knows it's in debt, so anyone else reading in the meantime will
+// wait their turn too.
+pub fn throttle(bytes: usize) {
+    let wait = match IO_LIMIT.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(bucket) => bucket.spend(bytes),
+        None => return,
+    };
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Read `bytes` through a full bucket in chunks, as a reader would, returning how long it took.
+    fn read_through(bucket: &mut TokenBucket, bytes: usize, chunk: usize) -> Duration {
+        let start = Instant::now();
+        for _ in 0..bytes / chunk {
+            thread::sleep(bucket.spend(chunk));
+        }
+        start.elapsed()
+    }
+
+    fn full_bucket(rate: f64) -> TokenBucket {
+        TokenBucket {
+            rate,
+            capacity: rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn reading_past_the_allowance_takes_as_long_as_the_rate_says() {
+        // The first million bytes are free, and another quarter-million take a quarter-second.
+        let elapsed = read_through(&mut full_bucket(1_000_000.0), 1_250_000, 50_000);
+        assert!(elapsed >= Duration::from_millis(240), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn reads_within_the_allowance_do_not_wait() {
+        let mut bucket = full_bucket(1_000_000.0);
+        assert_eq!(bucket.spend(600_000), Duration::ZERO);
+        assert_eq!(bucket.spend(300_000), Duration::ZERO);
+        // A buffer bigger than what's left overdraws the bucket, and the debt is paid off in time.
+        let wait = bucket.spend(300_000);
+        assert!(wait > Duration::from_millis(150) && wait <= Duration::from_millis(200));
+    }
+}
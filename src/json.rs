@@ -0,0 +1,349 @@
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+// Write a string as a JSON string literal, escaping as required by RFC 8259.
+pub fn write_json_string(dest: &mut impl Write, s: &str) -> io::Result<()> {
+    write!(dest, "\"")?;
+    write_json_chars(dest, s)?;
+    write!(dest, "\"")
+}
+
+// Write a path as a JSON string literal.  Unix paths are arbitrary bytes, not necessarily UTF-8,
+// so any byte that isn't part of valid UTF-8 is written as a lone surrogate escape from
+// `\udc80` to `\udcff` (as Python's "surrogateescape" does).  No valid UTF-8 text can produce
+// these, so they clearly mark where the path wasn't text, and `parse_json` turns them back into
+// the original bytes.
+pub fn write_json_path(dest: &mut impl Write, path: &Path) -> io::Result<()> {
+    write!(dest, "\"")?;
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        write_json_chars(dest, chunk.valid())?;
+        for b in chunk.invalid() {
+            write!(dest, "\\u{:04x}", 0xDC00 + u32::from(*b))?;
+        }
+    }
+    write!(dest, "\"")
+}
+
+fn write_json_chars(dest: &mut impl Write, s: &str) -> io::Result<()> {
+    for c in s.chars() {
+        match c {
+            '"' => write!(dest, "\\\"")?,
+            '\\' => write!(dest, "\\\\")?,
+            '\n' => write!(dest, "\\n")?,
+            '\r' => write!(dest, "\\r")?,
+            '\t' => write!(dest, "\\t")?,
+            c if c < ' ' => write!(dest, "\\u{:04x}", c as u32)?,
+            c => write!(dest, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+// A parsed JSON value.  Numbers are kept as their source text, so that callers can parse them as
+// whatever integer type they need without losing precision through a float.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    // A string containing escapes for bytes that aren't valid UTF-8 (see `write_json_path`).
+    RawString(Vec<u8>),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    // Look up a member of an object.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // A string as a path, including any raw bytes that weren't valid UTF-8.
+    pub fn as_path(&self) -> Option<PathBuf> {
+        match self {
+            JsonValue::String(s) => Some(PathBuf::from(s)),
+            JsonValue::RawString(bytes) => Some(PathBuf::from(OsString::from_vec(bytes.clone()))),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            JsonValue::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            JsonValue::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// Parse a complete JSON document.
+pub fn parse_json(s: &str) -> Result<JsonValue> {
+    let mut parser = JsonParser {
+        src: s.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.src.len() {
+        bail!(
+            "Trailing characters after JSON value at byte {}",
+            parser.pos
+        );
+    }
+    Ok(value)
+}
+
+// A straightforward recursive-descent parser for RFC 8259 JSON.
+struct JsonParser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.src.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, b: u8) -> Result<()> {
+        if self.peek() != Some(b) {
+            bail!("Expected {:?} at byte {} of JSON", b as char, self.pos);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expect_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue> {
+        if !self.src[self.pos..].starts_with(literal.as_bytes()) {
+            bail!("Expected {:?} at byte {} of JSON", literal, self.pos);
+        }
+        self.pos += literal.len();
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => {
+                let bytes = self.parse_string_bytes()?;
+                match String::from_utf8(bytes) {
+                    Ok(s) => Ok(JsonValue::String(s)),
+                    Err(e) => Ok(JsonValue::RawString(e.into_bytes())),
+                }
+            }
+            Some(b't') => self.expect_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.expect_literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.expect_literal("null", JsonValue::Null),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            _ => bail!("Unexpected character at byte {} of JSON", self.pos),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                _ => bail!("Expected ',' or '}}' at byte {} of JSON", self.pos),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => bail!("Expected ',' or ']' at byte {} of JSON", self.pos),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.src[start..self.pos])?;
+        if text.parse::<f64>().is_err() {
+            bail!("Bad number {:?} at byte {} of JSON", text, start);
+        }
+        Ok(JsonValue::Number(text.to_owned()))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok());
+        match digits {
+            Some(n) => {
+                self.pos += 4;
+                Ok(n)
+            }
+            None => bail!("Bad \\u escape at byte {} of JSON", self.pos),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        Ok(String::from_utf8(self.parse_string_bytes()?)?)
+    }
+
+    // Parse a string, which may contain raw bytes escaped as lone surrogates.
+    fn parse_string_bytes(&mut self) -> Result<Vec<u8>> {
+        self.expect(b'"')?;
+        let mut bytes: Vec<u8> = Vec::new();
+        loop {
+            let Some(b) = self.peek() else {
+                bail!("Unterminated string in JSON");
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let Some(escape) = self.peek() else {
+                        bail!("Unterminated string in JSON");
+                    };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            // Characters outside the BMP arrive as a UTF-16 surrogate pair.
+                            if (0xD800..0xDC00).contains(&code) {
+                                self.expect(b'\\')?;
+                                self.expect(b'u')?;
+                                let low = self.parse_hex4()?;
+                                code =
+                                    0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00));
+                            } else if (0xDC80..0xDD00).contains(&code) {
+                                bytes.push((code - 0xDC00) as u8);
+                                continue;
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => c,
+                                None => bail!("Bad \\u escape at byte {} of JSON", self.pos),
+                            }
+                        }
+                        _ => bail!("Bad escape at byte {} of JSON", self.pos),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => bytes.push(b),
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn json_path(path: &Path) -> String {
+        let mut dest = Vec::new();
+        write_json_path(&mut dest, path).unwrap();
+        String::from_utf8(dest).unwrap()
+    }
+
+    #[test]
+    fn paths_that_are_not_utf8_are_marked_and_read_back() {
+        let path = Path::new(OsStr::from_bytes(b"/data/caf\xe9 \xff\xfe.txt"));
+        let json = json_path(path);
+        assert_eq!(json, r#""/data/caf\udce9 \udcff\udcfe.txt""#);
+        assert_eq!(parse_json(&json).unwrap().as_path().unwrap(), path);
+    }
+
+    #[test]
+    fn text_paths_are_written_as_text() {
+        let path = Path::new("/data/café \"quoted\"\tand\\slashed");
+        let json = json_path(path);
+        assert_eq!(json, r#""/data/café \"quoted\"\tand\\slashed""#);
+        assert_eq!(parse_json(&json).unwrap().as_path().unwrap(), path);
+    }
+
+    #[test]
+    fn documents_parse_into_values() {
+        let value =
+            parse_json(r#" {"n": 18446744073709551615, "a": [-1, "x"], "t": true} "#).unwrap();
+        assert_eq!(value.get("n").and_then(JsonValue::as_u64), Some(u64::MAX));
+        let array = value.get("a").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(array[0].as_i64(), Some(-1));
+        assert_eq!(array[1].as_str(), Some("x"));
+        assert!(value.get("missing").is_none());
+        assert!(parse_json(r#"{"a": 1} x"#).is_err());
+        assert!(parse_json(r#"{"a": 1"#).is_err());
+    }
+}
@@ -0,0 +1,611 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::by_dir::DirListing;
+use crate::deadline::was_truncated;
+use crate::errors::ErrorLog;
+use crate::group_by_content::Confidence;
+use crate::json::{write_json_path, write_json_string};
+use crate::keep::select_representative;
+use crate::options::Options;
+use crate::summary::{format_bytes, reclaimable_bytes};
+use crate::DedupFile;
+
+// The version of the JSON report's structure, given as `format_version` at the start of every
+// report.  This goes up by one whenever the structure changes so that a consumer written for the
+// old one might misread it (something renamed, removed or changed in type or meaning); merely
+// adding fields doesn't count.
+pub const OUTPUT_FORMAT_VERSION: u64 = 1;
+
+// Write the report as a JSON object.  Its `groups` array holds one object per group of duplicates,
+// with the size of each file in the group (and with `--pretty-sizes`, the same in binary units as
+// `size_human`) and its list of `files`, each of which has one or more `paths` (several if it has
+// hard links) and, unless the file was identified by path alone, the `device` and `inode` its
+// paths share and its number of `links`.  As in the HTML report, a `keep` policy adds the index
+// of the file it would keep, groups matched only by their recorded hashes are marked `hash_only`,
+// and files under a `--protect` directory are marked `protected`.  Groups only compared as far as
+// `--compare-limit` are marked `partial_match`.  Every group has its `confidence`, saying how it
+// was matched (see `Confidence`), and the `reclaimable_bytes` removing its redundant files would
+// free.  If the groups are in rank order, `group_count` says how many there are, and each has its
+// `rank` (starting from 1).  With `--chunk-hashes`, a group also has its `chunk_size` and the
+// `chunk_hashes` of its content.
+//
+// With `--flat-json`, a group has a single `paths` array of every path to every file in it, hard
+// links included, in place of its `files`, and `keep` is the path to keep rather than an index.
+//
+// With `--embed-config`, a `config` object before the groups records the settings that produced
+// the report (see `write_config`).
+//
+// Groups are written as they arrive, so this works in low-memory mode too.  If we ran out of time
+// (with `--max-runtime`) before finding them all, `truncated` says so after the groups.  If there
+// are `errors` they come last (after the groups have been found, and so after all the reading is
+// done), as an `errors` array of paths we couldn't read, in which `phase`, and why.
+pub fn write_dupes_json<G: AsRef<[DedupFile]>>(
+    dest: &mut impl Write,
+    dupes: impl IntoIterator<Item = G>,
+    group_count: Option<usize>,
+    errors: Option<&ErrorLog>,
+    options: &Options,
+) -> io::Result<()> {
+    write!(dest, "{{\"format_version\": {}, ", OUTPUT_FORMAT_VERSION)?;
+    write_config(dest, options)?;
+    write!(dest, "\"groups\": [")?;
+    for (i, group) in dupes.into_iter().enumerate() {
+        write!(dest, "{}\n  ", if i == 0 { "" } else { "," })?;
+        let rank = group_count.map(|_| i + 1);
+        write_group(dest, group.as_ref(), rank, options)?;
+    }
+    write!(dest, "\n]")?;
+    if was_truncated() {
+        write!(dest, ",\n\"truncated\": \"time limit\"")?;
+    }
+    write_errors(dest, errors)?;
+    writeln!(dest, "}}")
+}
+
+// Write the report reorganised by directory, as a JSON object whose `directories` array holds an
+// object for each directory with redundant copies.  Each has its `dir` and its `files`, each of
+// which has its `path`, `size` and the path of the copy we'd keep instead (`kept`).  Any `errors`
+// are listed, and the config embedded, as in `write_dupes_json`.
+pub fn write_dir_listing_json(
+    dest: &mut impl Write,
+    listing: &DirListing,
+    errors: Option<&ErrorLog>,
+    options: &Options,
+) -> io::Result<()> {
+    write!(dest, "{{\"format_version\": {}, ", OUTPUT_FORMAT_VERSION)?;
+    write_config(dest, options)?;
+    write!(dest, "\"directories\": [")?;
+    for (i, (dir, files)) in listing.iter().enumerate() {
+        write!(dest, "{}\n  {{\"dir\": ", if i == 0 { "" } else { "," })?;
+        write_json_path(dest, dir)?;
+        write!(dest, ", \"files\": [")?;
+        for (j, file) in files.iter().enumerate() {
+            write!(dest, "{}{{\"path\": ", if j == 0 { "" } else { ", " })?;
+            write_json_path(dest, &file.path)?;
+            write!(dest, ", \"size\": {}", file.size)?;
+            if options.pretty_sizes {
+                write!(dest, ", \"size_human\": ")?;
+                write_json_string(dest, &format_bytes(file.size))?;
+            }
+            write!(dest, ", \"kept\": ")?;
+            write_json_path(dest, &file.kept)?;
+            write!(dest, "}}")?;
+        }
+        write!(dest, "]}}")?;
+    }
+    write!(dest, "\n]")?;
+    write_errors(dest, errors)?;
+    writeln!(dest, "}}")
+}
+
+// With `--embed-config`, write the settings that decide what's found and how it's reported: the
+// version of find_dupes, where we looked (`path`, or the `dumps` we merged), and the filters,
+// comparison and keep settings in effect, in the same terms as on the command line.  Sizes are in
+// bytes, with any relative ones resolved.  Paths are as given, whatever `--cwd-relative` does to
+// paths in the report.
+fn write_config(dest: &mut impl Write, options: &Options) -> io::Result<()> {
+    if !options.embed_config {
+        return Ok(());
+    }
+    let opt = |n: Option<u64>| n.map_or("null".to_owned(), |n| n.to_string());
+    write!(
+        dest,
+        "\"config\": {{\"version\": \"{}\", ",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    if let Some(target) = &options.target {
+        write!(dest, "\"path\": ")?;
+        write_json_path(dest, target)?;
+    }
+    if let Some(dumps) = &options.merge_indexes {
+        write!(dest, "\"dumps\": ")?;
+        write_json_paths(dest, dumps)?;
+    }
+    write!(
+        dest,
+        ", \"path_style\": \"{}\", \"symlinks\": \"{}\", \
+         \"strict_containment\": {}, \"min_size\": {}, \"max_size\": {}, \
+         \"exclude_empty_files\": {}, \"min_group\": {}, \"by_alloc_size\": {}, \
+         \"no_inode\": {}, \"per_directory\": {}, \"same_extension\": {}, \
+         \"exclude\": ",
+        options.path_style.name(),
+        options.symlinks.name(),
+        options.strict_containment,
+        options.min_size,
+        opt(options.max_size),
+        options.exclude_empty_files,
+        options.min_group,
+        options.by_alloc_size,
+        options.no_inode,
+        options.per_directory,
+        options.same_extension
+    )?;
+    write_json_strings(dest, &options.excludes)?;
+    write!(dest, ", \"include\": ")?;
+    write_json_strings(dest, &options.includes)?;
+    write!(
+        dest,
+        ", \"limit\": {}, \"sample_rate\": {}, \"shuffle_seed\": {}, \"snapshot\": {}, \
+         \"min_age_cutoff\": {}, \"owner\": {}, \"ignore_case_content\": {}, \
+         \"ignore_whitespace\": {}, \"ignore_header\": {}, \"ignore_trailer\": {}, \
+         \"compare_limit\": {}, \"sample_blocks\": {}, \"assume_unchanged\": {}, \
+         \"double_hash\": {}, \"compare_metadata\": {}, \"group_key\": \"{}\", \
+         \"sort_within_groups\": {}, \"keep\": ",
+        opt(options.limit),
+        options
+            .sample_rate
+            .map_or("null".to_owned(), |rate| rate.to_string()),
+        opt(options.shuffle_seed),
+        options.snapshot_start.is_some(),
+        opt(options.modified_cutoff.map(|(secs, _)| secs as u64)),
+        opt(options.owner.map(u64::from)),
+        options.ignore_case_content,
+        options.ignore_whitespace,
+        options.ignore_header,
+        options.ignore_trailer,
+        opt(options.compare_limit),
+        opt(options.sample_blocks),
+        options.assume_unchanged,
+        options.double_hash,
+        options.compare_metadata,
+        options.group_key.name(),
+        options
+            .sort_within_groups
+            .map_or("null".to_owned(), |order| format!("\"{}\"", order.name()))
+    )?;
+    match options.keep {
+        Some(policy) => write!(dest, "\"{}\"", policy.name())?,
+        None => write!(dest, "null")?,
+    }
+    write!(dest, ", \"skip_hashes\": ")?;
+    match &options.skip_hashes {
+        Some(path) => write_json_path(dest, path)?,
+        None => write!(dest, "null")?,
+    }
+    write!(dest, ", \"exclude_signature\": ")?;
+    match &options.exclude_signature {
+        Some(signature) => {
+            let hex: String = signature.iter().map(|b| format!("{:02x}", b)).collect();
+            write!(dest, "\"{}\"", hex)?
+        }
+        None => write!(dest, "null")?,
+    }
+    write!(dest, ", \"protect\": ")?;
+    write_json_paths(dest, &options.protect)?;
+    writeln!(dest, ", \"format\": \"json\"}},")
+}
+
+fn write_json_strings(dest: &mut impl Write, strings: &[String]) -> io::Result<()> {
+    write!(dest, "[")?;
+    for (i, s) in strings.iter().enumerate() {
+        write!(dest, "{}", if i == 0 { "" } else { ", " })?;
+        write_json_string(dest, s)?;
+    }
+    write!(dest, "]")
+}
+
+fn write_json_paths(dest: &mut impl Write, paths: &[PathBuf]) -> io::Result<()> {
+    write!(dest, "[")?;
+    for (i, path) in paths.iter().enumerate() {
+        write!(dest, "{}", if i == 0 { "" } else { ", " })?;
+        write_json_path(dest, path)?;
+    }
+    write!(dest, "]")
+}
+
+fn write_errors(dest: &mut impl Write, errors: Option<&ErrorLog>) -> io::Result<()> {
+    if let Some(errors) = errors {
+        let errors = errors.take();
+        write!(dest, ",\n\"errors\": [")?;
+        for (i, error) in errors.iter().enumerate() {
+            write!(dest, "{}\n  {{\"path\": ", if i == 0 { "" } else { "," })?;
+            write_json_path(dest, &error.path)?;
+            write!(
+                dest,
+                ", \"phase\": \"{}\", \"reason\": ",
+                error.phase.name()
+            )?;
+            write_json_string(dest, &error.message)?;
+            write!(dest, "}}")?;
+        }
+        write!(dest, "{}]", if errors.is_empty() { "" } else { "\n" })?;
+    }
+    Ok(())
+}
+
+fn write_group(
+    dest: &mut impl Write,
+    group: &[DedupFile],
+    rank: Option<usize>,
+    options: &Options,
+) -> io::Result<()> {
+    write!(dest, "{{")?;
+    if let Some(rank) = rank {
+        write!(dest, "\"rank\": {}, ", rank)?;
+    }
+    write!(dest, "\"size\": {}", group[0].size)?;
+    if options.pretty_sizes {
+        write!(dest, ", \"size_human\": ")?;
+        write_json_string(dest, &format_bytes(group[0].size))?;
+    }
+    if options.by_alloc_size {
+        write!(dest, ", \"alloc_size\": {}", group[0].alloc_size)?;
+    }
+    if let Some(policy) = options.keep {
+        let keep = select_representative(group, policy);
+        if options.flat_json {
+            write!(dest, ", \"keep\": ")?;
+            write_json_path(dest, &group[keep].paths[0])?;
+        } else {
+            write!(dest, ", \"keep\": {}", keep)?;
+        }
+    }
+    write!(
+        dest,
+        ", \"reclaimable_bytes\": {}",
+        reclaimable_bytes(group)
+    )?;
+    let confidence = Confidence::of(group, options);
+    write!(dest, ", \"confidence\": \"{}\"", confidence.name())?;
+    if options.merging_dumps() && confidence == Confidence::Hashes {
+        write!(dest, ", \"hash_only\": true")?;
+    }
+    if confidence == Confidence::Prefix {
+        write!(dest, ", \"partial_match\": true")?;
+    }
+    if let (Some(chunk_size), Some(hashes)) = (options.chunk_hashes, &group[0].chunk_hashes) {
+        write!(
+            dest,
+            ", \"chunk_size\": {}, \"chunk_hashes\": [",
+            chunk_size
+        )?;
+        for (i, hash) in hashes.iter().enumerate() {
+            write!(dest, "{}", if i == 0 { "" } else { ", " })?;
+            write_json_string(dest, hash)?;
+        }
+        write!(dest, "]")?;
+    }
+    if options.flat_json {
+        write!(dest, ", \"paths\": [")?;
+        let paths = group.iter().flat_map(|df| &df.paths);
+        for (i, path) in paths.enumerate() {
+            write!(dest, "{}", if i == 0 { "" } else { ", " })?;
+            write_json_path(dest, path)?;
+        }
+        return write!(dest, "]}}");
+    }
+    write!(dest, ", \"files\": [")?;
+    for (i, df) in group.iter().enumerate() {
+        write!(dest, "{}{{\"paths\": [", if i == 0 { "" } else { ", " })?;
+        for (j, path) in df.paths.iter().enumerate() {
+            if j > 0 {
+                write!(dest, ", ")?;
+            }
+            write_json_path(dest, path)?;
+        }
+        write!(dest, "]")?;
+        if df.inode != 0 {
+            write!(
+                dest,
+                ", \"device\": {}, \"inode\": {}, \"links\": {}",
+                df.device, df.inode, df.nlink
+            )?;
+        }
+        if df.protected {
+            write!(dest, ", \"protected\": true")?;
+        }
+        write!(dest, "}}")?;
+    }
+    write!(dest, "]}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ScanError;
+    use crate::group_by_content::group_by_content;
+    use crate::json::{parse_json, JsonValue};
+    use crate::progress::Phase;
+    use crate::testing::{fake_file, file_record, options, TempDir};
+    use std::cell::RefCell;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    fn json_report(groups: &[Vec<DedupFile>], errors: Option<&ErrorLog>, args: &[&str]) -> String {
+        let mut dest = Vec::new();
+        let options = options(&[args, &["--format", "json", "/"]].concat());
+        write_dupes_json(&mut dest, groups, None, errors, &options).unwrap();
+        String::from_utf8(dest).unwrap()
+    }
+
+    #[test]
+    fn both_reports_start_with_the_format_version() {
+        let groups = vec![vec![fake_file(&["/a"], 6, 1), fake_file(&["/b"], 6, 2)]];
+        let mut listing = Vec::new();
+        write_dir_listing_json(&mut listing, &DirListing::new(), None, &options(&["/"])).unwrap();
+        for report in [
+            json_report(&groups, None, &[]),
+            String::from_utf8(listing).unwrap(),
+        ] {
+            let version = parse_json(&report)
+                .unwrap()
+                .get("format_version")
+                .and_then(JsonValue::as_u64);
+            assert_eq!(version, Some(OUTPUT_FORMAT_VERSION), "{}", report);
+            assert!(report.starts_with(&format!(
+                "{{\"format_version\": {}, ",
+                OUTPUT_FORMAT_VERSION
+            )));
+        }
+    }
+
+    // Somewhere to write that can be looked at while it's being written to.
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn groups_are_written_as_they_come_and_read_back_as_if_written_at_once() {
+        let groups: Vec<Vec<DedupFile>> = (0..3)
+            .map(|i| {
+                let name = format!("/g{}", i);
+                vec![
+                    fake_file(&[&name], 6, 2 * i),
+                    fake_file(&["/x"], 6, 2 * i + 1),
+                ]
+            })
+            .collect();
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let as_they_come = groups.iter().enumerate().inspect(|&(i, _)| {
+            if i > 0 {
+                let so_far = String::from_utf8(written.borrow().clone()).unwrap();
+                assert!(so_far.contains(&format!("/g{}", i - 1)), "{}", so_far);
+            }
+        });
+        let options = options(&["--format", "json", "/"]);
+        let mut dest = Shared(written.clone());
+        write_dupes_json(
+            &mut dest,
+            as_they_come.map(|(_, g)| g),
+            None,
+            None,
+            &options,
+        )
+        .unwrap();
+
+        let incremental = String::from_utf8(written.take()).unwrap();
+        assert_eq!(incremental, json_report(&groups, None, &[]));
+        let parsed = parse_json(&incremental).unwrap();
+        let parsed_groups = parsed.get("groups").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(parsed_groups.len(), 3);
+    }
+
+    #[test]
+    fn groups_are_written_with_their_files_and_links() {
+        let groups = vec![vec![
+            fake_file(&["/a", "/a2"], 6, 11),
+            fake_file(&["/b \"quoted\""], 6, 12),
+        ]];
+        assert_eq!(
+            json_report(&groups, None, &[]),
+            "{\"format_version\": 1, \"groups\": [\n  \
+             {\"size\": 6, \"reclaimable_bytes\": 6, \"confidence\": \"byte-confirmed\", \
+             \"files\": [{\"paths\": [\"/a\", \"/a2\"], \"device\": 1, \"inode\": 11, \
+             \"links\": 2}, {\"paths\": [\"/b \\\"quoted\\\"\"], \"device\": 1, \"inode\": 12, \
+             \"links\": 1}]}\n]}\n"
+        );
+    }
+
+    #[test]
+    fn flat_json_lists_every_path_including_hard_links() {
+        let groups = vec![vec![
+            fake_file(&["/a", "/a2", "/a3"], 6, 11),
+            fake_file(&["/b"], 6, 12),
+        ]];
+        assert_eq!(
+            json_report(&groups, None, &["--flat-json"]),
+            "{\"format_version\": 1, \"groups\": [\n  \
+             {\"size\": 6, \"reclaimable_bytes\": 6, \"confidence\": \"byte-confirmed\", \
+             \"paths\": [\"/a\", \"/a2\", \"/a3\", \"/b\"]}\n]}\n"
+        );
+        // The file to keep is given by path, since there are no files to number.
+        let kept = json_report(&groups, None, &["--flat-json", "--keep", "most-linked"]);
+        assert!(kept.contains(r#""keep": "/a", "#), "{}", kept);
+    }
+
+    #[test]
+    fn groups_matched_only_up_to_the_compare_limit_are_flagged() {
+        let groups = vec![vec![fake_file(&["/a"], 6, 1), fake_file(&["/b"], 6, 2)]];
+        let json = json_report(&groups, None, &["--compare-limit", "4"]);
+        assert!(
+            json.contains(r#""confidence": "prefix-confirmed", "partial_match": true"#),
+            "{}",
+            json
+        );
+        let json = json_report(&groups, None, &["--compare-limit", "6"]);
+        assert!(!json.contains("partial_match"), "{}", json);
+    }
+
+    #[test]
+    fn the_embedded_config_reads_back_as_the_settings_given() {
+        let groups = vec![vec![fake_file(&["/a"], 6, 1), fake_file(&["/b"], 6, 2)]];
+        let args = [
+            "--embed-config",
+            "--min-size",
+            "2k",
+            "--max-size",
+            "1000000",
+            "--exclude",
+            "*.tmp",
+            "--exclude",
+            "cache",
+            "--keep",
+            "oldest",
+            "--symlinks",
+            "within-root",
+            "--compare-limit",
+            "4096",
+            "--protect",
+            "/",
+        ];
+        let report = parse_json(&json_report(&groups, None, &args)).unwrap();
+        let config = report.get("config").unwrap();
+        let string = |key| config.get(key).and_then(JsonValue::as_str);
+        let number = |key| config.get(key).and_then(JsonValue::as_u64);
+        assert_eq!(string("version"), Some(env!("CARGO_PKG_VERSION")));
+        assert_eq!(
+            config.get("path").and_then(JsonValue::as_path),
+            Some(PathBuf::from("/"))
+        );
+        assert_eq!(number("min_size"), Some(2000));
+        assert_eq!(number("max_size"), Some(1_000_000));
+        assert_eq!(number("compare_limit"), Some(4096));
+        assert_eq!(config.get("limit"), Some(&JsonValue::Null));
+        assert_eq!(string("keep"), Some("oldest"));
+        assert_eq!(string("symlinks"), Some("within-root"));
+        assert_eq!(string("format"), Some("json"));
+        let excludes: Vec<&str> = config
+            .get("exclude")
+            .and_then(JsonValue::as_array)
+            .unwrap()
+            .iter()
+            .filter_map(JsonValue::as_str)
+            .collect();
+        assert_eq!(excludes, ["*.tmp", "cache"]);
+        let protect = config.get("protect").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(protect, [JsonValue::String("/".to_owned())]);
+        // The groups are still there after it.
+        assert_eq!(
+            report
+                .get("groups")
+                .and_then(JsonValue::as_array)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Without asking, there's no config.
+        let report = parse_json(&json_report(&groups, None, &[])).unwrap();
+        assert_eq!(report.get("config"), None);
+    }
+
+    #[test]
+    fn ranks_are_dense_and_in_order() {
+        let groups: Vec<Vec<DedupFile>> = (0..4)
+            .map(|i| {
+                vec![
+                    fake_file(&["/x"], 10 - i, 2 * i),
+                    fake_file(&["/y"], 10 - i, 2 * i + 1),
+                ]
+            })
+            .collect();
+        let mut dest = Vec::new();
+        let options = options(&["--rank", "--format", "json", "/"]);
+        write_dupes_json(&mut dest, &groups, Some(groups.len()), None, &options).unwrap();
+        let json = String::from_utf8(dest).unwrap();
+        let ranks: Vec<&str> = json
+            .lines()
+            .filter_map(|line| line.strip_prefix("  {\"rank\": "))
+            .map(|rest| &rest[..rest.find(',').unwrap()])
+            .collect();
+        assert_eq!(ranks, ["1", "2", "3", "4"]);
+        // Unranked groups have no rank.
+        assert!(!json_report(&groups, None, &[]).contains("rank"));
+    }
+
+    #[test]
+    fn errors_are_listed_after_the_groups() {
+        let errors = ErrorLog::default();
+        errors.record(ScanError::new(
+            Path::new("/gone"),
+            Phase::Compare,
+            &io::Error::from(io::ErrorKind::NotFound),
+        ));
+        assert_eq!(
+            json_report(&[], Some(&errors), &["--report-errors"]),
+            "{\"format_version\": 1, \"groups\": [\n],\n\"errors\": [\n  \
+             {\"path\": \"/gone\", \"phase\": \"compare\", \"reason\": \"disappeared during scan\"}\
+             \n]}\n"
+        );
+        // Without any, the array is still there, so that its absence can't be mistaken for none.
+        assert!(
+            json_report(&[], Some(&ErrorLog::default()), &["--report-errors"])
+                .ends_with("\"errors\": []}\n")
+        );
+    }
+
+    #[test]
+    fn files_that_disappear_before_comparison_are_errors() {
+        let dir = TempDir::new();
+        let files = ["a", "b", "c"].map(|name| file_record(&dir.write(name, "same")));
+        fs::remove_file(&files[2].paths[0]).unwrap();
+        let errors = ErrorLog::default();
+        let groups: Vec<Vec<DedupFile>> =
+            group_by_content(vec![files.to_vec()], &options(&["/"]), &errors).collect();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+        let json = json_report(&groups, Some(&errors), &["--report-errors"]);
+        let gone = format!(
+            "{{\"path\": \"{}\", \"phase\": \"compare\", \"reason\": \"disappeared during scan\"}}",
+            files[2].paths[0].display()
+        );
+        assert!(json.contains(&gone), "{}", json);
+    }
+
+    #[test]
+    fn unreadable_directories_are_errors() {
+        let dir = TempDir::new();
+        dir.write("a", "same");
+        dir.write("locked/b", "same");
+        let locked = dir.path().join("locked");
+        fs::set_permissions(&locked, Permissions::from_mode(0o000)).unwrap();
+        if fs::read_dir(&locked).is_ok() {
+            fs::set_permissions(&locked, Permissions::from_mode(0o755)).unwrap();
+            eprintln!("Skipping test: permissions aren't enforced for this user");
+            return;
+        }
+        let errors = ErrorLog::default();
+        let options = options(&["--min-size", "1", dir.path().to_str().unwrap()]);
+        let files: Vec<DedupFile> =
+            crate::group_by_inode::group_by_inode(dir.path(), &options, &errors).collect();
+        fs::set_permissions(&locked, Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(files.len(), 1);
+        let json = json_report(&[], Some(&errors), &["--report-errors"]);
+        let denied = format!(
+            "{{\"path\": \"{}\", \"phase\": \"traverse\", \"reason\": \"permission denied\"}}",
+            locked.display()
+        );
+        assert!(json.contains(&denied), "{}", json);
+    }
+}
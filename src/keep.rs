@@ -0,0 +1,206 @@
+use std::cmp::Reverse;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use crate::DedupFile;
+
+// How to choose which file in a group of duplicates is the one to keep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepPolicy {
+    // The first file in the group: by path, unless `--sort-within-groups` says otherwise.
+    First,
+    // The file with the shortest path (e.g. the least deeply nested).
+    ShortestPath,
+    // The file with the longest path.
+    LongestPath,
+    // The least recently modified file.
+    Oldest,
+    // The most recently modified file.
+    Newest,
+    // The file with the most hard links, so that the most paths keep pointing at the same data.
+    MostLinked,
+}
+
+impl KeepPolicy {
+    // The names of the policies as given on the command line.
+    pub const NAMES: [&'static str; 6] = [
+        "first",
+        "shortest-path",
+        "longest-path",
+        "oldest",
+        "newest",
+        "most-linked",
+    ];
+
+    pub fn from_name(name: &str) -> Result<KeepPolicy> {
+        Ok(match name {
+            "first" => KeepPolicy::First,
+            "shortest-path" => KeepPolicy::ShortestPath,
+            "longest-path" => KeepPolicy::LongestPath,
+            "oldest" => KeepPolicy::Oldest,
+            "newest" => KeepPolicy::Newest,
+            "most-linked" => KeepPolicy::MostLinked,
+            _ => bail!("Unknown keep policy {:?}", name),
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeepPolicy::First => "first",
+            KeepPolicy::ShortestPath => "shortest-path",
+            KeepPolicy::LongestPath => "longest-path",
+            KeepPolicy::Oldest => "oldest",
+            KeepPolicy::Newest => "newest",
+            KeepPolicy::MostLinked => "most-linked",
+        }
+    }
+}
+
+// The length of a file's shortest path.  Hard links mean a file may have several.
+fn shortest_path_len(df: &DedupFile) -> usize {
+    df.paths
+        .iter()
+        .map(|p| p.as_os_str().len())
+        .min()
+        .unwrap_or(0)
+}
+
+// The length of a file's longest path.
+fn longest_path_len(df: &DedupFile) -> usize {
+    df.paths
+        .iter()
+        .map(|p| p.as_os_str().len())
+        .max()
+        .unwrap_or(0)
+}
+
+// Pick the index of the file to keep from a (non-empty) group of duplicates.  Anything that needs
+// a survivor should ask here, so that every feature agrees on which one it is.  If any files are
+// protected, the survivor is always one of them.  Ties go to the earliest candidate in the group,
+// so the group should be in a settled order (see `sort_within_group`) for the choice to be too.
+pub fn select_representative(group: &[DedupFile], policy: KeepPolicy) -> usize {
+    let any_protected = group.iter().any(|df| df.protected);
+    let mut candidates = group
+        .iter()
+        .enumerate()
+        .filter(|(_, df)| df.protected || !any_protected);
+    let best = match policy {
+        KeepPolicy::First => candidates.next(),
+        KeepPolicy::ShortestPath => candidates.min_by_key(|(_, df)| shortest_path_len(df)),
+        KeepPolicy::LongestPath => candidates.min_by_key(|(_, df)| Reverse(longest_path_len(df))),
+        KeepPolicy::Oldest => candidates.min_by_key(|(_, df)| df.mtime),
+        KeepPolicy::Newest => candidates.min_by_key(|(_, df)| Reverse(df.mtime)),
+        KeepPolicy::MostLinked => candidates.min_by_key(|(_, df)| Reverse(df.nlink)),
+    };
+    best.map(|(i, _)| i).unwrap_or(0)
+}
+
+// True iff the file at index `i` stays: it's either the survivor or protected.  Every other file
+// in the group is a redundant copy.
+pub fn is_kept(group: &[DedupFile], i: usize, policy: KeepPolicy) -> bool {
+    group[i].protected || i == select_representative(group, policy)
+}
+
+// Note which files in a group are protected, i.e. have a path under one of the `protect`
+// directories.  These are in the same form as the paths, so this must happen before the paths are
+// made relative.
+pub fn mark_protected(group: &mut [DedupFile], protect: &[PathBuf]) {
+    for df in group {
+        df.protected = df
+            .paths
+            .iter()
+            .any(|path| protect.iter().any(|dir| path.starts_with(dir)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_file;
+
+    // Three copies: b is the oldest and has the shortest path, c is the newest and has the most
+    // links, and a has the longest path.
+    fn group() -> Vec<DedupFile> {
+        let mut a = fake_file(&["/data/archive/a"], 10, 1);
+        a.mtime = 200;
+        let mut b = fake_file(&["/b"], 10, 2);
+        b.mtime = 100;
+        let mut c = fake_file(&["/data/c", "/data/c2"], 10, 3);
+        c.mtime = 300;
+        vec![a, b, c]
+    }
+
+    #[test]
+    fn each_policy_picks_its_file() {
+        let group = group();
+        let kept = |policy| select_representative(&group, policy);
+        assert_eq!(kept(KeepPolicy::First), 0);
+        assert_eq!(kept(KeepPolicy::ShortestPath), 1);
+        assert_eq!(kept(KeepPolicy::LongestPath), 0);
+        assert_eq!(kept(KeepPolicy::Oldest), 1);
+        assert_eq!(kept(KeepPolicy::Newest), 2);
+        assert_eq!(kept(KeepPolicy::MostLinked), 2);
+    }
+
+    #[test]
+    fn a_protected_file_is_always_the_one_kept() {
+        let mut group = group();
+        group[0].protected = true;
+        for name in KeepPolicy::NAMES {
+            let policy = KeepPolicy::from_name(name).unwrap();
+            assert_eq!(select_representative(&group, policy), 0, "{}", name);
+            assert!(is_kept(&group, 0, policy));
+            assert!(!is_kept(&group, 1, policy) && !is_kept(&group, 2, policy));
+        }
+    }
+
+    #[test]
+    fn every_protected_file_is_kept_and_the_policy_picks_among_them() {
+        let mut group = group();
+        group[1].protected = true;
+        group[2].protected = true;
+        let kept = |policy| select_representative(&group, policy);
+        assert_eq!(kept(KeepPolicy::First), 1);
+        assert_eq!(kept(KeepPolicy::LongestPath), 2);
+        assert_eq!(kept(KeepPolicy::Newest), 2);
+        assert_eq!(kept(KeepPolicy::Oldest), 1);
+        for name in KeepPolicy::NAMES {
+            let policy = KeepPolicy::from_name(name).unwrap();
+            let kept: Vec<bool> = (0..3).map(|i| is_kept(&group, i, policy)).collect();
+            assert_eq!(kept, [false, true, true], "{}", name);
+        }
+    }
+
+    #[test]
+    fn files_with_any_path_under_a_protected_directory_are_protected() {
+        let mut group = group();
+        group.push(fake_file(&["/data/archive2/d"], 10, 4));
+        mark_protected(
+            &mut group,
+            &[PathBuf::from("/data/archive"), PathBuf::from("/elsewhere")],
+        );
+        let protected: Vec<bool> = group.iter().map(|df| df.protected).collect();
+        assert_eq!(protected, [true, false, false, false]);
+        mark_protected(&mut group, &[PathBuf::from("/data")]);
+        let protected: Vec<bool> = group.iter().map(|df| df.protected).collect();
+        assert_eq!(protected, [true, false, true, true]);
+    }
+
+    #[test]
+    fn ties_go_to_the_earliest_file() {
+        let group = vec![fake_file(&["/x"], 10, 1), fake_file(&["/y"], 10, 2)];
+        for name in KeepPolicy::NAMES {
+            let policy = KeepPolicy::from_name(name).unwrap();
+            assert_eq!(select_representative(&group, policy), 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn every_policy_has_a_name() {
+        for name in KeepPolicy::NAMES {
+            assert_eq!(KeepPolicy::from_name(name).unwrap().name(), name);
+        }
+        assert!(KeepPolicy::from_name("biggest").is_err());
+    }
+}
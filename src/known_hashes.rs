@@ -0,0 +1,125 @@
+// Content that's expected to be duplicated, for `--skip-hashes`.
+//
+// A backup system may keep a list of the content it has already archived, and copies of that are
+// no surprise.  The list has a BLAKE3 digest in hex at the start of each line, so `b3sum` output
+// (digest, then path) will do.  Blank lines and lines starting with `#` are ignored.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::errors::{ErrorLog, ScanError};
+use crate::hash::hash_file;
+use crate::progress::Phase;
+use crate::DedupFile;
+
+// Load a list of digests, lowercased to match ours.
+pub fn read_hash_list(path: &Path) -> Result<HashSet<String>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read hash list {:?}", path))?;
+    let mut hashes = HashSet::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let digest = line.split_whitespace().next().unwrap_or_default();
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!(
+                "Bad BLAKE3 digest {:?} on line {} of hash list {:?}",
+                digest,
+                i + 1,
+                path
+            );
+        }
+        hashes.insert(digest.to_ascii_lowercase());
+    }
+    Ok(hashes)
+}
+
+// Drop the files in a group of duplicates whose content is in the list.  If the files in the group
+// were compared in full (`exact`), they all have the same content, so one digest decides for all
+// of them; otherwise each file is checked.  We use the digest recorded in a dump if there is one,
+// and hash the file if not (and it's here to hash).  A file we can't hash is kept, and noted in
+// `errors`.
+pub fn drop_known_content(
+    group: Vec<DedupFile>,
+    known: &HashSet<String>,
+    exact: bool,
+    errors: &ErrorLog,
+) -> Vec<DedupFile> {
+    let is_known = |df: &DedupFile| {
+        let hash = match &df.hash {
+            Some(hash) => Ok(hash.clone()),
+            None if df.is_local() => hash_file(&df.paths[0]),
+            None => return false,
+        };
+        match hash {
+            Ok(hash) => known.contains(&hash),
+            Err(e) => {
+                errors.record(ScanError::new(&df.paths[0], Phase::Compare, &e));
+                false
+            }
+        }
+    };
+    if exact {
+        let source = group.iter().find(|df| df.hash.is_some() || df.is_local());
+        return match source {
+            Some(df) if is_known(df) => Vec::new(),
+            _ => group,
+        };
+    }
+    group.into_iter().filter(|df| !is_known(df)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file_record, TempDir};
+
+    #[test]
+    fn hash_lists_may_be_b3sum_output_with_comments() {
+        let dir = TempDir::new();
+        let digest = hash_file(&dir.write("a", "archived")).unwrap();
+        let list = dir.write(
+            "list",
+            format!(
+                "# archived\n\n{}  a\n  {}\n",
+                digest.to_ascii_uppercase(),
+                "0".repeat(64)
+            ),
+        );
+        assert_eq!(
+            read_hash_list(&list).unwrap(),
+            HashSet::from([digest, "0".repeat(64)])
+        );
+
+        let bad = dir.write("bad", format!("{}\nnot-a-digest  b\n", "0".repeat(64)));
+        let e = read_hash_list(&bad).unwrap_err().to_string();
+        assert!(e.contains("\"not-a-digest\" on line 2"), "{}", e);
+        assert!(read_hash_list(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn a_group_whose_content_is_known_is_dropped() {
+        let dir = TempDir::new();
+        let archived = ["a", "b"].map(|name| file_record(&dir.write(name, "archived")));
+        let new = ["c", "d"].map(|name| file_record(&dir.write(name, "new")));
+        let known = HashSet::from([hash_file(&archived[0].paths[0]).unwrap()]);
+        let errors = ErrorLog::default();
+
+        assert!(drop_known_content(archived.to_vec(), &known, true, &errors).is_empty());
+        assert_eq!(
+            drop_known_content(new.to_vec(), &known, true, &errors).len(),
+            2
+        );
+        // Files that weren't compared in full are checked one by one.
+        let mixed = vec![archived[0].clone(), new[0].clone(), archived[1].clone()];
+        let kept = drop_known_content(mixed, &known, false, &errors);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].paths, new[0].paths);
+        assert!(errors.take().is_empty());
+    }
+}
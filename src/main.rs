@@ -1,83 +1,524 @@
-use std::collections::HashMap;
-use std::io;
-use std::path::Path;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 mod group_by_inode;
-use group_by_inode::{group_by_inode, DedupFile};
+use group_by_inode::{group_by_inode, DedupFile, FileIdentity, TraversalStats};
 
 mod group_by_content;
-use group_by_content::group_by_content;
+use group_by_content::{first_file_difference, group_by_content, split_by_key, CompareOptions};
+
+mod group_by_normalised;
+use group_by_normalised::group_by_normalised_content;
 
 mod html;
-use html::write_dupes_html;
-
-// Parse a string describing the size of a file, with optional SI or IEC unit prefix.
-fn parse_file_size_spec(s: &str) -> Result<u64> {
-    let mut t: String = s.to_owned();
-    t.make_ascii_lowercase();
-    let (num_str, suffix) = t
-        .find(|c: char| c.is_ascii_alphabetic())
-        .map(|i| t.split_at(i))
-        .unwrap_or((&t, ""));
-    let multiplier: u64 = match suffix {
-        "" => 1,
-        "k" | "kb" => 1_000,
-        "m" | "mb" => 1_000_000,
-        "g" | "gb" => 1_000_000_000,
-        "t" | "tb" => 1_000_000_000_000,
-        "ki" | "kib" => 1_024,
-        "mi" | "mib" => 1_024 * 1_024,
-        "gi" | "gib" => 1_024 * 1_024 * 1_024,
-        "ti" | "tib" => 1_024 * 1_024 * 1_024 * 1_024,
-        _ => bail!("Failed to parse file size (bad multiplier -- got {:?})", s),
-    };
-    num_str
-        .parse()
-        .map(|num: u64| num * multiplier)
-        .with_context(|| format!("Failed to parse file size (bad number -- got {:?})", s))
-}
+use html::{write_dir_listing_html, write_dupes_html};
 
-fn main() -> Result<()> {
-    use clap::{command, Arg};
-
-    // Parse command-line arguments.
-    let matches = command!()
-        .arg(
-            Arg::new("PATH")
-                .help("Location to search")
-                .required(true)
-                .index(1),
-        )
-        .arg(
-            Arg::new("MIN_SIZE")
-                .long("min-size")
-                .help("Ignore files smaller than this (bytes)")
-                .default_value("100000"),
-        )
-        .get_matches();
+mod json_report;
+use json_report::{write_dir_listing_json, write_dupes_json};
 
-    let target = Path::new(
-        matches
-            .get_one::<String>("PATH")
-            .expect("Failed to read PATH from command-line arguments"),
+mod dot;
+use dot::write_dupes_dot;
+
+mod tree;
+use tree::write_dupes_tree;
+
+mod checkpoint;
+use checkpoint::{read_checkpoint, write_checkpoint, CHECKPOINT_INTERVAL};
+
+mod by_dir;
+use by_dir::{list_by_dir, savings_by_dir, write_savings_by_dir, DirListing};
+
+mod errors;
+use errors::{keep_going_or_fail, ErrorLog, ScanError};
+
+mod blake3;
+mod hash;
+mod json;
+use hash::{hash_file, hash_file_chunks};
+
+mod dump;
+use dump::{read_dump, write_file_record};
+
+mod index;
+use index::{build_index, write_index};
+
+mod prefixes;
+use prefixes::{find_prefixes, write_prefixes};
+
+mod dir_dupes;
+use dir_dupes::{find_dir_dupes, write_dir_dupes};
+
+mod review;
+use review::write_review_dir;
+
+mod broken_symlinks;
+use broken_symlinks::{take_broken_symlinks, write_broken_symlinks};
+
+mod snapshot;
+use snapshot::drop_snapshot_copies;
+
+mod in_use;
+use in_use::files_open_for_writing;
+
+mod known_hashes;
+use known_hashes::{drop_known_content, read_hash_list};
+
+mod signature;
+use signature::drop_signature_matches;
+
+mod hardlinkable;
+use hardlinkable::{is_hardlinkable, Credentials};
+
+mod keep;
+use keep::{mark_protected, KeepPolicy};
+
+mod open_files;
+use open_files::set_max_open_files;
+
+mod io_limit;
+use io_limit::set_io_limit;
+
+mod deadline;
+use deadline::{set_deadline, was_truncated};
+
+mod explain;
+use explain::{enable_explain, explain_singleton};
+
+mod progress;
+use progress::{enable_progress, finish_progress, set_phase, Phase};
+
+mod sample;
+
+mod summary;
+use summary::{
+    format_thousands, sort_by_reclaimable, write_counts, write_extension_summary, write_probe,
+    write_size_histogram,
+};
+
+mod relative;
+use relative::{
+    canonical_cwd, make_relative, normalise_dump_paths, normalise_path, relativise_group,
+    relativise_paths,
+};
+
+mod config;
+mod glob;
+mod options;
+use options::{GroupOrder, Options, OutputFormat, PathStyle};
+
+mod diff;
+use diff::{diff_reports, read_report, write_report_diff};
+
+mod verify;
+use verify::{verify_report, write_verification};
+
+mod external_sort;
+use external_sort::{group_by_size_external, DEFAULT_RUN_LEN};
+
+#[cfg(test)]
+mod testing;
+
+// Report how much of the filesystem we looked at, and if we only took a sample, how to take the
+// same one again.
+fn print_traversal_stats(stats: TraversalStats, options: &Options) {
+    eprintln!(
+        "Scanned {} entries, considered {} files",
+        format_thousands(stats.entries_seen),
+        format_thousands(stats.files_considered)
     );
+    if stats.files_too_new > 0 {
+        eprintln!(
+            "Skipped {} files created or changed since the scan started",
+            format_thousands(stats.files_too_new)
+        );
+    }
+    if stats.files_too_young > 0 {
+        eprintln!(
+            "Skipped {} files modified more recently than --min-age",
+            format_thousands(stats.files_too_young)
+        );
+    }
+    if stats.files_not_owned > 0 {
+        eprintln!(
+            "Skipped {} files owned by other users",
+            format_thousands(stats.files_not_owned)
+        );
+    }
+    if let Some(seed) = options.shuffle_seed {
+        eprintln!("Sampled with --shuffle-seed {}", seed);
+    }
+}
+
+// With `--find-broken-symlinks`, list the broken symlinks we came across on stderr, with their
+// paths in the same form as the report's.  Their targets are shown as they are.
+fn list_broken_symlinks(relative_base: Option<&Path>) -> Result<()> {
+    let mut symlinks = take_broken_symlinks();
+    if let Some(base) = relative_base {
+        for (path, _) in &mut symlinks {
+            *path = make_relative(path, base);
+        }
+    }
+    write_broken_symlinks(&mut io::stderr(), &symlinks).context("Failed to write broken symlinks")
+}
+
+// With `--exclude-signature`, drop the files in a group of candidates that start with it, before
+// they're compared.
+fn exclude_signature(
+    group: Vec<DedupFile>,
+    options: &Options,
+    errors: &ErrorLog,
+) -> Vec<DedupFile> {
+    match &options.exclude_signature {
+        Some(signature) => drop_signature_matches(group, signature, errors),
+        None => group,
+    }
+}
+
+// With `--inode-order`, put the groups of candidates, and the files in each, in order of device
+// and inode number.  On most filesystems that's roughly the order their content is laid out on
+// disk, so reading them in that order saves seeking.  Groups and files are both taken from the
+// end of their lists to be compared, so they're sorted in reverse.
+fn sort_by_inode(shortlist: &mut [Vec<DedupFile>]) {
+    for group in shortlist.iter_mut() {
+        group.sort_by_key(|df| Reverse((df.device, df.inode)));
+    }
+    shortlist.sort_by_key(|group| Reverse(group.last().map(|df| (df.device, df.inode))));
+}
 
-    let min_size_str = matches
-        .get_one::<String>("MIN_SIZE")
-        .expect("Failed to find MIN_SIZE argument despite clap default_value");
-    let min_size: u64 = parse_file_size_spec(min_size_str)?;
+// Put the files in a group of duplicates in the order asked for with `--sort-within-groups` (or
+// by path, by default), with ties broken by path.  Sorting by path sorts the hard links to each
+// file too.  Files are found in whatever order the filesystem and our hash maps give, so this is
+// what makes the report, and the file each policy keeps, the same from one run to the next.  It
+// happens before anything picks a file to keep, so `--keep first` keeps the first in this
+// order.
+fn sort_within_group(group: &mut [DedupFile], order: GroupOrder) {
+    if order == GroupOrder::Path {
+        for df in group.iter_mut() {
+            df.paths.sort();
+        }
+    }
+    group.sort_by(|df1, df2| {
+        let by_order = match order {
+            GroupOrder::Path => std::cmp::Ordering::Equal,
+            GroupOrder::Mtime => df1.mtime.cmp(&df2.mtime),
+            GroupOrder::Size => df1.size.cmp(&df2.size),
+        };
+        by_order.then_with(|| df1.paths.cmp(&df2.paths))
+    });
+}
+
+// Whatever happened upstream, this is the last word on which groups get reported: each must still
+// have at least `min_group` distinct files (and so at least two), and given `credentials`, we must
+// be able to replace them with hard links.
+fn is_reportable(group: &[DedupFile], min_group: usize, credentials: Option<&Credentials>) -> bool {
+    group.len() >= min_group.max(2)
+        && credentials.is_none_or(|credentials| is_hardlinkable(group, credentials))
+}
 
+// Group each partition of files by size, ready for comparing.  Only the groups with more than one
+// file (after `--exclude-signature`) are kept.
+fn shortlist_by_size(
+    partitions: Vec<Vec<DedupFile>>,
+    options: &Options,
+    errors: &ErrorLog,
+) -> Vec<Vec<DedupFile>> {
+    let mut shortlist: Vec<Vec<DedupFile>> = partitions
+        .into_iter()
+        .flat_map(|p| group_by_size(p, options.by_alloc_size))
+        .map(|grp| exclude_signature(grp, options, errors))
+        .filter(|grp| grp.len() > 1)
+        .collect();
+    if options.inode_order {
+        sort_by_inode(&mut shortlist);
+    }
+    shortlist
+}
+
+// Group a list of files by size, discarding any size that only one file has.  If `by_alloc_size`
+// is set, files must also have the same amount of space allocated on disk, so e.g. a sparse file
+// and a fully-allocated copy of it aren't grouped.  The groups are in order of size, and the files
+// in each in order of path, so that they're compared (and `--explain` says so) the same way each
+// run.
+fn group_by_size(files: Vec<DedupFile>, by_alloc_size: bool) -> Vec<Vec<DedupFile>> {
+    let mut dupes_by_size: BTreeMap<(u64, u64), Vec<DedupFile>> = BTreeMap::new();
+    for f in files {
+        let key = (f.size, if by_alloc_size { f.alloc_size } else { 0 });
+        dupes_by_size.entry(key).or_default().push(f);
+    }
+
+    dupes_by_size
+        .into_values()
+        .filter(|grp| {
+            if grp.len() == 1 {
+                explain_singleton(&grp[0].paths[0], "size");
+            }
+            grp.len() > 1
+        })
+        .map(|mut grp| {
+            grp.sort_by(|f1, f2| f1.paths.cmp(&f2.paths));
+            grp
+        })
+        .collect()
+}
+
+// Partition a list of files by the directory containing them, with `--per-directory`, and/or by
+// their (lowercased) extension, with `--same-extension`, so that only files in the same partition
+// can be duplicates.  Hard links may live in several directories, and have different names, so we
+// use the first path we found for each file.  The partitions are in order of their directory and
+// extension, so that whatever follows them (like `--explain`) goes the same way every run.
+fn partition_files(files: Vec<DedupFile>, options: &Options) -> Vec<Vec<DedupFile>> {
+    if !(options.per_directory || options.same_extension) {
+        return vec![files];
+    }
+    let mut partitions: BTreeMap<(Option<PathBuf>, Option<String>), Vec<DedupFile>> =
+        BTreeMap::new();
+    for f in files {
+        let path = &f.paths[0];
+        let parent = options
+            .per_directory
+            .then(|| path.parent().unwrap_or(Path::new("")).to_path_buf());
+        let extension = options.same_extension.then(|| {
+            path.extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default()
+        });
+        partitions.entry((parent, extension)).or_default().push(f);
+    }
+    partitions.into_values().collect()
+}
+
+// With `--chunk-hashes`, digest the content of a group of duplicates chunk by chunk.  Every file
+// in the group has the same content, so we only read one of them (one that's actually here, if
+// they came from dumps) and give each file the same digests.
+fn add_chunk_hashes(group: &mut [DedupFile], options: &Options, errors: &ErrorLog) {
+    let Some(chunk_size) = options.chunk_hashes else {
+        return;
+    };
+    let Some(source) = group
+        .iter()
+        .find(|df| !options.merging_dumps() || df.is_local())
+    else {
+        return;
+    };
+    let path = source.paths[0].clone();
+    match hash_file_chunks(&path, chunk_size) {
+        Ok(hashes) => {
+            for df in group.iter_mut() {
+                df.chunk_hashes = Some(hashes.clone());
+            }
+        }
+        Err(e) => errors.record(ScanError::new(&path, Phase::Compare, &e)),
+    }
+}
+
+// Having skipped unreadable paths, say which they were (unless they were already listed in the
+// report).  Likewise if we stopped early for lack of time.
+fn warn_scan_errors(errors: &ErrorLog, options: &Options) {
+    if was_truncated() {
+        eprintln!("Warning: truncated due to time limit, so some duplicates may be missing");
+    }
+    if !options.report_errors {
+        for error in errors.take() {
+            eprintln!("Warning: skipped {:?}: {}", error.path, error.message);
+        }
+    }
+}
+
+// Open a file descriptor we inherited, for writing.  We reopen it by name rather than adopting
+// the raw descriptor, which would need `unsafe`.
+fn open_fd(fd: i32) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/dev/fd/{}", fd))
+}
+
+// Where progress events go: stderr, unless we were given another file descriptor.
+fn open_progress(options: &Options) -> Result<Box<dyn Write + Send>> {
+    match options.progress_fd {
+        Some(fd) => {
+            Ok(Box::new(open_fd(fd).with_context(|| {
+                format!("Failed to open --progress-fd {}", fd)
+            })?))
+        }
+        None => Ok(Box::new(io::stderr())),
+    }
+}
+
+// Where the report goes: stdout, unless we were given a file or file descriptor.
+fn open_report(options: &Options) -> Result<Box<dyn Write>> {
+    if let Some(fd) = options.output_fd {
+        return Ok(Box::new(BufWriter::new(
+            open_fd(fd).with_context(|| format!("Failed to open --output-fd {}", fd))?,
+        )));
+    }
+    match &options.output {
+        Some(path) => Ok(Box::new(BufWriter::new(
+            File::create(path)
+                .with_context(|| format!("Failed to create output file {:?}", path))?,
+        ))),
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+// Write the report in the requested format (or just the totals, with `--count-only`).  Errors are
+// drained from `errors` only after all the groups have been written, since the groups may still be
+// being found as we go.  If the groups have been ranked, `group_count` says how many there are.
+fn write_report<G: AsRef<[DedupFile]>>(
+    dest: &mut impl Write,
+    dupes: impl IntoIterator<Item = G>,
+    group_count: Option<usize>,
+    errors: &ErrorLog,
+    options: &Options,
+) -> Result<()> {
+    if let Some(style) = options.count_only {
+        write_counts(dest, dupes, style).context("Failed to write counts")?;
+        return dest.flush().context("Failed to write counts");
+    }
+    match options.format {
+        OutputFormat::Html => write_dupes_html(dest, dupes, group_count, options),
+        OutputFormat::Json => write_dupes_json(
+            dest,
+            dupes,
+            group_count,
+            Some(errors).filter(|_| options.report_errors),
+            options,
+        ),
+        OutputFormat::Dot => write_dupes_dot(dest, dupes, options),
+        OutputFormat::Tree => write_dupes_tree(dest, dupes, options.pretty_sizes),
+    }
+    .context("Failed to write report")?;
+    dest.flush().context("Failed to write report")
+}
+
+// Write the report reorganised by directory, in the requested format.
+fn write_dir_listing(
+    dest: &mut impl Write,
+    listing: &DirListing,
+    errors: &ErrorLog,
+    options: &Options,
+) -> Result<()> {
+    match options.format {
+        OutputFormat::Html => write_dir_listing_html(dest, listing, options),
+        OutputFormat::Json => write_dir_listing_json(
+            dest,
+            listing,
+            Some(errors).filter(|_| options.report_errors),
+            options,
+        ),
+        OutputFormat::Dot | OutputFormat::Tree => {
+            unreachable!(
+                "Wrote {:?} by directory despite options check",
+                options.format
+            )
+        }
+    }
+    .context("Failed to write report")?;
+    dest.flush().context("Failed to write report")
+}
+
+// The same pipeline as in `main`, but streaming from end to end so that we never hold every file
+// in memory at once.  Traversal results are consolidated and grouped by size using an on-disk
+// merge sort, each size group goes through content comparison on its own, and each group of
+// duplicates is written out as soon as we find it.
+fn find_dupes_low_memory(
+    target: &Path,
+    options: &Options,
+    errors: &ErrorLog,
+    finalise_group: impl FnMut(Vec<DedupFile>) -> Option<Vec<DedupFile>>,
+) -> Result<()> {
+    let mut traversal = group_by_inode(target, options, errors);
+    let groups_by_size = group_by_size_external(&mut traversal, DEFAULT_RUN_LEN)
+        .context("Failed to spill file list to disk")?;
+    if options.stats {
+        print_traversal_stats(traversal.stats(), options);
+    }
+    set_phase(Phase::Compare);
+
+    let mut spill_error = None;
+    let dupes_by_content = groups_by_size
+        .map_while(|grp| grp.map_err(|e| spill_error = Some(e)).ok())
+        .flat_map(|grp| {
+            let mut shortlist: Vec<Vec<DedupFile>> =
+                partition_files(exclude_signature(grp, options, errors), options)
+                    .into_iter()
+                    .filter(|g| g.len() > 1)
+                    .collect();
+            if options.inode_order {
+                sort_by_inode(&mut shortlist);
+            }
+            group_by_content(shortlist, options, errors)
+        })
+        .flat_map(|grp| split_by_key(grp, options))
+        .filter_map(finalise_group);
+
+    let mut dest = open_report(options)?;
+    write_report(&mut dest, dupes_by_content, None, errors, options)?;
+
+    if let Some(e) = spill_error {
+        return Err(e).context("Failed to read spilled file list back from disk");
+    }
+    // Everything is streamed, so this is the first chance we get to check.
+    warn_scan_errors(errors, options);
+    finish_progress();
+    Ok(())
+}
+
+// The sizes of all the files in the tree that pass our other filters, counting each file on disk
+// once however many hard links it has.  This means an extra traversal, but we need it to find
+// percentiles and means before the real traversal can apply them.
+fn sizes_in_tree(target: &Path, options: &Options, errors: &ErrorLog) -> Vec<u64> {
+    let mut unbounded = options.clone();
+    unbounded.min_size = 0;
+    unbounded.max_size = None;
+    let mut seen: HashSet<FileIdentity> = HashSet::new();
+    group_by_inode(target, &unbounded, errors)
+        .filter(|f| seen.insert(f.identity()))
+        .map(|f| {
+            if options.by_alloc_size {
+                f.alloc_size
+            } else {
+                f.size
+            }
+        })
+        .collect()
+}
+
+// Traverse the filesystem, returning a list of the files on disk.  With `--resume`, we carry on
+// from the last checkpoint if there is one, and save a new one every so often.
+fn find_files(target: &Path, options: &Options, errors: &ErrorLog) -> Result<Vec<DedupFile>> {
     // Traverse the filesystem.  Since we expect to be limited by disk I/O, there may be no
     // performance benefit from parallelism.
     //
     // Consolidate  by device number and inode -- i.e. find multiple hard links to the same file
     // on disk.  It's going to take some time to traverse the filesystem, so if we were to group
-    // by size first, there's a risk the file could change as we're traversing.
-    let mut files_by_inode: HashMap<(u64, u64), DedupFile> = HashMap::new();
-    for f in group_by_inode(target, min_size) {
-        let ino = (f.device, f.inode);
+    // by size first, there's a risk the file could change as we're traversing.  Where inode
+    // numbers can't be trusted, each path counts as a file of its own.
+    let mut files_by_inode: HashMap<FileIdentity, DedupFile> = HashMap::new();
+    let mut traversal = group_by_inode(target, options, errors);
+    if let Some(state_path) = &options.resume {
+        if let Some(checkpoint) = read_checkpoint(state_path)? {
+            if checkpoint.root != target {
+                bail!(
+                    "Checkpoint {:?} is for {:?}, not {:?}",
+                    state_path,
+                    checkpoint.root,
+                    target
+                );
+            }
+            files_by_inode.extend(checkpoint.files.into_iter().map(|f| (f.identity(), f)));
+            traversal = traversal.resume(checkpoint.state);
+        }
+    }
+    let mut last_checkpoint = traversal.stats().dirs_read;
+    while let Some(f) = traversal.next() {
+        let ino = f.identity();
         match files_by_inode.get_mut(&ino) {
             Some(existing_f) => {
                 // We found another hard link to a file on disk we've already seen.  Since we have
@@ -85,37 +526,970 @@ fn main() -> Result<()> {
                 assert_eq!(f.paths.len(), 1);
                 existing_f.paths.push(f.paths[0].to_path_buf());
                 existing_f.size = f.size;
+                existing_f.alloc_size = f.alloc_size;
                 existing_f.nlink = f.nlink;
+                existing_f.mtime = f.mtime;
+                existing_f.mode = f.mode;
+                existing_f.uid = f.uid;
+                existing_f.gid = f.gid;
             }
             None => {
                 files_by_inode.insert(ino, f);
             }
         }
+        if let Some(state_path) = &options.resume {
+            let dirs_read = traversal.stats().dirs_read;
+            if traversal.is_between_dirs() && dirs_read >= last_checkpoint + CHECKPOINT_INTERVAL {
+                write_checkpoint(
+                    state_path,
+                    target,
+                    &traversal.state(),
+                    files_by_inode.values(),
+                )?;
+                last_checkpoint = dirs_read;
+            }
+        }
+    }
+    // Save the finished traversal too, so that if we're interrupted while comparing we needn't
+    // traverse again.
+    if let Some(state_path) = &options.resume {
+        write_checkpoint(
+            state_path,
+            target,
+            &traversal.state(),
+            files_by_inode.values(),
+        )?;
+    }
+    if options.stats {
+        print_traversal_stats(traversal.stats(), options);
+    }
+
+    Ok(files_by_inode.into_values().collect())
+}
+
+fn main() -> Result<()> {
+    let mut options = Options::from_args()?;
+    set_max_open_files(options.max_open_files);
+    if let Some(bytes_per_sec) = options.io_limit {
+        set_io_limit(bytes_per_sec);
+    }
+    if let Some(budget) = options.max_runtime {
+        set_deadline(budget);
+    }
+    if options.progress_json {
+        enable_progress(open_progress(&options)?);
+    }
+    if options.explain {
+        enable_explain();
+    }
+    let errors = ErrorLog::default();
+
+    // Settle the form of the root path once and for all, since every path we find is built on it.
+    // Protected directories need to be in the same form to recognise the paths under them.
+    if options.path_style == PathStyle::Canonical {
+        if let Some(target) = &options.target {
+            options.target = Some(
+                fs::canonicalize(target)
+                    .with_context(|| format!("Failed to canonicalize PATH {:?}", target))?,
+            );
+        }
+        for dir in &mut options.protect {
+            *dir = fs::canonicalize(&*dir)
+                .with_context(|| format!("Failed to canonicalize --protect {:?}", dir))?;
+        }
+    } else if options.normalize_paths {
+        if let Some(target) = &mut options.target {
+            *target = normalise_path(target);
+        }
+        for dir in &mut options.protect {
+            *dir = normalise_path(dir);
+        }
+        if let Some(dir) = &mut options.relative_to {
+            *dir = normalise_path(dir);
+        }
     }
 
-    // Now group our consolidated list of files on disk by size.
-    let mut dupes_by_size: HashMap<u64, Vec<DedupFile>> = HashMap::new();
-    for f in files_by_inode.into_values() {
-        match dupes_by_size.get_mut(&f.size) {
-            Some(existing_f) => {
-                existing_f.push(f);
+    // Comparing saved reports doesn't involve the filesystem (beyond reading them) at all.
+    if let Some((old_path, new_path)) = &options.diff {
+        let diff = diff_reports(
+            &read_report(old_path, options.normalize_paths)?,
+            &read_report(new_path, options.normalize_paths)?,
+        );
+        return write_report_diff(&mut io::stdout(), &diff).context("Failed to write report diff");
+    }
+
+    // Nor does checking that a saved report still holds, beyond reading the files it lists.
+    if let Some(report_path) = &options.verify {
+        let verification = verify_report(
+            read_report(report_path, options.normalize_paths)?,
+            CompareOptions::new(&options),
+        );
+        write_verification(&mut io::stdout(), &verification)
+            .context("Failed to write verification")?;
+        if verification.failures() > 0 {
+            bail!(
+                "{} group(s) in {:?} failed to verify",
+                verification.failures(),
+                report_path
+            );
+        }
+        return Ok(());
+    }
+
+    // Or finding where two particular files differ.
+    if let Some((path1, path2)) = &options.diff_offsets {
+        let size = |path: &Path| {
+            fs::metadata(path)
+                .map(|m| m.len())
+                .with_context(|| format!("Failed to read {:?}", path))
+        };
+        let (size1, size2) = (size(path1)?, size(path2)?);
+        let difference = first_file_difference(path1, path2, CompareOptions::new(&options))
+            .map_err(|e| anyhow!("Failed to compare {:?}: {}", e.path, e.message))?;
+        match difference {
+            Some(offset) if size1 != size2 => println!(
+                "First difference at byte {} (sizes differ: {} and {} bytes)",
+                offset, size1, size2
+            ),
+            Some(offset) => println!("First difference at byte {}", offset),
+            None => println!("Identical"),
+        }
+        return Ok(());
+    }
+
+    // Files from different dumps may have come from different machines, where the same device and
+    // inode numbers mean nothing, so we don't try to consolidate them.
+    let dumped_files = match &options.merge_indexes {
+        Some(dump_paths) => {
+            let mut files = Vec::new();
+            for dump_path in dump_paths {
+                match read_dump(dump_path, options.keep_going) {
+                    Ok(mut dumped) => {
+                        if options.normalize_paths {
+                            normalise_dump_paths(&mut dumped);
+                        }
+                        files.append(&mut dumped);
+                    }
+                    Err(e) => keep_going_or_fail(e, options.keep_going)?,
+                }
             }
-            None => {
-                dupes_by_size.insert(f.size, vec![f]);
+            Some(files)
+        }
+        None => None,
+    };
+
+    // Size limits given as percentiles or multiples of the mean need a first pass over every
+    // file's size.
+    if options.min_size_relative.is_some() || options.max_size_relative.is_some() {
+        let sizes = match &dumped_files {
+            Some(files) => files.iter().map(|df| df.size).collect(),
+            None => sizes_in_tree(
+                options
+                    .target
+                    .as_deref()
+                    .expect("Failed to read PATH despite clap requirement"),
+                &options,
+                &errors,
+            ),
+        };
+        options.resolve_relative_sizes(sizes);
+    }
+    let options = options;
+
+    // Paths are canonical (absolute) by default, which can be verbose.  Trim them if asked,
+    // putting the base in the same form as the paths.  This is purely presentational, so it's the
+    // very last thing we do before writing output.
+    let relative_base = match (&options.relative_to, options.path_style) {
+        (None, _) if !options.cwd_relative => None,
+        (None, PathStyle::Canonical) => {
+            Some(canonical_cwd().context("Failed to determine current directory")?)
+        }
+        (None, PathStyle::AsGiven) => {
+            Some(env::current_dir().context("Failed to determine current directory")?)
+        }
+        (Some(dir), PathStyle::Canonical) => Some(
+            fs::canonicalize(dir)
+                .with_context(|| format!("Failed to canonicalize --relative-to {:?}", dir))?,
+        ),
+        (Some(dir), PathStyle::AsGiven) => Some(dir.clone()),
+    };
+    let tidy_group = |mut group: Vec<DedupFile>| {
+        if let Some(base) = &relative_base {
+            relativise_group(&mut group, base);
+        }
+        group
+    };
+
+    // With `--only-hardlinkable`, we must be able to replace the files in a group with hard links
+    // to be worth reporting it.
+    let credentials = if options.only_hardlinkable {
+        Some(Credentials::current().context("Failed to determine the current user")?)
+    } else {
+        None
+    };
+    let is_reportable =
+        |group: &Vec<DedupFile>| is_reportable(group, options.min_group, credentials.as_ref());
+    // With `--snapshot-aware`, copies of a file in snapshots are expected rather than worth
+    // reporting, so we leave them out, counting them (and the groups of nothing else) for a note.
+    let snapshot_copies = Cell::new(0);
+    let snapshot_groups = Cell::new(0);
+    let drop_snapshots = |group: Vec<DedupFile>| match &options.snapshot_roots {
+        Some(patterns) => {
+            let (group, dropped) = drop_snapshot_copies(group, patterns);
+            snapshot_copies.set(snapshot_copies.get() + dropped);
+            if dropped > 0 && group.len() < 2 {
+                snapshot_groups.set(snapshot_groups.get() + 1);
             }
+            group
+        }
+        None => group,
+    };
+    let note_snapshots =
+        || {
+            if options.snapshot_roots.is_some() {
+                eprintln!(
+                "Note: left out {} snapshot cop{} of other files, and {} group(s) of nothing else",
+                snapshot_copies.get(),
+                if snapshot_copies.get() == 1 { "y" } else { "ies" },
+                snapshot_groups.get()
+            );
+            }
+        };
+    // With `--skip-hashes`, content that's already known (e.g. archived) isn't worth reporting
+    // either.
+    let known_hashes = match &options.skip_hashes {
+        Some(path) => Some(read_hash_list(path)?),
+        None => None,
+    };
+    let drop_known = |group: Vec<DedupFile>| match &known_hashes {
+        Some(known) if !group.is_empty() => {
+            let exact = options.compares_every_byte(group[0].size);
+            drop_known_content(group, known, exact, &errors)
+        }
+        _ => group,
+    };
+    // With `--skip-open-files`, files being written by other processes are too risky to act on.
+    // We look for them once, when the first group of duplicates is found, and list each we leave
+    // out, with its path shown like those in the report.  Files without a usable inode number
+    // can't be recognised.
+    let open_files = OnceCell::new();
+    let left_open = RefCell::new(Vec::new());
+    let drop_open = |group: Vec<DedupFile>| {
+        if !options.skip_open_files {
+            return group;
+        }
+        let open = open_files.get_or_init(files_open_for_writing);
+        let (closed, open): (Vec<DedupFile>, Vec<DedupFile>) = group
+            .into_iter()
+            .partition(|df| df.inode == 0 || !open.contains(&(df.device, df.inode)));
+        left_open
+            .borrow_mut()
+            .extend(open.into_iter().map(|df| df.paths[0].clone()));
+        closed
+    };
+    let note_open_files = || {
+        let left_open = left_open.borrow();
+        if !left_open.is_empty() {
+            eprintln!(
+                "Note: left out {} file(s) open for writing by other processes:",
+                left_open.len()
+            );
+            for path in left_open.iter() {
+                match &relative_base {
+                    Some(base) => eprintln!("  {}", make_relative(path, base).display()),
+                    None => eprintln!("  {}", path.display()),
+                }
+            }
+        }
+    };
+    // Every group of duplicates, however it was found, goes through the same filters, and those
+    // that make it are put in order and filled in for the report.
+    let finalise_group = |group: Vec<DedupFile>| {
+        let mut group = drop_open(drop_known(drop_snapshots(group)));
+        if !is_reportable(&group) {
+            return None;
+        }
+        sort_within_group(
+            &mut group,
+            options.sort_within_groups.unwrap_or(GroupOrder::Path),
+        );
+        mark_protected(&mut group, &options.protect);
+        add_chunk_hashes(&mut group, &options, &errors);
+        Some(group)
+    };
+
+    if options.low_memory {
+        let target = options
+            .target
+            .as_deref()
+            .expect("Failed to read PATH despite clap requirement");
+        find_dupes_low_memory(target, &options, &errors, |group| {
+            finalise_group(group).map(tidy_group)
+        })?;
+        note_snapshots();
+        note_open_files();
+        if options.find_broken_symlinks {
+            list_broken_symlinks(relative_base.as_deref())?;
         }
+        return Ok(());
     }
 
+    // Either merge existing file dumps, or traverse the filesystem.
+    let mut files: Vec<DedupFile> = match dumped_files {
+        Some(mut files) => {
+            files.retain(|df| {
+                df.size >= options.min_size
+                    && options.max_size.is_none_or(|max| df.size <= max)
+                    && !(options.exclude_empty_files && df.size == 0)
+                    && options
+                        .modified_cutoff
+                        .is_none_or(|(secs, _)| df.mtime <= secs)
+                    && options.owner.is_none_or(|uid| df.uid == uid)
+            });
+            files
+        }
+        None => {
+            let target = options
+                .target
+                .as_deref()
+                .expect("Failed to read PATH despite clap requirement");
+            find_files(target, &options, &errors)?
+        }
+    };
+
+    // To help choose size limits, we can stop here and just say how big the files are.
+    if options.size_histogram {
+        write_size_histogram(&mut io::stdout(), &files).context("Failed to write histogram")?;
+        warn_scan_errors(&errors, &options);
+        finish_progress();
+        return Ok(());
+    }
+    // Similarly, to see whether a scan is worth finishing, we can stop once we know which files
+    // we'd compare.
+    if options.probe {
+        let shortlist = shortlist_by_size(partition_files(files, &options), &options, &errors);
+        write_probe(&mut io::stdout(), &shortlist, &options).context("Failed to write probe")?;
+        warn_scan_errors(&errors, &options);
+        finish_progress();
+        return Ok(());
+    }
+    set_phase(Phase::Compare);
+
+    // Record everything we found, for merging with dumps from elsewhere later.  Hashing every
+    // file means reading all of them, so this is much slower than finding duplicates alone.  The
+    // records are in order of path, so that dumps of the same tree are the same.
+    if let Some(dump_path) = &options.dump_files {
+        files.sort_by(|f1, f2| f1.paths.cmp(&f2.paths));
+        let mut dump_dest = BufWriter::new(
+            File::create(dump_path)
+                .with_context(|| format!("Failed to create dump file {:?}", dump_path))?,
+        );
+        for f in &mut files {
+            f.hash = hash_file(&f.paths[0]).ok();
+            write_file_record(&mut dump_dest, f)
+                .with_context(|| format!("Failed to write dump file {:?}", dump_path))?;
+        }
+        dump_dest
+            .flush()
+            .with_context(|| format!("Failed to write dump file {:?}", dump_path))?;
+    }
+
+    // Files of different sizes are never duplicates, but the smaller may be a truncated copy of
+    // the larger, which we look for now if asked, while we have the whole list.
+    let prefixes = if options.find_prefixes {
+        find_prefixes(&files, &errors)
+    } else {
+        Vec::new()
+    };
+
+    // Now group our consolidated list of files on disk by size.  If we only care about duplicates
+    // within the same directory (or with the same extension), partition the files first and group
+    // each partition by size separately.
+    let all_files = if options.include_unique {
+        files.clone()
+    } else {
+        Vec::new()
+    };
+    let partitions = partition_files(files, &options);
+
     // Finally, check the list of files by size to find which are actually the same data.
-    let shortlist: Vec<Vec<DedupFile>> = dupes_by_size
-        .into_values()
-        .filter(|grp| grp.len() > 1)
-        .collect();
-    let dupes_by_content: Vec<Vec<DedupFile>> = group_by_content(shortlist).collect();
+    // Ignoring whitespace means files of different sizes may match, so in that case we skip
+    // grouping by size and compare normalised content instead.
+    let mut dupes_by_content: Vec<Vec<DedupFile>> = if options.ignore_whitespace {
+        partitions
+            .into_iter()
+            .map(|p| exclude_signature(p, &options, &errors))
+            .map(|mut p| {
+                // Each file is read in turn, from the start of the list.
+                if options.inode_order {
+                    p.sort_by_key(|df| (df.device, df.inode));
+                }
+                p
+            })
+            .flat_map(|p| group_by_normalised_content(p, &options, &errors))
+            .flat_map(|grp| split_by_key(grp, &options))
+            .filter_map(finalise_group)
+            .collect()
+    } else {
+        let shortlist = shortlist_by_size(partitions, &options, &errors);
+        group_by_content(shortlist, &options, &errors)
+            .flat_map(|grp| split_by_key(grp, &options))
+            .filter_map(finalise_group)
+            .collect()
+    };
+    // The files in each group are in order, and so are the groups, by their first paths (though
+    // `--rank` reorders them later).
+    dupes_by_content.sort_by(|g1, g2| g1[0].paths.cmp(&g2[0].paths));
+
+    // The index covers the same groups, plus (if asked) every file that isn't a duplicate as a
+    // group of its own.  Build it now, while the paths can still be opened for hashing.
+    let index = options.emit_index.as_deref().map(|index_path| {
+        let dupes: HashSet<FileIdentity> = dupes_by_content
+            .iter()
+            .flatten()
+            .map(DedupFile::identity)
+            .collect();
+        // In order of path, like the groups, so that the index is the same each run.
+        let mut uniques: Vec<Vec<DedupFile>> = all_files
+            .into_iter()
+            .filter(|df| !dupes.contains(&df.identity()))
+            .map(|mut df| {
+                df.paths.sort();
+                vec![df]
+            })
+            .collect();
+        uniques.sort_by(|g1, g2| g1[0].paths.cmp(&g2[0].paths));
+        (
+            index_path,
+            build_index(dupes_by_content.iter().chain(&uniques)),
+        )
+    });
 
-    // Write results to stdout as HTML.
-    let mut dest = io::stdout();
-    write_dupes_html(&mut dest, &dupes_by_content);
+    // Savings are counted by directory below the root, so this needs the paths as they were found,
+    // but the directories themselves are shown like any other path.
+    let dir_savings = options.savings_by_dir.map(|depth| {
+        let mut rows = savings_by_dir(
+            &dupes_by_content,
+            options.keep.unwrap_or(KeepPolicy::First),
+            options.target.as_deref(),
+            depth,
+        );
+        if let Some(base) = &relative_base {
+            for (dir, _, _) in &mut rows {
+                *dir = make_relative(dir, base);
+            }
+        }
+        rows
+    });
+
+    // Whole directories that are copies of others are found from the files in them, so as for
+    // savings by directory, we need the paths as they were found.
+    let dir_dupes = options.find_dir_dupes.then(|| {
+        let mut matches = find_dir_dupes(&dupes_by_content, options.target.as_deref(), &errors);
+        if let Some(base) = &relative_base {
+            for m in &mut matches {
+                m.copy = make_relative(&m.copy, base);
+                m.original = make_relative(&m.original, base);
+            }
+        }
+        matches
+    });
 
+    // Write the report, biggest savings first if we're ranking groups.
+    let group_count = if options.rank {
+        sort_by_reclaimable(&mut dupes_by_content);
+        Some(dupes_by_content.len())
+    } else {
+        None
+    };
+
+    // The review directory is numbered like the report, but its symlinks must lead to the paths
+    // as they were found.
+    if let Some(review_dir) = &options.review_dir {
+        write_review_dir(review_dir, &dupes_by_content)
+            .with_context(|| format!("Failed to write review directory {:?}", review_dir))?;
+    }
+
+    let dupes_by_content: Vec<Vec<DedupFile>> =
+        dupes_by_content.into_iter().map(tidy_group).collect();
+    let mut dest = open_report(&options)?;
+    if options.group_output_by_dir {
+        let listing = list_by_dir(&dupes_by_content, options.keep.unwrap_or(KeepPolicy::First));
+        write_dir_listing(&mut dest, &listing, &errors, &options)?;
+    } else {
+        write_report(&mut dest, &dupes_by_content, group_count, &errors, &options)?;
+    }
+    warn_scan_errors(&errors, &options);
+    note_snapshots();
+    note_open_files();
+
+    if options.by_extension {
+        write_extension_summary(&mut io::stderr(), &dupes_by_content, &options)
+            .context("Failed to write summary by extension")?;
+    }
+    if let Some(rows) = &dir_savings {
+        write_savings_by_dir(&mut io::stderr(), rows)
+            .context("Failed to write savings by directory")?;
+    }
+    if options.find_prefixes {
+        write_prefixes(&mut io::stderr(), &prefixes).context("Failed to write prefixes")?;
+    }
+    if let Some(matches) = &dir_dupes {
+        write_dir_dupes(&mut io::stderr(), matches)
+            .context("Failed to write copies of directories")?;
+    }
+    if options.find_broken_symlinks {
+        list_broken_symlinks(relative_base.as_deref())?;
+    }
+
+    if let Some((index_path, mut entries)) = index {
+        if let Some(base) = &relative_base {
+            for entry in &mut entries {
+                relativise_paths(&mut entry.paths, base);
+            }
+        }
+        let mut index_dest = BufWriter::new(
+            File::create(index_path)
+                .with_context(|| format!("Failed to create index file {:?}", index_path))?,
+        );
+        write_index(&mut index_dest, &entries)
+            .with_context(|| format!("Failed to write index file {:?}", index_path))?;
+    }
+
+    // The run is complete, so there's nothing left to resume.
+    if let Some(state_path) = &options.resume {
+        fs::remove_file(state_path)
+            .with_context(|| format!("Failed to remove checkpoint {:?}", state_path))?;
+    }
+
+    finish_progress();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{parse_json, JsonValue};
+    use crate::keep::select_representative;
+    use crate::summary::reclaimable_bytes;
+    use crate::testing::{fake_file, options, Mount, TempDir};
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    #[test]
+    fn keeper_does_not_depend_on_the_order_files_were_found() {
+        let files = [
+            fake_file(&["/t/b"], 10, 1),
+            fake_file(&["/t/c", "/t/a2"], 10, 2),
+            fake_file(&["/t/a"], 10, 3),
+        ];
+        for order in [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ] {
+            let mut group: Vec<DedupFile> = order.iter().map(|&i| files[i].clone()).collect();
+            sort_within_group(&mut group, GroupOrder::Path);
+            let kept = &group[select_representative(&group, KeepPolicy::First)];
+            assert_eq!(kept.paths, [PathBuf::from("/t/a")], "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn partitions_are_in_order_of_directory_and_extension() {
+        let files = [
+            fake_file(&["/t/b/x.JPG"], 10, 1),
+            fake_file(&["/t/a/y.png"], 10, 2),
+            fake_file(&["/t/a/z.jpg"], 10, 3),
+            fake_file(&["/t/b/w.png"], 10, 4),
+            fake_file(&["/t/a/v"], 10, 5),
+        ];
+        let options = options(&["--per-directory", "--same-extension", "/t"]);
+        for order in [[0, 1, 2, 3, 4], [4, 3, 2, 1, 0], [2, 0, 4, 1, 3]] {
+            let files: Vec<DedupFile> = order.iter().map(|&i| files[i].clone()).collect();
+            let inodes: Vec<Vec<u64>> = partition_files(files, &options)
+                .iter()
+                .map(|p| p.iter().map(|df| df.inode).collect())
+                .collect();
+            assert_eq!(inodes, [[5], [3], [2], [1], [4]], "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn mtime_order_lists_a_group_oldest_first() {
+        let file = |path, mtime| DedupFile {
+            mtime,
+            ..fake_file(&[path], 10, 1)
+        };
+        let mut group = vec![
+            file("/t/a", 300),
+            file("/t/b", 100),
+            file("/t/d", 200),
+            file("/t/c", 100),
+        ];
+        let order = options(&["--sort-within-groups", "mtime", "/t"]).sort_within_groups;
+        sort_within_group(&mut group, order.expect("No order parsed"));
+        let paths: Vec<&Path> = group.iter().map(|df| df.paths[0].as_path()).collect();
+        // Files modified at the same time are in order of path.
+        assert_eq!(paths, ["/t/b", "/t/c", "/t/d", "/t/a"].map(Path::new));
+        sort_within_group(&mut group, GroupOrder::Path);
+        let paths: Vec<&Path> = group.iter().map(|df| df.paths[0].as_path()).collect();
+        assert_eq!(paths, ["/t/a", "/t/b", "/t/c", "/t/d"].map(Path::new));
+    }
+
+    #[test]
+    fn groups_filtered_down_to_one_file_are_dropped() {
+        let group = vec![
+            fake_file(&["/home/a"], 10, 1),
+            fake_file(&["/.snapshots/1/home/a"], 10, 2),
+        ];
+        assert!(is_reportable(&group, 2, None));
+        let (group, dropped) = drop_snapshot_copies(group, &[".snapshots/*".to_string()]);
+        assert_eq!(dropped, 1);
+        assert!(!is_reportable(&group, 2, None));
+        assert!(!is_reportable(&[], 2, None));
+    }
+
+    #[test]
+    fn inode_order_has_the_files_taken_in_order_of_device_and_inode() {
+        let file = |device, inode| DedupFile {
+            device,
+            ..fake_file(&["/f"], 10, inode)
+        };
+        let mut shortlist = vec![
+            vec![file(1, 40), file(2, 1), file(1, 7)],
+            vec![file(1, 90), file(1, 3)],
+            vec![file(1, 12), file(1, 50)],
+        ];
+        sort_by_inode(&mut shortlist);
+        // Groups and the files in each are taken from the end, as the content phase takes them.
+        let mut taken = Vec::new();
+        while let Some(mut group) = shortlist.pop() {
+            while let Some(df) = group.pop() {
+                taken.push((df.device, df.inode));
+            }
+        }
+        assert_eq!(
+            taken,
+            [(1, 3), (1, 90), (1, 7), (1, 40), (2, 1), (1, 12), (1, 50)]
+        );
+    }
+
+    #[test]
+    fn groups_smaller_than_min_group_are_dropped() {
+        let group: Vec<DedupFile> = (1..=3).map(|i| fake_file(&["/f"], 10, i)).collect();
+        assert!(is_reportable(&group, 3, None));
+        assert!(!is_reportable(&group, 4, None));
+        assert!(!is_reportable(&group[..1], 0, None));
+    }
+
+    #[test]
+    fn only_hardlinkable_drops_groups_spanning_devices() {
+        let credentials = Credentials::current().unwrap();
+        let mut group: Vec<DedupFile> = (1..=3).map(|i| fake_file(&["/f"], 10, i)).collect();
+        for df in &mut group {
+            df.uid = credentials.uid();
+            df.mode = 0o600;
+        }
+        assert!(is_reportable(&group, 2, Some(&credentials)));
+        group[2].device = 2;
+        assert!(!is_reportable(&group, 2, Some(&credentials)));
+        assert!(is_reportable(&group, 2, None));
+    }
+
+    // The paths of each file found below `root`, relative to it, with these options.
+    fn files_found(root: &Path, args: &[&str]) -> Vec<Vec<PathBuf>> {
+        let options = options(&[args, &["--min-size", "1", root.to_str().unwrap()]].concat());
+        let mut files: Vec<Vec<PathBuf>> = find_files(root, &options, &ErrorLog::default())
+            .unwrap()
+            .into_iter()
+            .map(|df| {
+                let mut paths: Vec<PathBuf> = df
+                    .paths
+                    .iter()
+                    .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+                    .collect();
+                paths.sort();
+                paths
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn a_file_reached_through_a_symlinked_directory_is_one_file_with_two_paths() {
+        let dir = TempDir::new();
+        dir.write("data/a", "same");
+        symlink(dir.path().join("data"), dir.path().join("view")).unwrap();
+        assert_eq!(
+            files_found(dir.path(), &["--symlinks", "follow"]),
+            [["data/a", "view/a"].map(PathBuf::from)]
+        );
+    }
+
+    // A bind mount shows the same files (the same device and inode numbers) under another path, so
+    // they're consolidated just like hard links.  Only root can bind-mount, so for anyone else
+    // there's nothing to test.
+    #[test]
+    fn a_file_under_a_bind_mount_is_one_file_with_two_paths() {
+        let dir = TempDir::new();
+        dir.write("data/a", "same");
+        dir.write("data/b", "same");
+        let view = dir.path().join("view");
+        fs::create_dir(&view).unwrap();
+        let Some(mount) = Mount::bind(&dir.path().join("data"), &view) else {
+            eprintln!("Skipping test: can't bind-mount as this user");
+            return;
+        };
+        let found = files_found(dir.path(), &[]);
+        drop(mount);
+        assert_eq!(
+            found,
+            [
+                ["data/a", "view/a"].map(PathBuf::from),
+                ["data/b", "view/b"].map(PathBuf::from)
+            ]
+        );
+    }
+
+    #[test]
+    fn the_report_can_go_to_an_inherited_pipe() {
+        let groups = vec![vec![
+            fake_file(&["/d/a"], 10, 1),
+            fake_file(&["/d/b"], 10, 2),
+        ]];
+        let (mut reader, writer) = io::pipe().unwrap();
+        let fd = writer.as_raw_fd().to_string();
+        let options = options(&["--output-fd", &fd, "--format", "json", "/"]);
+        let errors = ErrorLog::default();
+
+        let mut dest = open_report(&options).unwrap();
+        write_report(&mut dest, &groups, None, &errors, &options).unwrap();
+        drop(dest);
+        drop(writer);
+        let mut piped = String::new();
+        reader.read_to_string(&mut piped).unwrap();
+
+        let mut expected = Vec::new();
+        write_report(&mut expected, &groups, None, &errors, &options).unwrap();
+        assert_eq!(piped.as_bytes(), expected);
+        assert!(piped.contains("/d/b"), "{}", piped);
+    }
+
+    #[test]
+    fn resuming_an_interrupted_traversal_finds_the_same_files() {
+        let dir = TempDir::new();
+        let tree = dir.path().join("tree");
+        for i in 0..6 {
+            for j in 0..3 {
+                dir.write(&format!("tree/d{}/e{}/f", i, j), "x");
+            }
+        }
+        fs::hard_link(tree.join("d0/e0/f"), tree.join("d5/link")).unwrap();
+        let uninterrupted = files_found(&tree, &[]);
+
+        // Stop once a few directories have been read, and save where we'd got to.
+        let state_path = dir.path().join("state");
+        let first_run = options(&["--min-size", "1", tree.to_str().unwrap()]);
+        let mut traversal = group_by_inode(&tree, &first_run, &ErrorLog::default());
+        let mut files_so_far = Vec::new();
+        while traversal.stats().dirs_read < 5 || !traversal.is_between_dirs() {
+            files_so_far.extend(traversal.next());
+        }
+        assert!(!files_so_far.is_empty());
+        write_checkpoint(&state_path, &tree, &traversal.state(), &files_so_far).unwrap();
+
+        let state = state_path.to_str().unwrap();
+        assert_eq!(files_found(&tree, &["--resume", state]), uninterrupted);
+        // A checkpoint for another root is refused.
+        let other = options(&["--resume", state, dir.path().to_str().unwrap()]);
+        assert!(find_files(dir.path(), &other, &ErrorLog::default()).is_err());
+    }
+
+    #[test]
+    fn options_built_in_code_drive_the_whole_search() {
+        let dir = TempDir::new();
+        dir.write("a", "same");
+        dir.write("sub/b", "same");
+        dir.write("c", "diff");
+        dir.write("big", "same, but bigger");
+        let options = Options {
+            target: Some(dir.path().to_path_buf()),
+            min_size: 1,
+            max_size: Some(10),
+            ..Options::default()
+        };
+        let errors = ErrorLog::default();
+
+        let files = find_files(dir.path(), &options, &errors).unwrap();
+        assert_eq!(files.len(), 3);
+        let shortlist = shortlist_by_size(partition_files(files, &options), &options, &errors);
+        let mut groups: Vec<Vec<DedupFile>> = group_by_content(shortlist, &options, &errors)
+            .filter(|group| is_reportable(group, options.min_group, None))
+            .collect();
+        assert_eq!(groups.len(), 1);
+        sort_within_group(&mut groups[0], GroupOrder::Path);
+        let paths: Vec<&PathBuf> = groups[0].iter().flat_map(|df| &df.paths).collect();
+        assert_eq!(paths, [&dir.path().join("a"), &dir.path().join("sub/b")]);
+        assert!(errors.take().is_empty());
+    }
+
+    #[test]
+    fn sparse_files_are_told_apart_by_alloc_size() {
+        let dir = TempDir::new();
+        let sparse = dir.path().join("sparse");
+        File::create(&sparse).unwrap().set_len(1 << 20).unwrap();
+        dir.write("dense", vec![0; 1 << 20]);
+        let root = dir.path().to_str().unwrap();
+        let errors = ErrorLog::default();
+
+        let files = find_files(dir.path(), &options(&["--min-size", "1", root]), &errors).unwrap();
+        assert_eq!(files.len(), 2);
+        if files.iter().all(|df| df.alloc_size == df.size) {
+            eprintln!("Skipping test: {} doesn't support sparse files", root);
+            return;
+        }
+        assert_eq!(group_by_size(files.clone(), false).len(), 1);
+        assert!(group_by_size(files, true).is_empty());
+
+        // The size limits apply to the space allocated, so the sparse file is too small.
+        let options = options(&["--min-size", "524288", "--by-alloc-size", root]);
+        let files = find_files(dir.path(), &options, &errors).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].paths[0].ends_with("dense"));
+        assert!(errors.take().is_empty());
+    }
+
+    // The groups of duplicates found below `root`, each as the sorted paths relative to it, with
+    // these options.
+    fn dupes_found(root: &Path, args: &[&str]) -> Vec<Vec<PathBuf>> {
+        let options = options(&[args, &["--min-size", "1", root.to_str().unwrap()]].concat());
+        let errors = ErrorLog::default();
+        let files = find_files(root, &options, &errors).unwrap();
+        let shortlist = shortlist_by_size(partition_files(files, &options), &options, &errors);
+        let mut groups: Vec<Vec<PathBuf>> = group_by_content(shortlist, &options, &errors)
+            .map(|group| {
+                let mut paths: Vec<PathBuf> = group
+                    .iter()
+                    .map(|df| df.paths[0].strip_prefix(root).unwrap().to_path_buf())
+                    .collect();
+                paths.sort();
+                paths
+            })
+            .collect();
+        groups.sort();
+        assert!(errors.take().is_empty());
+        groups
+    }
+
+    #[test]
+    fn the_json_report_clusters_a_group_by_inode() {
+        let dir = TempDir::new();
+        let a = dir.write("a", "same content");
+        fs::hard_link(&a, dir.path().join("a-link")).unwrap();
+        dir.write("b", "same content");
+        let options = options(&[
+            "--format",
+            "json",
+            "--min-size",
+            "1",
+            dir.path().to_str().unwrap(),
+        ]);
+        let errors = ErrorLog::default();
+        let files = find_files(dir.path(), &options, &errors).unwrap();
+        let shortlist = shortlist_by_size(partition_files(files, &options), &options, &errors);
+        let mut dest = Vec::new();
+        let groups = group_by_content(shortlist, &options, &errors);
+        write_dupes_json(&mut dest, groups, None, None, &options).unwrap();
+
+        let report = parse_json(std::str::from_utf8(&dest).unwrap()).unwrap();
+        let groups = report.get("groups").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(groups.len(), 1);
+        let mut clusters: Vec<(Vec<PathBuf>, u64, u64)> = groups[0]
+            .get("files")
+            .and_then(JsonValue::as_array)
+            .unwrap()
+            .iter()
+            .map(|file| {
+                let field = |key| file.get(key).and_then(JsonValue::as_u64).unwrap();
+                let mut paths: Vec<PathBuf> = file
+                    .get("paths")
+                    .and_then(JsonValue::as_array)
+                    .unwrap()
+                    .iter()
+                    .map(|path| path.as_path().unwrap())
+                    .collect();
+                paths.sort();
+                assert_eq!(field("device"), fs::metadata(&paths[0]).unwrap().dev());
+                (paths, field("inode"), field("links"))
+            })
+            .collect();
+        clusters.sort();
+        let inode = |name| fs::metadata(dir.path().join(name)).unwrap().ino();
+        // The hard links are one file, with both paths; the copy is a file of its own.
+        assert_eq!(
+            clusters,
+            [
+                (vec![a.clone(), dir.path().join("a-link")], inode("a"), 2),
+                (vec![dir.path().join("b")], inode("b"), 1),
+            ]
+        );
+    }
+
+    // On a case-insensitive volume the other spellings of a name lead to the same file, as these
+    // links do here.
+    #[test]
+    fn a_file_under_case_variant_names_is_one_file_and_not_its_own_duplicate() {
+        let dir = TempDir::new();
+        let photo = dir.write("photo.jpg", "pixels");
+        fs::hard_link(&photo, dir.path().join("PHOTO.JPG")).unwrap();
+        symlink("photo.jpg", dir.path().join("Photo.JPG")).unwrap();
+        let args = ["--symlinks", "follow"];
+        assert_eq!(
+            files_found(dir.path(), &args),
+            [["PHOTO.JPG", "Photo.JPG", "photo.jpg"].map(PathBuf::from)]
+        );
+        assert!(dupes_found(dir.path(), &args).is_empty());
+    }
+
+    #[test]
+    fn hard_links_in_a_group_are_not_counted_as_space_to_reclaim() {
+        let dir = TempDir::new();
+        let a = dir.write("a", [7; 1000]);
+        fs::hard_link(&a, dir.path().join("a-link")).unwrap();
+        fs::hard_link(&a, dir.path().join("a-link2")).unwrap();
+        dir.write("b", [7; 1000]);
+        dir.write("c", [7; 1000]);
+        let options = options(&["--min-size", "1", dir.path().to_str().unwrap()]);
+        let errors = ErrorLog::default();
+        let files = find_files(dir.path(), &options, &errors).unwrap();
+        let shortlist = shortlist_by_size(vec![files], &options, &errors);
+        let groups: Vec<Vec<DedupFile>> = group_by_content(shortlist, &options, &errors).collect();
+        assert_eq!(groups.len(), 1);
+        // Five paths, but three files, of which one stays.
+        assert_eq!(groups[0].iter().map(|df| df.paths.len()).sum::<usize>(), 5);
+        assert_eq!(reclaimable_bytes(&groups[0]), 2000);
+    }
+
+    #[test]
+    fn same_extension_only_groups_files_whose_extensions_match() {
+        let dir = TempDir::new();
+        for name in ["a.bin", "b.BIN", "c.dat", "d", "e"] {
+            dir.write(name, [0; 64]);
+        }
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            dupes_found(dir.path(), &[]),
+            [paths(&["a.bin", "b.BIN", "c.dat", "d", "e"])]
+        );
+        assert_eq!(
+            dupes_found(dir.path(), &["--same-extension"]),
+            [paths(&["a.bin", "b.BIN"]), paths(&["d", "e"])]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::io_limit::throttle;
+use crate::progress;
+
+// Comfortably below the usual soft RLIMIT_NOFILE of 1024, leaving room for stdio, output files and
+// whatever else we have open.
+pub const DEFAULT_MAX_OPEN_FILES: usize = 256;
+
+static MAX_OPEN_FILES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_OPEN_FILES);
+static OPEN_FILES: Mutex<usize> = Mutex::new(0);
+static FILE_CLOSED: Condvar = Condvar::new();
+
+// Set the limit on files opened through `open_limited` at once.  Comparisons hold two files open
+// at a time, so a single thread needs a limit of at least two to make progress.
+pub fn set_max_open_files(max: usize) {
+    MAX_OPEN_FILES.store(max.max(2), Ordering::SeqCst);
+}
+
+// A file opened through `open_limited`.  Its slot is given back when it's dropped.  Since all
+// reading of file content for comparison and hashing goes through here, so does any `--io-limit`.
+pub struct LimitedFile {
+    file: File,
+}
+
+impl Read for LimitedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_count = self.file.read(buf)?;
+        throttle(read_count);
+        progress::bytes_read(read_count);
+        Ok(read_count)
+    }
+}
+
+impl Seek for LimitedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Drop for LimitedFile {
+    fn drop(&mut self) {
+        let mut open = OPEN_FILES.lock().unwrap_or_else(|e| e.into_inner());
+        *open -= 1;
+        FILE_CLOSED.notify_one();
+    }
+}
+
+// Open a file for reading, first waiting until fewer than the maximum number of files are open
+// through here.  This keeps us clear of "too many open files" however many comparisons or hashes
+// are in flight.
+pub fn open_limited(path: &Path) -> io::Result<LimitedFile> {
+    let mut open = OPEN_FILES.lock().unwrap_or_else(|e| e.into_inner());
+    while *open >= MAX_OPEN_FILES.load(Ordering::SeqCst) {
+        open = FILE_CLOSED.wait(open).unwrap_or_else(|e| e.into_inner());
+    }
+    *open += 1;
+    drop(open);
+
+    match File::open(path) {
+        Ok(file) => Ok(LimitedFile { file }),
+        Err(e) => {
+            let mut open = OPEN_FILES.lock().unwrap_or_else(|e| e.into_inner());
+            *open -= 1;
+            FILE_CLOSED.notify_one();
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn more_files_than_the_limit_can_be_opened_one_after_another() {
+        let dir = TempDir::new();
+        let path = dir.write("a", "content");
+        for _ in 0..DEFAULT_MAX_OPEN_FILES * 2 {
+            let mut content = String::new();
+            open_limited(&path)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, "content");
+        }
+    }
+
+    #[test]
+    fn failing_to_open_gives_the_slot_back() {
+        let dir = TempDir::new();
+        let missing = dir.path().join("missing");
+        for _ in 0..DEFAULT_MAX_OPEN_FILES * 2 {
+            assert!(open_limited(&missing).is_err());
+        }
+        assert!(open_limited(&dir.write("a", "")).is_ok());
+    }
+}
@@ -0,0 +1,1691 @@
+// Everything the user can tell us, gathered from the command line and (optionally) a config file.
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{command, Arg, ArgAction, ArgMatches};
+
+use crate::config::{read_config, Config};
+use crate::errors::keep_going_or_fail;
+use crate::group_by_content::SAMPLE_BLOCK_LEN;
+use crate::hardlinkable::Credentials;
+use crate::keep::KeepPolicy;
+use crate::open_files::DEFAULT_MAX_OPEN_FILES;
+use crate::sample::fresh_seed;
+use crate::snapshot::DEFAULT_SNAPSHOT_ROOTS;
+use crate::summary::{mean, percentile};
+
+// How to write the report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Html,
+    Json,
+    Dot,
+    Tree,
+}
+
+impl OutputFormat {
+    // Names as given on the command line or in a config file.
+    pub const NAMES: [&'static str; 4] = ["html", "json", "dot", "tree"];
+
+    pub fn from_name(name: &str) -> Result<OutputFormat> {
+        match name {
+            "html" => Ok(OutputFormat::Html),
+            "json" => Ok(OutputFormat::Json),
+            "dot" => Ok(OutputFormat::Dot),
+            "tree" => Ok(OutputFormat::Tree),
+            _ => bail!("Unknown output format {:?}", name),
+        }
+    }
+}
+
+// How to write the one-line summary for `--count-only`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CountStyle {
+    Tsv,
+    Json,
+    // Just the reclaimable bytes, for `--wasted-bytes`.
+    WastedBytes,
+}
+
+impl CountStyle {
+    pub const NAMES: [&'static str; 2] = ["tsv", "json"];
+
+    pub fn from_name(name: &str) -> Result<CountStyle> {
+        match name {
+            "tsv" => Ok(CountStyle::Tsv),
+            "json" => Ok(CountStyle::Json),
+            _ => bail!("Unknown count style {:?}", name),
+        }
+    }
+}
+
+// What form the paths we find (and report) take.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    // Absolute, with symlinks in the root resolved, so that each file has exactly one path (per
+    // hard link) however it was reached.
+    #[default]
+    Canonical,
+    // Below the root exactly as it was given, relative or otherwise.
+    AsGiven,
+}
+
+impl PathStyle {
+    pub const NAMES: [&'static str; 2] = ["canonical", "as-given"];
+
+    pub fn from_name(name: &str) -> Result<PathStyle> {
+        match name {
+            "canonical" => Ok(PathStyle::Canonical),
+            "as-given" => Ok(PathStyle::AsGiven),
+            _ => bail!("Unknown path style {:?}", name),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PathStyle::Canonical => "canonical",
+            PathStyle::AsGiven => "as-given",
+        }
+    }
+}
+
+// What files must have in common, besides their content, to count as duplicates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupKey {
+    #[default]
+    Content,
+    ContentName,
+    ContentExt,
+    ContentMtime,
+}
+
+impl GroupKey {
+    pub const NAMES: [&'static str; 4] =
+        ["content", "content+name", "content+ext", "content+mtime"];
+
+    pub fn from_name(name: &str) -> Result<GroupKey> {
+        match name {
+            "content" => Ok(GroupKey::Content),
+            "content+name" => Ok(GroupKey::ContentName),
+            "content+ext" => Ok(GroupKey::ContentExt),
+            "content+mtime" => Ok(GroupKey::ContentMtime),
+            _ => bail!("Unknown group key {:?}", name),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GroupKey::Content => "content",
+            GroupKey::ContentName => "content+name",
+            GroupKey::ContentExt => "content+ext",
+            GroupKey::ContentMtime => "content+mtime",
+        }
+    }
+}
+
+// How to order the files in each group of duplicates, with `--sort-within-groups`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupOrder {
+    Path,
+    // Oldest first.
+    Mtime,
+    // Smallest first.  Only files compared ignoring whitespace can differ in size.
+    Size,
+}
+
+impl GroupOrder {
+    pub const NAMES: [&'static str; 3] = ["path", "mtime", "size"];
+
+    pub fn from_name(name: &str) -> Result<GroupOrder> {
+        match name {
+            "path" => Ok(GroupOrder::Path),
+            "mtime" => Ok(GroupOrder::Mtime),
+            "size" => Ok(GroupOrder::Size),
+            _ => bail!("Unknown group order {:?}", name),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GroupOrder::Path => "path",
+            GroupOrder::Mtime => "mtime",
+            GroupOrder::Size => "size",
+        }
+    }
+}
+
+// What to do with symlinks below the root.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    #[default]
+    Skip,
+    Follow,
+    // Follow only those whose targets are under the root.
+    WithinRoot,
+}
+
+impl SymlinkPolicy {
+    pub const NAMES: [&'static str; 3] = ["skip", "follow", "within-root"];
+
+    pub fn from_name(name: &str) -> Result<SymlinkPolicy> {
+        match name {
+            "skip" => Ok(SymlinkPolicy::Skip),
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "within-root" => Ok(SymlinkPolicy::WithinRoot),
+            _ => bail!("Unknown symlink policy {:?}", name),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SymlinkPolicy::Skip => "skip",
+            SymlinkPolicy::Follow => "follow",
+            SymlinkPolicy::WithinRoot => "within-root",
+        }
+    }
+}
+
+// A size limit, either in bytes or relative to the sizes of all the files in the tree: as a
+// percentile of them, or a multiple of their mean.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SizeThreshold {
+    Bytes(u64),
+    Percentile(f64),
+    MeanMultiple(f64),
+}
+
+impl SizeThreshold {
+    // The limit in bytes, given the sizes of every file in the tree.
+    pub fn resolve(self, sizes: &mut [u64]) -> u64 {
+        match self {
+            SizeThreshold::Bytes(bytes) => bytes,
+            SizeThreshold::Percentile(p) => percentile(sizes, p),
+            SizeThreshold::MeanMultiple(multiple) => (mean(sizes) * multiple).round() as u64,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub target: Option<PathBuf>,
+    pub merge_indexes: Option<Vec<PathBuf>>,
+    pub diff: Option<(PathBuf, PathBuf)>,
+    pub verify: Option<PathBuf>,
+    pub diff_offsets: Option<(PathBuf, PathBuf)>,
+    pub min_size: u64,
+    pub max_size: Option<u64>,
+    // If the limits were given relative to the sizes in the tree, these say how, and `min_size`
+    // and `max_size` are meaningless until `resolve_relative_sizes` has been called.
+    pub min_size_relative: Option<SizeThreshold>,
+    pub max_size_relative: Option<SizeThreshold>,
+    pub exclude_empty_files: bool,
+    pub min_group: usize,
+    pub limit: Option<u64>,
+    // When the scan started, as seconds and nanoseconds since the epoch, with `--snapshot`.
+    pub snapshot_start: Option<(i64, i64)>,
+    // With `--min-age`, when files must have been last modified by, in the same form.
+    pub modified_cutoff: Option<(i64, i64)>,
+    // With `--owned-only`, the user (us) whose files are the only ones considered.
+    pub owner: Option<u32>,
+    pub sample_rate: Option<f64>,
+    // Always set if `sample_rate` is, whether or not we were given one.
+    pub shuffle_seed: Option<u64>,
+    pub by_alloc_size: bool,
+    pub no_inode: bool,
+    pub excluded_devices: HashSet<u64>,
+    pub excludes: Vec<String>,
+    pub includes: Vec<String>,
+    pub per_directory: bool,
+    pub same_extension: bool,
+    pub path_style: PathStyle,
+    pub normalize_paths: bool,
+    pub symlinks: SymlinkPolicy,
+    pub strict_containment: bool,
+    pub cwd_relative: bool,
+    pub relative_to: Option<PathBuf>,
+    pub low_memory: bool,
+    // Read candidates in order of inode number, for `--inode-order`.
+    pub inode_order: bool,
+    pub ignore_case_content: bool,
+    pub ignore_whitespace: bool,
+    pub ignore_header: u64,
+    pub ignore_trailer: u64,
+    // Stop comparing after this many bytes (after any header) have matched.
+    pub compare_limit: Option<u64>,
+    pub keep: Option<KeepPolicy>,
+    // Directories whose files are always kept, in the same form as the paths we find.
+    pub protect: Vec<PathBuf>,
+    pub compare_metadata: bool,
+    pub group_key: GroupKey,
+    pub sort_within_groups: Option<GroupOrder>,
+    pub double_hash: bool,
+    // Compare files bigger than this many blocks only in that many, with `--sample-blocks`.
+    pub sample_blocks: Option<u64>,
+    // Report files of the same size as unconfirmed duplicates without comparing them.
+    pub assume_unchanged: bool,
+    // Patterns for where snapshots are, with `--snapshot-aware` (see `snapshot`).
+    pub snapshot_roots: Option<Vec<String>>,
+    pub skip_open_files: bool,
+    pub skip_hashes: Option<PathBuf>,
+    // Leave out files starting with these bytes, for `--exclude-signature`.
+    pub exclude_signature: Option<Vec<u8>>,
+    pub only_hardlinkable: bool,
+    // Digest each chunk of this many bytes in every group of duplicates, for `--chunk-hashes`.
+    pub chunk_hashes: Option<u64>,
+    pub emit_index: Option<PathBuf>,
+    pub include_unique: bool,
+    pub dump_files: Option<PathBuf>,
+    // Where to checkpoint the traversal, and resume it from, with `--resume`.
+    pub resume: Option<PathBuf>,
+    pub max_open_files: usize,
+    pub io_limit: Option<u64>,
+    pub max_runtime: Option<Duration>,
+    pub size_histogram: bool,
+    pub probe: bool,
+    pub savings_by_dir: Option<usize>,
+    pub by_extension: bool,
+    pub find_prefixes: bool,
+    pub find_dir_dupes: bool,
+    pub find_broken_symlinks: bool,
+    // Where to lay out the groups as directories of symlinks, if anywhere.
+    pub review_dir: Option<PathBuf>,
+    pub rank: bool,
+    // Reorganise the report by directory rather than by group (see `by_dir`).
+    pub group_output_by_dir: bool,
+    // Write only the totals rather than the report, if set.
+    pub count_only: Option<CountStyle>,
+    pub stats: bool,
+    pub progress_json: bool,
+    // Trace how files were grouped to stderr, for `--explain`.
+    pub explain: bool,
+    pub progress_fd: Option<i32>,
+    pub format: OutputFormat,
+    // List the paths in each group of the JSON report directly, rather than by file.
+    pub flat_json: bool,
+    // Show each file's device, inode number and link count in the HTML report.
+    pub html_detailed: bool,
+    // Write sizes in binary units (as well as in bytes, where the report is read by machines).
+    pub pretty_sizes: bool,
+    // Include a `config` object in the JSON report.
+    pub embed_config: bool,
+    pub output: Option<PathBuf>,
+    pub output_fd: Option<i32>,
+    pub report_errors: bool,
+    // Carry on past continuable problems (see `errors`).
+    pub keep_going: bool,
+}
+
+// The options as they'd be with no flags on the command line, and no config file, for building
+// options in code: e.g. `Options { min_size: 1, ..Options::default() }`.  There's no `target`, so
+// one must be given before traversing anything.
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            target: None,
+            merge_indexes: None,
+            diff: None,
+            verify: None,
+            diff_offsets: None,
+            min_size: 100_000,
+            max_size: None,
+            min_size_relative: None,
+            max_size_relative: None,
+            exclude_empty_files: true,
+            min_group: 2,
+            limit: None,
+            snapshot_start: None,
+            modified_cutoff: None,
+            owner: None,
+            sample_rate: None,
+            shuffle_seed: None,
+            by_alloc_size: false,
+            no_inode: false,
+            excluded_devices: HashSet::new(),
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            per_directory: false,
+            same_extension: false,
+            path_style: PathStyle::default(),
+            normalize_paths: false,
+            symlinks: SymlinkPolicy::default(),
+            strict_containment: false,
+            cwd_relative: false,
+            relative_to: None,
+            low_memory: false,
+            inode_order: false,
+            ignore_case_content: false,
+            ignore_whitespace: false,
+            ignore_header: 0,
+            ignore_trailer: 0,
+            compare_limit: None,
+            keep: None,
+            protect: Vec::new(),
+            compare_metadata: false,
+            group_key: GroupKey::default(),
+            sort_within_groups: None,
+            double_hash: false,
+            sample_blocks: None,
+            assume_unchanged: false,
+            snapshot_roots: None,
+            skip_open_files: false,
+            skip_hashes: None,
+            exclude_signature: None,
+            only_hardlinkable: false,
+            chunk_hashes: None,
+            emit_index: None,
+            include_unique: false,
+            dump_files: None,
+            resume: None,
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            io_limit: None,
+            max_runtime: None,
+            size_histogram: false,
+            probe: false,
+            savings_by_dir: None,
+            by_extension: false,
+            find_prefixes: false,
+            find_dir_dupes: false,
+            find_broken_symlinks: false,
+            review_dir: None,
+            rank: false,
+            group_output_by_dir: false,
+            count_only: None,
+            stats: false,
+            progress_json: false,
+            explain: false,
+            progress_fd: None,
+            format: OutputFormat::default(),
+            flat_json: false,
+            html_detailed: false,
+            pretty_sizes: false,
+            embed_config: false,
+            output: None,
+            output_fd: None,
+            report_errors: false,
+            keep_going: false,
+        }
+    }
+}
+
+// The current time in the same form as file timestamps.
+fn snapshot_start() -> (i64, i64) {
+    time_before_now(Duration::ZERO)
+}
+
+// Some time ago, in the same form as file timestamps.
+fn time_before_now(age: Duration) -> (i64, i64) {
+    let since_epoch = SystemTime::now()
+        .checked_sub(age)
+        .unwrap_or(UNIX_EPOCH)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (
+        since_epoch.as_secs() as i64,
+        i64::from(since_epoch.subsec_nanos()),
+    )
+}
+
+// Parse a string describing the size of a file, with optional SI or IEC unit prefix.
+pub fn parse_file_size_spec(s: &str) -> Result<u64> {
+    let mut t: String = s.to_owned();
+    t.make_ascii_lowercase();
+    let (num_str, suffix) = t
+        .find(|c: char| c.is_ascii_alphabetic())
+        .map(|i| t.split_at(i))
+        .unwrap_or((&t, ""));
+    let multiplier: u64 = match suffix {
+        "" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "ki" | "kib" => 1_024,
+        "mi" | "mib" => 1_024 * 1_024,
+        "gi" | "gib" => 1_024 * 1_024 * 1_024,
+        "ti" | "tib" => 1_024 * 1_024 * 1_024 * 1_024,
+        _ => bail!("Failed to parse file size (bad multiplier -- got {:?})", s),
+    };
+    num_str
+        .parse()
+        .map(|num: u64| num * multiplier)
+        .with_context(|| format!("Failed to parse file size (bad number -- got {:?})", s))
+}
+
+// Parse a string of bytes written in hex, two digits each, e.g. "89504e47".
+pub fn parse_hex_bytes(s: &str) -> Result<Vec<u8>> {
+    let digits = s.trim();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        bail!(
+            "Failed to parse hex bytes (need an even number of digits -- got {:?})",
+            s
+        );
+    }
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("Failed to parse hex bytes (bad digit -- got {:?})", s);
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).expect("Checked hex digits"))
+        .collect())
+}
+
+// Parse a length of time: a number of seconds, or of minutes, hours or days with the suffix `m`,
+// `h` or `d` (or `s` for seconds, to be explicit), e.g. "90s" or "1.5h".
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let (num_str, multiplier) = match s.trim().to_ascii_lowercase() {
+        t if t.ends_with('s') => (t[..t.len() - 1].to_owned(), 1.0),
+        t if t.ends_with('m') => (t[..t.len() - 1].to_owned(), 60.0),
+        t if t.ends_with('h') => (t[..t.len() - 1].to_owned(), 3600.0),
+        t if t.ends_with('d') => (t[..t.len() - 1].to_owned(), 86400.0),
+        t => (t, 1.0),
+    };
+    num_str
+        .parse::<f64>()
+        .ok()
+        .and_then(|num| Duration::try_from_secs_f64(num * multiplier).ok())
+        .with_context(|| format!("Failed to parse duration (got {:?})", s))
+}
+
+// Parse a size limit, which is either a file size (see `parse_file_size_spec`), a percentile such
+// as "p90", or a multiple of the mean such as "2x-mean" (or just "mean").
+pub fn parse_size_threshold(s: &str) -> Result<SizeThreshold> {
+    let lowercase = s.to_ascii_lowercase();
+    if let Some(multiple_str) = lowercase.strip_suffix("mean") {
+        let multiple = match multiple_str {
+            "" => 1.0,
+            _ => multiple_str
+                .strip_suffix("x-")
+                .and_then(|num_str| num_str.parse::<f64>().ok())
+                .with_context(|| format!("Failed to parse multiple of the mean (got {:?})", s))?,
+        };
+        if !(multiple.is_finite() && multiple > 0.0) {
+            bail!("Multiple of the mean must be positive (got {:?})", s);
+        }
+        return Ok(SizeThreshold::MeanMultiple(multiple));
+    }
+    match s.strip_prefix(['p', 'P']) {
+        Some(num_str) => {
+            let percentile: f64 = num_str
+                .parse()
+                .with_context(|| format!("Failed to parse percentile (got {:?})", s))?;
+            if !(0.0..=100.0).contains(&percentile) {
+                bail!("Percentile must be between 0 and 100 (got {:?})", s);
+            }
+            Ok(SizeThreshold::Percentile(percentile))
+        }
+        None => parse_file_size_spec(s).map(SizeThreshold::Bytes),
+    }
+}
+
+fn command() -> clap::Command {
+    command!()
+        .arg(
+            Arg::new("PATH")
+                .help("Location to search")
+                .required_unless_present_any(["MERGE_INDEXES", "DIFF", "VERIFY", "DIFF_OFFSETS"])
+                .index(1),
+        )
+        .arg(
+            Arg::new("CONFIG")
+                .long("config")
+                .value_name("FILE")
+                .help("Read settings from this TOML file (command-line flags take precedence)"),
+        )
+        .arg(
+            Arg::new("MIN_SIZE")
+                .long("min-size")
+                .help(
+                    "Ignore files smaller than this (bytes, a percentile such as p90, or a \
+                     multiple of the mean such as 2x-mean)",
+                )
+                .default_value("100000"),
+        )
+        .arg(Arg::new("MAX_SIZE").long("max-size").help(
+            "Ignore files larger than this (bytes, a percentile such as p90, or a multiple \
+                     of the mean such as 2x-mean)",
+        ))
+        .arg(
+            Arg::new("EXCLUDE_EMPTY_FILES")
+                .long("exclude-empty-files")
+                .value_name("BOOL")
+                .help("Ignore empty files, whatever --min-size says")
+                .value_parser(clap::value_parser!(bool))
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("true")
+                .default_value("true"),
+        )
+        .arg(
+            Arg::new("MIN_GROUP")
+                .long("min-group")
+                .help("Only report groups with at least this many distinct files")
+                .value_parser(clap::value_parser!(u64).range(2..))
+                .default_value("2"),
+        )
+        .arg(
+            Arg::new("LIMIT")
+                .long("limit")
+                .value_name("N")
+                .help("Stop scanning after finding this many files (for a quick sample)")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with("MERGE_INDEXES"),
+        )
+        .arg(
+            Arg::new("SAMPLE_RATE")
+                .long("sample-rate")
+                .value_name("FRACTION")
+                .help("Only consider a random sample of this fraction of files (e.g. 0.01)")
+                .value_parser(clap::value_parser!(f64))
+                .conflicts_with("MERGE_INDEXES"),
+        )
+        .arg(
+            Arg::new("SHUFFLE_SEED")
+                .long("shuffle-seed")
+                .value_name("SEED")
+                .help("Seed --sample-rate so that the same files are sampled every time")
+                .value_parser(clap::value_parser!(u64))
+                .requires("SAMPLE_RATE"),
+        )
+        .arg(
+            Arg::new("SNAPSHOT")
+                .long("snapshot")
+                .help("Skip files created or changed after the scan started")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("MERGE_INDEXES"),
+        )
+        .arg(
+            Arg::new("MIN_AGE")
+                .long("min-age")
+                .value_name("DURATION")
+                .help("Skip files modified less than this long ago (e.g. 12h, 7d)"),
+        )
+        .arg(
+            Arg::new("OWNED_ONLY")
+                .long("owned-only")
+                .help("Only consider files owned by the current (effective) user")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("BY_ALLOC_SIZE")
+                .long("by-alloc-size")
+                .help("Filter, group and report by space allocated on disk (for sparse files)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("NO_INODE")
+                .long("no-inode")
+                .help("Identify files by path rather than inode number (no hard link detection)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("PROTECT")
+                .long("protect")
+                .value_name("DIR")
+                .help("Always keep files under this directory, preferring them as the survivor")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("EXCLUDE_DEVICE")
+                .long("exclude-device")
+                .value_name("PATH")
+                .help("Skip everything on the same device (mounted filesystem) as this path")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("EXCLUDE")
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Skip files and directories whose names match this wildcard pattern")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("INCLUDE")
+                .long("include")
+                .value_name("PATTERN")
+                .help("Only consider files whose names match this wildcard pattern")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("PER_DIRECTORY")
+                .long("per-directory")
+                .help("Only report duplicates that share a parent directory")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("SAME_EXTENSION")
+                .long("same-extension")
+                .help("Only report duplicates that share a file extension (ignoring case)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("PATHS")
+                .long("paths")
+                .value_name("STYLE")
+                .help("Report canonical paths, or paths below PATH as given")
+                .value_parser(PathStyle::NAMES)
+                .default_value("canonical"),
+        )
+        .arg(
+            Arg::new("NORMALIZE_PATHS")
+                .long("normalize-paths")
+                .help("Collapse . and .. in paths given, and in dumps and reports read")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("SYMLINKS")
+                .long("symlinks")
+                .value_name("POLICY")
+                .help(
+                    "Skip symlinks below PATH, follow them, or follow only those leading within it",
+                )
+                .value_parser(SymlinkPolicy::NAMES)
+                .default_value("skip"),
+        )
+        .arg(
+            Arg::new("STRICT_CONTAINMENT")
+                .long("strict-containment")
+                .help("Skip files whose real path (with symlinks resolved) is outside PATH")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("CWD_RELATIVE")
+                .long("cwd-relative")
+                .help("Show paths relative to the current directory where possible")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("RELATIVE_TO")
+                .long("relative-to")
+                .value_name("DIR")
+                .help("Show paths relative to this directory where possible")
+                .conflicts_with("CWD_RELATIVE"),
+        )
+        .arg(
+            Arg::new("LOW_MEMORY")
+                .long("low-memory")
+                .help("Sort files on disk rather than in memory (slower, for huge trees)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("INODE_ORDER")
+                .long("inode-order")
+                .help("Compare files in order of inode number (fewer seeks on spinning disks)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("IGNORE_CASE_CONTENT")
+                .long("ignore-case-content")
+                .help("Treat files differing only in ASCII letter case as duplicates")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("IGNORE_WHITESPACE")
+                .long("ignore-whitespace")
+                .help(
+                    "Treat files differing only in line endings or trailing whitespace as \
+                     duplicates",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("IGNORE_HEADER")
+                .long("ignore-header")
+                .value_name("SIZE")
+                .help("Leave this many bytes at the start of each file out of the comparison")
+                .conflicts_with_all(["IGNORE_WHITESPACE", "EMIT_INDEX", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("IGNORE_TRAILER")
+                .long("ignore-trailer")
+                .value_name("SIZE")
+                .help("Leave this many bytes at the end of each file out of the comparison")
+                .conflicts_with_all(["IGNORE_WHITESPACE", "EMIT_INDEX", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("COMPARE_LIMIT")
+                .long("compare-limit")
+                .value_name("SIZE")
+                .help(
+                    "Treat files as duplicates once this many bytes match, marking them as partial",
+                )
+                .conflicts_with_all(["IGNORE_WHITESPACE", "EMIT_INDEX", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("SAMPLE_BLOCKS")
+                .long("sample-blocks")
+                .value_name("N")
+                .help(
+                    "Compare big files only in N blocks of 64 KiB spread through them, marking \
+                     them as sampled",
+                )
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with_all([
+                    "IGNORE_WHITESPACE",
+                    "COMPARE_LIMIT",
+                    "DOUBLE_HASH",
+                    "EMIT_INDEX",
+                    "MERGE_INDEXES",
+                ]),
+        )
+        .arg(
+            Arg::new("ASSUME_UNCHANGED")
+                .long("assume-unchanged")
+                .help(
+                    "Report files of the same size as unconfirmed duplicates, without reading them",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "IGNORE_WHITESPACE",
+                    "COMPARE_LIMIT",
+                    "SAMPLE_BLOCKS",
+                    "DOUBLE_HASH",
+                    "EMIT_INDEX",
+                ]),
+        )
+        .arg(
+            Arg::new("DOUBLE_HASH")
+                .long("double-hash")
+                .help(
+                    "Treat files as duplicates if their BLAKE3 and FNV-1a hashes both match, \
+                     without comparing bytes",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["IGNORE_WHITESPACE", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("GROUP_KEY")
+                .long("group-key")
+                .value_name("KEY")
+                .help(
+                    "What duplicates must have in common: content, and maybe name, extension or \
+                     mtime",
+                )
+                .value_parser(GroupKey::NAMES)
+                .default_value("content"),
+        )
+        .arg(
+            Arg::new("SORT_WITHIN_GROUPS")
+                .long("sort-within-groups")
+                .value_name("ORDER")
+                .help("Order the files in each group by path, mtime (oldest first) or size")
+                .value_parser(GroupOrder::NAMES),
+        )
+        .arg(
+            Arg::new("COMPARE_METADATA")
+                .long("compare-metadata")
+                .help(
+                    "Only treat files as duplicates if their mtime, permissions and owner match \
+                     too",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("SNAPSHOT_AWARE")
+                .long("snapshot-aware")
+                .help(
+                    "Leave out copies of files in filesystem snapshots, noting how many on stderr",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("SNAPSHOT_ROOT")
+                .long("snapshot-root")
+                .value_name("PATTERN")
+                .help("Treat directories matching this as snapshots, rather than the usual places")
+                .action(ArgAction::Append)
+                .requires("SNAPSHOT_AWARE"),
+        )
+        .arg(
+            Arg::new("SKIP_HASHES")
+                .long("skip-hashes")
+                .value_name("FILE")
+                .help(
+                    "Leave out files whose BLAKE3 digest is listed in this file (e.g. b3sum \
+                     output)",
+                ),
+        )
+        .arg(
+            Arg::new("EXCLUDE_SIGNATURE")
+                .long("exclude-signature")
+                .value_name("HEXBYTES")
+                .help(
+                    "Leave out files whose content starts with these bytes, given in hex (e.g. \
+                     89504e47)",
+                ),
+        )
+        .arg(
+            Arg::new("SKIP_OPEN_FILES")
+                .long("skip-open-files")
+                .help(
+                    "Leave out files that other processes have open for writing, listing them on \
+                     stderr (Linux only)",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with("MERGE_INDEXES"),
+        )
+        .arg(
+            Arg::new("ONLY_HARDLINKABLE")
+                .long("only-hardlinkable")
+                .help("Only report groups on one device whose files we may all write to")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("MERGE_INDEXES"),
+        )
+        .arg(
+            Arg::new("CHUNK_HASHES")
+                .long("chunk-hashes")
+                .value_name("SIZE")
+                .help("Report a digest of each chunk of this size for every group (JSON only)")
+                .conflicts_with_all([
+                    "IGNORE_CASE_CONTENT",
+                    "IGNORE_WHITESPACE",
+                    "IGNORE_HEADER",
+                    "IGNORE_TRAILER",
+                ]),
+        )
+        .arg(
+            Arg::new("KEEP")
+                .long("keep")
+                .help("Highlight the file in each group that this policy would keep")
+                .value_parser(KeepPolicy::NAMES),
+        )
+        .arg(
+            Arg::new("EMIT_INDEX")
+                .long("emit-index")
+                .value_name("FILE")
+                .help(
+                    "Also write a JSON index of BLAKE3 digest to paths for each group of \
+                     duplicates",
+                )
+                .conflicts_with_all(["LOW_MEMORY", "IGNORE_CASE_CONTENT", "IGNORE_WHITESPACE"]),
+        )
+        .arg(
+            Arg::new("INCLUDE_UNIQUE")
+                .long("include-unique")
+                .help("Include files with no duplicates in the index")
+                .action(ArgAction::SetTrue)
+                .requires("EMIT_INDEX"),
+        )
+        .arg(
+            Arg::new("DUMP_FILES")
+                .long("dump-files")
+                .value_name("FILE")
+                .help("Also write every file considered, with its BLAKE3 digest, as JSON Lines")
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("RESUME")
+                .long("resume")
+                .value_name("STATE")
+                .help("Save the traversal to this file as we go, and carry on from it if it exists")
+                .requires("PATH")
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("MERGE_INDEXES")
+                .long("merge-indexes")
+                .value_name("FILE")
+                .help("Find duplicates across file dumps (from --dump-files) instead of scanning")
+                .num_args(1..)
+                .conflicts_with_all([
+                    "PATH",
+                    "LOW_MEMORY",
+                    "IGNORE_CASE_CONTENT",
+                    "IGNORE_WHITESPACE",
+                    "EMIT_INDEX",
+                    "DUMP_FILES",
+                ]),
+        )
+        .arg(
+            Arg::new("DIFF")
+                .long("diff")
+                .value_names(["OLD_REPORT", "NEW_REPORT"])
+                .help("Compare two JSON reports and list new, resolved and changed groups")
+                .num_args(2)
+                .conflicts_with_all(["PATH", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("VERIFY")
+                .long("verify")
+                .value_name("REPORT")
+                .help("Check that the groups in a JSON report are still duplicates")
+                .conflicts_with_all(["PATH", "MERGE_INDEXES", "DIFF"]),
+        )
+        .arg(
+            Arg::new("DIFF_OFFSETS")
+                .long("diff-offsets")
+                .value_names(["FILE1", "FILE2"])
+                .help("Say where two files first differ, if they do")
+                .num_args(2)
+                .conflicts_with_all(["PATH", "MERGE_INDEXES", "DIFF", "VERIFY"]),
+        )
+        .arg(
+            Arg::new("MAX_OPEN_FILES")
+                .long("max-open-files")
+                .help("Open at most this many files at once for comparing and hashing")
+                .value_parser(clap::value_parser!(u64).range(2..))
+                .default_value("256"),
+        )
+        .arg(
+            Arg::new("IO_LIMIT")
+                .long("io-limit")
+                .value_name("BYTES_PER_SEC")
+                .help("Read file content no faster than this (bytes per second)"),
+        )
+        .arg(
+            Arg::new("MAX_RUNTIME")
+                .long("max-runtime")
+                .value_name("DURATION")
+                .help("Stop looking after this long (e.g. 90s, 10m, 2h) and report what we found"),
+        )
+        .arg(
+            Arg::new("SIZE_HISTOGRAM")
+                .long("size-histogram")
+                .help("Print a histogram of the sizes of the files found, without comparing them")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("PROBE")
+                .long("probe")
+                .help(
+                    "Print how many files and bytes comparing would involve, without comparing \
+                     them",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["LOW_MEMORY", "IGNORE_WHITESPACE", "SIZE_HISTOGRAM"]),
+        )
+        .arg(
+            Arg::new("SAVINGS_BY_DIR")
+                .long("savings-by-dir")
+                .value_name("DEPTH")
+                .help(
+                    "Print reclaimable space under each directory DEPTH levels below PATH to \
+                     stderr, as TSV",
+                )
+                .value_parser(clap::value_parser!(usize))
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("BY_EXTENSION")
+                .long("by-extension")
+                .help("Print a summary of reclaimable space by file extension to stderr")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("FIND_PREFIXES")
+                .long("find-prefixes")
+                .help(
+                    "Also list files (of 4 KiB or more) that are the start of bigger ones, on \
+                     stderr",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["LOW_MEMORY", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("GROUP_OUTPUT_BY_DIR")
+                .long("group-output-by-dir")
+                .help("List redundant copies by the directory holding them, for cleaning up")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["LOW_MEMORY", "RANK", "CHUNK_HASHES"]),
+        )
+        .arg(
+            Arg::new("FIND_DIR_DUPES")
+                .long("find-dir-dupes")
+                .help(
+                    "Also list directories whose whole contents are the same as another's, on \
+                     stderr",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["LOW_MEMORY", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("FIND_BROKEN_SYMLINKS")
+                .long("find-broken-symlinks")
+                .help("Also list symlinks whose targets don't exist, on stderr")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("MERGE_INDEXES"),
+        )
+        .arg(
+            Arg::new("REVIEW_DIR")
+                .long("review-dir")
+                .value_name("DIR")
+                .help("Also create a directory of symlinks to the files in each group, under DIR")
+                .conflicts_with_all(["LOW_MEMORY", "MERGE_INDEXES"]),
+        )
+        .arg(
+            Arg::new("RANK")
+                .long("rank")
+                .help("List groups with the most reclaimable space first, numbering them")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("LOW_MEMORY"),
+        )
+        .arg(
+            Arg::new("COUNT_ONLY")
+                .long("count-only")
+                .value_name("STYLE")
+                .help("Write only the number of groups, redundant files and reclaimable bytes")
+                .value_parser(CountStyle::NAMES)
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("tsv")
+                .conflicts_with_all(["FORMAT", "GROUP_OUTPUT_BY_DIR"]),
+        )
+        .arg(
+            Arg::new("WASTED_BYTES")
+                .long("wasted-bytes")
+                .help("Write only the number of reclaimable bytes, as a bare integer")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["COUNT_ONLY", "FORMAT", "GROUP_OUTPUT_BY_DIR"]),
+        )
+        .arg(
+            Arg::new("FORMAT")
+                .long("format")
+                .help("Write the report in this format")
+                .value_parser(OutputFormat::NAMES)
+                .default_value("html"),
+        )
+        .arg(
+            Arg::new("FLAT_JSON")
+                .long("flat-json")
+                .help("List all the paths in each group in one array (JSON format only)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["GROUP_OUTPUT_BY_DIR", "COUNT_ONLY", "WASTED_BYTES"]),
+        )
+        .arg(
+            Arg::new("HTML_DETAILED")
+                .long("html-detailed")
+                .help("Show each file's device, inode number and link count (HTML format only)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("GROUP_OUTPUT_BY_DIR"),
+        )
+        .arg(
+            Arg::new("PRETTY_SIZES")
+                .long("pretty-sizes")
+                .help("Write sizes in binary units, e.g. 1.5 MiB (alongside bytes in JSON)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("EMBED_CONFIG")
+                .long("embed-config")
+                .help("Record the settings that produced the report in it (JSON format only)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("OUTPUT")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Write the report to this file rather than stdout"),
+        )
+        .arg(
+            Arg::new("OUTPUT_FD")
+                .long("output-fd")
+                .value_name("FD")
+                .help("Write the report to this open file descriptor rather than stdout")
+                .value_parser(clap::value_parser!(i32).range(0..))
+                .conflicts_with("OUTPUT"),
+        )
+        .arg(
+            Arg::new("REPORT_ERRORS")
+                .long("report-errors")
+                .help("List paths that couldn't be read in the report (JSON format only)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("KEEP_GOING")
+                .long("keep-going")
+                .help(
+                    "Skip unreadable dumps, bad records and missing --exclude-device paths with a \
+                     warning, rather than stopping",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("PROGRESS_JSON")
+                .long("progress-json")
+                .help("Write progress events as JSON Lines to stderr (or --progress-fd)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("PROGRESS_FD")
+                .long("progress-fd")
+                .value_name("FD")
+                .help("Write --progress-json events to this open file descriptor instead")
+                .value_parser(clap::value_parser!(i32).range(0..))
+                .requires("PROGRESS_JSON"),
+        )
+        .arg(
+            Arg::new("EXPLAIN")
+                .long("explain")
+                .help("Write a trace of how files were grouped as JSON Lines to stderr")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("IGNORE_WHITESPACE"),
+        )
+        .arg(
+            Arg::new("STATS")
+                .long("stats")
+                .help("Print how many entries were scanned and files considered to stderr")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+impl Options {
+    // Parse the command line, and the config file if it names one.  A flag given on the command
+    // line always wins; failing that, the config file's value is used; failing that, the default.
+    // For the lists of patterns, this means any `--exclude` (say) replaces the config file's
+    // `exclude` list entirely rather than adding to it.
+    pub fn from_args() -> Result<Options> {
+        let matches = command().get_matches();
+        let config = match matches.get_one::<String>("CONFIG") {
+            Some(path) => read_config(path.as_ref())?,
+            None => Config::default(),
+        };
+        Options::from_matches(&matches, config)
+    }
+
+    // Parse these arguments (starting with the program's name) as if they were the command line,
+    // without a config file.
+    #[cfg(test)]
+    pub fn parse_from<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<Options> {
+        let matches = command().try_get_matches_from(args)?;
+        Options::from_matches(&matches, Config::default())
+    }
+
+    fn from_matches(matches: &ArgMatches, config: Config) -> Result<Options> {
+        let on_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let strings = |id: &str| -> Vec<String> {
+            matches
+                .get_many::<String>(id)
+                .unwrap_or_default()
+                .cloned()
+                .collect()
+        };
+        let size = |id: &str| -> Result<Option<SizeThreshold>> {
+            matches
+                .get_one::<String>(id)
+                .map(|s| parse_size_threshold(s))
+                .transpose()
+        };
+
+        let size_spec = |id: &str| -> Result<u64> {
+            matches
+                .get_one::<String>(id)
+                .map_or(Ok(0), |s| parse_file_size_spec(s))
+        };
+
+        let min_size = match config.min_size {
+            Some(min_size) if !on_command_line("MIN_SIZE") => min_size,
+            _ => size("MIN_SIZE")?
+                .expect("Failed to find MIN_SIZE argument despite clap default_value"),
+        };
+        let max_size = match config.max_size {
+            Some(max_size) if !on_command_line("MAX_SIZE") => Some(max_size),
+            _ => size("MAX_SIZE")?,
+        };
+        let (min_size, min_size_relative) = match min_size {
+            SizeThreshold::Bytes(bytes) => (bytes, None),
+            relative => (0, Some(relative)),
+        };
+        let (max_size, max_size_relative) = match max_size {
+            Some(SizeThreshold::Bytes(bytes)) => (Some(bytes), None),
+            Some(relative) => (None, Some(relative)),
+            None => (None, None),
+        };
+        let excludes = match config.exclude {
+            Some(excludes) if !on_command_line("EXCLUDE") => excludes,
+            _ => strings("EXCLUDE"),
+        };
+        let includes = match config.include {
+            Some(includes) if !on_command_line("INCLUDE") => includes,
+            _ => strings("INCLUDE"),
+        };
+        let format = match config.format {
+            Some(format) if !on_command_line("FORMAT") => format,
+            _ => OutputFormat::from_name(
+                matches
+                    .get_one::<String>("FORMAT")
+                    .expect("Failed to find FORMAT argument despite clap default_value"),
+            )?,
+        };
+        let output_fd = matches.get_one::<i32>("OUTPUT_FD").copied();
+        let output = match config.output {
+            Some(output) if !on_command_line("OUTPUT") && output_fd.is_none() => Some(output),
+            _ => matches.get_one::<String>("OUTPUT").map(PathBuf::from),
+        };
+
+        // The format may come from the config file, so clap can't check this for us.
+        let report_errors = matches.get_flag("REPORT_ERRORS");
+        if report_errors && format != OutputFormat::Json {
+            bail!("--report-errors requires --format json");
+        }
+
+        let chunk_hashes = matches
+            .get_one::<String>("CHUNK_HASHES")
+            .map(|s| parse_file_size_spec(s))
+            .transpose()?;
+        if chunk_hashes == Some(0) {
+            bail!("--chunk-hashes must be at least one byte");
+        }
+        if chunk_hashes.is_some() && format != OutputFormat::Json {
+            bail!("--chunk-hashes requires --format json");
+        }
+        let skip_open_files = matches.get_flag("SKIP_OPEN_FILES");
+        if skip_open_files && !cfg!(target_os = "linux") {
+            bail!("--skip-open-files is only supported on Linux");
+        }
+        let embed_config = matches.get_flag("EMBED_CONFIG");
+        if embed_config && format != OutputFormat::Json {
+            bail!("--embed-config requires --format json");
+        }
+        let flat_json = matches.get_flag("FLAT_JSON");
+        if flat_json && format != OutputFormat::Json {
+            bail!("--flat-json requires --format json");
+        }
+        let html_detailed = matches.get_flag("HTML_DETAILED");
+        if html_detailed && format != OutputFormat::Html {
+            bail!("--html-detailed requires --format html");
+        }
+
+        if matches.get_flag("GROUP_OUTPUT_BY_DIR")
+            && matches!(format, OutputFormat::Dot | OutputFormat::Tree)
+        {
+            bail!("--group-output-by-dir only supports --format html or json");
+        }
+
+        let sample_rate = matches.get_one::<f64>("SAMPLE_RATE").copied();
+        if sample_rate.is_some_and(|rate| !(rate > 0.0 && rate <= 1.0)) {
+            bail!("--sample-rate must be more than 0 and at most 1");
+        }
+        // Every traversal (e.g. for percentiles, as well as the real one) must take the same
+        // sample, so settle on a seed now if we weren't given one.
+        let shuffle_seed = sample_rate.map(|_| {
+            matches
+                .get_one::<u64>("SHUFFLE_SEED")
+                .copied()
+                .unwrap_or_else(fresh_seed)
+        });
+
+        let keep_going = matches.get_flag("KEEP_GOING");
+
+        let mut excluded_devices: HashSet<u64> = HashSet::new();
+        for path in strings("EXCLUDE_DEVICE") {
+            match fs::metadata(&path)
+                .with_context(|| format!("Failed to find device for --exclude-device {:?}", path))
+            {
+                Ok(metadata) => {
+                    excluded_devices.insert(metadata.dev());
+                }
+                Err(e) => keep_going_or_fail(e, keep_going)?,
+            }
+        }
+
+        Ok(Options {
+            target: matches.get_one::<String>("PATH").map(PathBuf::from),
+            merge_indexes: matches
+                .get_many::<String>("MERGE_INDEXES")
+                .map(|paths| paths.map(PathBuf::from).collect()),
+            diff: matches.get_many::<String>("DIFF").map(|mut paths| {
+                let old = paths
+                    .next()
+                    .expect("Failed to read OLD_REPORT despite clap num_args");
+                let new = paths
+                    .next()
+                    .expect("Failed to read NEW_REPORT despite clap num_args");
+                (PathBuf::from(old), PathBuf::from(new))
+            }),
+            verify: matches.get_one::<String>("VERIFY").map(PathBuf::from),
+            diff_offsets: matches.get_many::<String>("DIFF_OFFSETS").map(|mut paths| {
+                let path1 = paths
+                    .next()
+                    .expect("Failed to read FILE1 despite clap num_args");
+                let path2 = paths
+                    .next()
+                    .expect("Failed to read FILE2 despite clap num_args");
+                (PathBuf::from(path1), PathBuf::from(path2))
+            }),
+            min_size,
+            max_size,
+            min_size_relative,
+            max_size_relative,
+            exclude_empty_files: *matches
+                .get_one::<bool>("EXCLUDE_EMPTY_FILES")
+                .expect("Failed to find EXCLUDE_EMPTY_FILES argument despite clap default_value"),
+            min_group: *matches
+                .get_one::<u64>("MIN_GROUP")
+                .expect("Failed to find MIN_GROUP argument despite clap default_value")
+                as usize,
+            limit: matches.get_one::<u64>("LIMIT").copied(),
+            snapshot_start: matches.get_flag("SNAPSHOT").then(snapshot_start),
+            modified_cutoff: matches
+                .get_one::<String>("MIN_AGE")
+                .map(|s| parse_duration(s))
+                .transpose()?
+                .map(time_before_now),
+            owner: if matches.get_flag("OWNED_ONLY") {
+                Some(
+                    Credentials::current()
+                        .context("Failed to determine the current user")?
+                        .uid(),
+                )
+            } else {
+                None
+            },
+            sample_rate,
+            shuffle_seed,
+            by_alloc_size: matches.get_flag("BY_ALLOC_SIZE"),
+            no_inode: matches.get_flag("NO_INODE"),
+            excluded_devices,
+            excludes,
+            includes,
+            per_directory: matches.get_flag("PER_DIRECTORY"),
+            same_extension: matches.get_flag("SAME_EXTENSION"),
+            path_style: PathStyle::from_name(
+                matches
+                    .get_one::<String>("PATHS")
+                    .expect("Failed to find PATHS argument despite clap default_value"),
+            )?,
+            normalize_paths: matches.get_flag("NORMALIZE_PATHS"),
+            symlinks: SymlinkPolicy::from_name(
+                matches
+                    .get_one::<String>("SYMLINKS")
+                    .expect("Failed to find SYMLINKS argument despite clap default_value"),
+            )?,
+            strict_containment: matches.get_flag("STRICT_CONTAINMENT"),
+            cwd_relative: matches.get_flag("CWD_RELATIVE"),
+            relative_to: matches.get_one::<String>("RELATIVE_TO").map(PathBuf::from),
+            low_memory: matches.get_flag("LOW_MEMORY"),
+            inode_order: matches.get_flag("INODE_ORDER"),
+            ignore_case_content: matches.get_flag("IGNORE_CASE_CONTENT"),
+            ignore_whitespace: matches.get_flag("IGNORE_WHITESPACE"),
+            ignore_header: size_spec("IGNORE_HEADER")?,
+            ignore_trailer: size_spec("IGNORE_TRAILER")?,
+            compare_limit: matches
+                .get_one::<String>("COMPARE_LIMIT")
+                .map(|s| parse_file_size_spec(s))
+                .transpose()?,
+            keep: matches
+                .get_one::<String>("KEEP")
+                .map(|name| KeepPolicy::from_name(name))
+                .transpose()?,
+            chunk_hashes,
+            protect: strings("PROTECT").into_iter().map(PathBuf::from).collect(),
+            compare_metadata: matches.get_flag("COMPARE_METADATA"),
+            double_hash: matches.get_flag("DOUBLE_HASH"),
+            sample_blocks: matches.get_one::<u64>("SAMPLE_BLOCKS").copied(),
+            assume_unchanged: matches.get_flag("ASSUME_UNCHANGED"),
+            group_key: GroupKey::from_name(
+                matches
+                    .get_one::<String>("GROUP_KEY")
+                    .expect("Failed to find GROUP_KEY argument despite clap default_value"),
+            )?,
+            sort_within_groups: matches
+                .get_one::<String>("SORT_WITHIN_GROUPS")
+                .map(|name| GroupOrder::from_name(name))
+                .transpose()?,
+            snapshot_roots: matches.get_flag("SNAPSHOT_AWARE").then(|| {
+                match matches.get_many::<String>("SNAPSHOT_ROOT") {
+                    Some(patterns) => patterns.cloned().collect(),
+                    None => DEFAULT_SNAPSHOT_ROOTS.map(str::to_owned).to_vec(),
+                }
+            }),
+            skip_hashes: matches.get_one::<String>("SKIP_HASHES").map(PathBuf::from),
+            exclude_signature: matches
+                .get_one::<String>("EXCLUDE_SIGNATURE")
+                .map(|s| parse_hex_bytes(s))
+                .transpose()?,
+            skip_open_files,
+            only_hardlinkable: matches.get_flag("ONLY_HARDLINKABLE"),
+            emit_index: matches.get_one::<String>("EMIT_INDEX").map(PathBuf::from),
+            include_unique: matches.get_flag("INCLUDE_UNIQUE"),
+            dump_files: matches.get_one::<String>("DUMP_FILES").map(PathBuf::from),
+            resume: matches.get_one::<String>("RESUME").map(PathBuf::from),
+            max_open_files: *matches
+                .get_one::<u64>("MAX_OPEN_FILES")
+                .expect("Failed to find MAX_OPEN_FILES argument despite clap default_value")
+                as usize,
+            max_runtime: matches
+                .get_one::<String>("MAX_RUNTIME")
+                .map(|s| parse_duration(s))
+                .transpose()?,
+            io_limit: matches
+                .get_one::<String>("IO_LIMIT")
+                .map(|s| parse_file_size_spec(s))
+                .transpose()?,
+            size_histogram: matches.get_flag("SIZE_HISTOGRAM"),
+            probe: matches.get_flag("PROBE"),
+            savings_by_dir: matches.get_one::<usize>("SAVINGS_BY_DIR").copied(),
+            by_extension: matches.get_flag("BY_EXTENSION"),
+            find_prefixes: matches.get_flag("FIND_PREFIXES"),
+            find_dir_dupes: matches.get_flag("FIND_DIR_DUPES"),
+            find_broken_symlinks: matches.get_flag("FIND_BROKEN_SYMLINKS"),
+            review_dir: matches.get_one::<String>("REVIEW_DIR").map(PathBuf::from),
+            rank: matches.get_flag("RANK"),
+            group_output_by_dir: matches.get_flag("GROUP_OUTPUT_BY_DIR"),
+            count_only: if matches.get_flag("WASTED_BYTES") {
+                Some(CountStyle::WastedBytes)
+            } else {
+                matches
+                    .get_one::<String>("COUNT_ONLY")
+                    .map(|name| CountStyle::from_name(name))
+                    .transpose()?
+            },
+            stats: matches.get_flag("STATS"),
+            progress_json: matches.get_flag("PROGRESS_JSON"),
+            explain: matches.get_flag("EXPLAIN"),
+            progress_fd: matches.get_one::<i32>("PROGRESS_FD").copied(),
+            format,
+            flat_json,
+            html_detailed,
+            pretty_sizes: matches.get_flag("PRETTY_SIZES"),
+            embed_config,
+            output,
+            output_fd,
+            report_errors,
+            keep_going,
+        })
+    }
+
+    // Turn any relative size limits into concrete ones, given the sizes of every file in the
+    // tree.
+    pub fn resolve_relative_sizes(&mut self, mut sizes: Vec<u64>) {
+        if let Some(threshold) = self.min_size_relative.take() {
+            self.min_size = threshold.resolve(&mut sizes);
+        }
+        if let Some(threshold) = self.max_size_relative.take() {
+            self.max_size = Some(threshold.resolve(&mut sizes));
+        }
+    }
+
+    // True iff files of this size are only known to match as far as `--compare-limit`, rather
+    // than all the way through.
+    pub fn is_partial_match(&self, size: u64) -> bool {
+        self.compare_limit.is_some_and(|limit| {
+            size.saturating_sub(self.ignore_header + self.ignore_trailer) > limit
+        })
+    }
+
+    // True iff files of this size are only compared in the blocks sampled with `--sample-blocks`,
+    // being bigger than those all together.
+    pub fn is_sampled(&self, size: u64) -> bool {
+        self.sample_blocks.is_some_and(|blocks| {
+            size.saturating_sub(self.ignore_header + self.ignore_trailer)
+                > blocks.saturating_mul(SAMPLE_BLOCK_LEN)
+        })
+    }
+
+    // True iff files of this size only count as duplicates if every byte of them matches.
+    pub fn compares_every_byte(&self, size: u64) -> bool {
+        !self.ignore_case_content
+            && !self.ignore_whitespace
+            && self.ignore_header == 0
+            && self.ignore_trailer == 0
+            && !self.is_partial_match(size)
+            && !self.is_sampled(size)
+            && !self.assume_unchanged
+    }
+
+    // True iff the files come from dumps rather than a traversal, in which case they may not exist
+    // locally and we may have only their recorded digests to go on.
+    pub fn merging_dumps(&self) -> bool {
+        self.merge_indexes.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_with_config(args: &[&str], config: Config) -> Options {
+        let matches = command()
+            .try_get_matches_from(std::iter::once("find_dupes").chain(args.iter().copied()))
+            .unwrap();
+        Options::from_matches(&matches, config).unwrap()
+    }
+
+    fn config() -> Config {
+        Config {
+            min_size: Some(SizeThreshold::Bytes(10)),
+            max_size: Some(SizeThreshold::Bytes(1000)),
+            exclude: Some(vec!["*.tmp".to_owned()]),
+            include: Some(vec!["*.jpg".to_owned()]),
+            format: Some(OutputFormat::Json),
+            output: Some(PathBuf::from("config.json")),
+        }
+    }
+
+    #[test]
+    fn config_file_settings_apply_when_not_on_the_command_line() {
+        let options = parse_with_config(&["/"], config());
+        assert_eq!(options.min_size, 10);
+        assert_eq!(options.max_size, Some(1000));
+        assert_eq!(options.excludes, ["*.tmp"]);
+        assert_eq!(options.includes, ["*.jpg"]);
+        assert_eq!(options.format, OutputFormat::Json);
+        assert_eq!(options.output, Some(PathBuf::from("config.json")));
+    }
+
+    #[test]
+    fn command_line_flags_override_the_config_file() {
+        let args = [
+            "--min-size",
+            "20",
+            "--max-size",
+            "2000",
+            "--exclude",
+            "*.bak",
+            "--include",
+            "*.png",
+            "--format",
+            "dot",
+            "--output",
+            "cli.dot",
+            "/",
+        ];
+        let options = parse_with_config(&args, config());
+        assert_eq!(options.min_size, 20);
+        assert_eq!(options.max_size, Some(2000));
+        assert_eq!(options.excludes, ["*.bak"]);
+        assert_eq!(options.includes, ["*.png"]);
+        assert_eq!(options.format, OutputFormat::Dot);
+        assert_eq!(options.output, Some(PathBuf::from("cli.dot")));
+    }
+
+    #[test]
+    fn default_options_are_those_of_a_bare_command_line() {
+        let parsed = Options {
+            target: None,
+            ..parse_with_config(&["/"], Config::default())
+        };
+        assert_eq!(format!("{:?}", Options::default()), format!("{:?}", parsed));
+    }
+
+    #[test]
+    fn size_limits_may_be_percentiles_or_multiples_of_the_mean() {
+        assert_eq!(
+            parse_size_threshold("10MiB").unwrap(),
+            SizeThreshold::Bytes(10 << 20)
+        );
+        assert_eq!(
+            parse_size_threshold("p90").unwrap(),
+            SizeThreshold::Percentile(90.0)
+        );
+        assert_eq!(
+            parse_size_threshold("P99.5").unwrap(),
+            SizeThreshold::Percentile(99.5)
+        );
+        assert_eq!(
+            parse_size_threshold("mean").unwrap(),
+            SizeThreshold::MeanMultiple(1.0)
+        );
+        assert_eq!(
+            parse_size_threshold("2x-mean").unwrap(),
+            SizeThreshold::MeanMultiple(2.0)
+        );
+        for bad in ["p101", "p-1", "px", "0x-mean", "2x-mea"] {
+            assert!(parse_size_threshold(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn durations_may_be_in_seconds_minutes_hours_or_days() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("0.001s").unwrap(), Duration::from_millis(1));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("2H").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        for bad in ["", "s", "-1s", "1w", "fast"] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn relative_size_limits_are_resolved_from_the_sizes_found() {
+        let mut options = parse_with_config(
+            &["--min-size", "p50", "--max-size", "2x-mean", "/"],
+            Config::default(),
+        );
+        options.resolve_relative_sizes(vec![10, 20, 30, 40, 1000]);
+        assert_eq!(options.min_size, 30);
+        assert_eq!(options.max_size, Some(440));
+        assert!(options.min_size_relative.is_none() && options.max_size_relative.is_none());
+    }
+
+    #[test]
+    fn mean_size_limits_are_multiples_of_the_mean_size_rounded() {
+        let mut sizes = vec![10, 10, 1000, 1000, 1000];
+        assert_eq!(SizeThreshold::MeanMultiple(1.0).resolve(&mut sizes), 604);
+        assert_eq!(SizeThreshold::MeanMultiple(0.5).resolve(&mut sizes), 302);
+        assert_eq!(SizeThreshold::MeanMultiple(0.01).resolve(&mut sizes), 6);
+        assert_eq!(SizeThreshold::MeanMultiple(2.0).resolve(&mut []), 0);
+        // Absolute sizes don't depend on the tree at all.
+        assert_eq!(SizeThreshold::Bytes(100).resolve(&mut sizes), 100);
+
+        let mut options = parse_with_config(&["--min-size", "0.5x-mean", "/"], Config::default());
+        assert_eq!(
+            options.min_size_relative,
+            Some(SizeThreshold::MeanMultiple(0.5))
+        );
+        options.resolve_relative_sizes(sizes);
+        assert_eq!(options.min_size, 302);
+    }
+
+    #[test]
+    fn defaults_apply_without_either() {
+        let options = parse_with_config(&["/"], Config::default());
+        assert_eq!(options.min_size, 100000);
+        assert_eq!(options.max_size, None);
+        assert!(options.excludes.is_empty() && options.includes.is_empty());
+        assert_eq!(options.format, OutputFormat::Html);
+        assert_eq!(options.output, None);
+    }
+}
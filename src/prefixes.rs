@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use crate::errors::{ErrorLog, ScanError};
+use crate::group_by_content::{compare_content, ReadFailure};
+use crate::open_files::open_limited;
+use crate::progress::Phase;
+use crate::DedupFile;
+
+// How much of the start of each file we read to shortlist candidates.  Files smaller than this
+// aren't considered at all: a truncated copy is usually of something big, and it keeps the
+// shortlist short.
+const PROBE_LEN: u64 = 4096;
+
+// A file whose whole content is the start of another, bigger file's, e.g. an interrupted download.
+#[derive(Clone, Debug)]
+pub struct PrefixMatch {
+    pub prefix: PathBuf,
+    pub prefix_size: u64,
+    pub full: PathBuf,
+    pub full_size: u64,
+}
+
+// Find the files that are byte-for-byte prefixes of other files, for `--find-prefixes`.  Files
+// are shortlisted by the first `PROBE_LEN` bytes, which a prefix must share with the file it's a
+// prefix of, and then each smaller file is compared with the start of each bigger one on the
+// shortlist.  Anything we can't read is noted in `errors`.
+pub fn find_prefixes(files: &[DedupFile], errors: &ErrorLog) -> Vec<PrefixMatch> {
+    let mut shortlists: HashMap<Vec<u8>, Vec<&DedupFile>> = HashMap::new();
+    for df in files.iter().filter(|df| df.size >= PROBE_LEN) {
+        let mut probe = Vec::new();
+        match open_limited(&df.paths[0]).and_then(|f| f.take(PROBE_LEN).read_to_end(&mut probe)) {
+            Ok(_) => shortlists.entry(probe).or_default().push(df),
+            Err(e) => errors.record(ScanError::new(&df.paths[0], Phase::Compare, &e)),
+        }
+    }
+
+    let mut matches = Vec::new();
+    for mut shortlist in shortlists.into_values().filter(|s| s.len() > 1) {
+        shortlist.sort_by_key(|df| df.size);
+        for (i, small) in shortlist.iter().enumerate() {
+            for big in shortlist[i + 1..]
+                .iter()
+                .filter(|big| big.size > small.size)
+            {
+                if is_prefix(small, big, errors) {
+                    matches.push(PrefixMatch {
+                        prefix: small.paths[0].clone(),
+                        prefix_size: small.size,
+                        full: big.paths[0].clone(),
+                        full_size: big.size,
+                    });
+                }
+            }
+        }
+    }
+    matches.sort_by(|m1, m2| {
+        m1.full
+            .cmp(&m2.full)
+            .then_with(|| m1.prefix.cmp(&m2.prefix))
+    });
+    matches
+}
+
+// True iff all of `small` is the same as the start of `big`.
+fn is_prefix(small: &DedupFile, big: &DedupFile, errors: &ErrorLog) -> bool {
+    let (small_path, big_path) = (&small.paths[0], &big.paths[0]);
+    let opened = open_limited(small_path)
+        .map_err(|e| ScanError::new(small_path, Phase::Compare, &e))
+        .and_then(|f1| {
+            open_limited(big_path)
+                .map(|f2| (f1, f2))
+                .map_err(|e| ScanError::new(big_path, Phase::Compare, &e))
+        });
+    let result = opened.and_then(|(mut f1, mut f2)| {
+        compare_content(&mut f1, small.size, &mut f2, small.size, false).map_err(|e| match e {
+            ReadFailure::First(e) => ScanError::new(small_path, Phase::Compare, &e),
+            ReadFailure::Second(e) => ScanError::new(big_path, Phase::Compare, &e),
+        })
+    });
+    result.unwrap_or_else(|e| {
+        errors.record(e);
+        false
+    })
+}
+
+// Write a line for each file that's a prefix of another.
+pub fn write_prefixes(dest: &mut impl Write, matches: &[PrefixMatch]) -> io::Result<()> {
+    writeln!(dest, "Prefixes of other files: {}", matches.len())?;
+    for m in matches {
+        writeln!(
+            dest,
+            "  {} ({} bytes) is the start of {} ({} bytes)",
+            m.prefix.display(),
+            m.prefix_size,
+            m.full.display(),
+            m.full_size
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file_record, TempDir};
+
+    #[test]
+    fn only_exact_prefixes_of_bigger_files_match() {
+        let dir = TempDir::new();
+        let content: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        let mut near = content[..6000].to_vec();
+        near[5000] ^= 1;
+        let files = [
+            dir.write("full", &content),
+            dir.write("copy", &content),
+            dir.write("truncated", &content[..6000]),
+            dir.write("near", &near),
+            dir.write("short", &content[..1000]),
+        ]
+        .map(|path| file_record(&path));
+        let errors = ErrorLog::default();
+
+        let matches = find_prefixes(&files, &errors);
+        let name = |path: &PathBuf| path.file_name().unwrap().to_str().unwrap().to_owned();
+        let found: Vec<(String, String)> = matches
+            .iter()
+            .map(|m| (name(&m.prefix), name(&m.full)))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("truncated".into(), "copy".into()),
+                ("truncated".into(), "full".into())
+            ]
+        );
+        assert!(errors.take().is_empty());
+
+        let mut report = Vec::new();
+        write_prefixes(&mut report, &matches[1..]).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            format!(
+                "Prefixes of other files: 1\n  {} (6000 bytes) is the start of {} (10000 bytes)\n",
+                dir.path().join("truncated").display(),
+                dir.path().join("full").display()
+            )
+        );
+    }
+}
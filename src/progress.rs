@@ -0,0 +1,108 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often to emit progress events, at most.  Often enough for a progress bar to look lively, but
+// not so often that writing them slows us down.
+const MIN_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Traverse,
+    Compare,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Traverse => "traverse",
+            Phase::Compare => "compare",
+        }
+    }
+}
+
+struct ProgressSink {
+    dest: Box<dyn Write + Send>,
+    phase: Phase,
+    last_emitted: Option<Instant>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static DIRS: AtomicU64 = AtomicU64::new(0);
+static FILES: AtomicU64 = AtomicU64::new(0);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static SINK: Mutex<Option<ProgressSink>> = Mutex::new(None);
+
+// Start writing progress events to `dest`, as newline-delimited JSON objects such as
+// `{"dirs":12,"files":340,"bytes_read":0,"phase":"traverse"}`.  Until this is called, progress
+// isn't reported at all.
+pub fn enable_progress(dest: Box<dyn Write + Send>) {
+    *SINK.lock().unwrap_or_else(|e| e.into_inner()) = Some(ProgressSink {
+        dest,
+        phase: Phase::Traverse,
+        last_emitted: None,
+    });
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+// Note that we've read a directory.
+pub fn dir_read() {
+    DIRS.fetch_add(1, Ordering::Relaxed);
+    emit(false);
+}
+
+// Note that we've found a file worth considering.
+pub fn file_found() {
+    FILES.fetch_add(1, Ordering::Relaxed);
+    emit(false);
+}
+
+// Note that we've read some file content.
+pub fn bytes_read(bytes: usize) {
+    BYTES_READ.fetch_add(bytes as u64, Ordering::Relaxed);
+    emit(false);
+}
+
+// Move on to another phase, telling the listener straight away.
+pub fn set_phase(phase: Phase) {
+    if let Some(sink) = SINK.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        sink.phase = phase;
+    }
+    emit(true);
+}
+
+// Emit a last event with the final counts.
+pub fn finish_progress() {
+    emit(true);
+}
+
+// Write an event, unless we did so very recently and this isn't `forced`.  Failing to write
+// progress is no reason to stop, so errors are ignored.
+fn emit(forced: bool) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut sink = SINK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(sink) = sink.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    if !forced
+        && sink
+            .last_emitted
+            .is_some_and(|last| now.duration_since(last) < MIN_INTERVAL)
+    {
+        return;
+    }
+    sink.last_emitted = Some(now);
+    let _ = writeln!(
+        sink.dest,
+        "{{\"dirs\":{},\"files\":{},\"bytes_read\":{},\"phase\":\"{}\"}}",
+        DIRS.load(Ordering::Relaxed),
+        FILES.load(Ordering::Relaxed),
+        BYTES_READ.load(Ordering::Relaxed),
+        sink.phase.name()
+    );
+    let _ = sink.dest.flush();
+}
@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::DedupFile;
+
+// Rewrite a path relative to a base directory, or leave it as-is if it doesn't live under that
+// base.  Both are assumed to be in the same form (e.g. both absolute and canonical).
+pub fn make_relative(path: &Path, base: &Path) -> PathBuf {
+    match path.strip_prefix(base) {
+        Ok(rel) => rel.to_path_buf(),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+// Rewrite a list of paths relative to a base directory.
+pub fn relativise_paths(paths: &mut [PathBuf], base: &Path) {
+    for path in paths {
+        *path = make_relative(path, base);
+    }
+}
+
+// Rewrite the paths of every file in a group relative to a base directory.
+pub fn relativise_group(group: &mut [DedupFile], base: &Path) {
+    for df in group {
+        relativise_paths(&mut df.paths, base);
+    }
+}
+
+// Tidy a path up lexically, without looking at the filesystem: drop `.` components, and let each
+// `..` cancel out the name before it.  A `..` at the root is the root, and one at the start of a
+// relative path is kept, since there's nothing to cancel.  Unlike canonicalising, this doesn't
+// resolve symlinks, so if `b` is a symlink then `a/b/..` may not really be `a`.
+pub fn normalise_path(path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalised.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalised.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalised.push(".."),
+            },
+            _ => normalised.push(component),
+        }
+    }
+    if normalised.as_os_str().is_empty() && !path.as_os_str().is_empty() {
+        normalised.push(".");
+    }
+    normalised
+}
+
+// Normalise the paths of the files loaded from one dump.  Paths in a dump all come from one
+// machine, so two spellings of the same path are the same file: any path we've already seen is
+// dropped, along with any file left without a path.
+pub fn normalise_dump_paths(files: &mut Vec<DedupFile>) {
+    let mut seen = HashSet::new();
+    for df in files.iter_mut() {
+        df.paths = df
+            .paths
+            .iter()
+            .map(|path| normalise_path(path))
+            .filter(|path| seen.insert(path.clone()))
+            .collect();
+    }
+    files.retain(|df| !df.paths.is_empty());
+}
+
+// The canonical form of the current working directory.
+pub fn canonical_cwd() -> io::Result<PathBuf> {
+    fs::canonicalize(env::current_dir()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_file;
+
+    #[test]
+    fn paths_inside_cwd_become_relative_and_others_stay_absolute() {
+        let cwd = canonical_cwd().unwrap();
+        let outside = cwd.parent().unwrap().join("elsewhere").join("x");
+        let mut group = vec![
+            fake_file(&[cwd.join("a").join("b").to_str().unwrap()], 1, 1),
+            fake_file(&[outside.to_str().unwrap()], 1, 2),
+        ];
+        relativise_group(&mut group, &cwd);
+        assert_eq!(group[0].paths, [PathBuf::from("a/b")]);
+        assert_eq!(group[1].paths, [outside]);
+    }
+
+    #[test]
+    fn paths_under_relative_to_base_are_stripped_and_others_stay_absolute() {
+        let base = Path::new("/mnt/archive");
+        let mut group = vec![
+            fake_file(&["/mnt/archive/photos/a.jpg", "/mnt/archive2/a.jpg"], 1, 1),
+            fake_file(&["/home/me/a.jpg"], 1, 2),
+        ];
+        relativise_group(&mut group, base);
+        assert_eq!(
+            group[0].paths,
+            [
+                PathBuf::from("photos/a.jpg"),
+                PathBuf::from("/mnt/archive2/a.jpg")
+            ]
+        );
+        assert_eq!(group[1].paths, [PathBuf::from("/home/me/a.jpg")]);
+    }
+
+    #[test]
+    fn dots_are_collapsed_without_looking_at_the_filesystem() {
+        for (path, normalised) in [
+            ("a/./b", "a/b"),
+            ("a/b/../c", "a/c"),
+            ("./a/b", "a/b"),
+            ("/a/b/../../c/.", "/c"),
+            ("/..", "/"),
+            ("../a/../../b", "../../b"),
+            ("a/..", "."),
+            ("./", "."),
+            ("", ""),
+        ] {
+            assert_eq!(
+                normalise_path(Path::new(path)),
+                Path::new(normalised),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn two_spellings_of_a_path_in_a_dump_are_one_path() {
+        let mut files = vec![
+            fake_file(&["/d/a", "/d/./a", "/d/x/../b"], 1, 1),
+            fake_file(&["/d/b"], 1, 2),
+        ];
+        normalise_dump_paths(&mut files);
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files[0].paths,
+            [PathBuf::from("/d/a"), PathBuf::from("/d/b")]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// Laying out the groups as directories of symlinks, for `--review-dir`.
+//
+// Each group gets a directory of its own, numbered in the order of the report, holding a symlink
+// to each path in the group, so that the groups can be looked through in a file manager.  Links
+// are named after the paths they lead to; where two in a group have the same name, as copies
+// often do, the later ones are numbered.  Nothing is overwritten: if a group's directory already
+// exists, we give up.
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{self, Path};
+
+use crate::DedupFile;
+
+// Create a directory under `dir` (which is created if need be) for each group, holding
+// symlinks to its files.
+pub fn write_review_dir(dir: &Path, groups: &[Vec<DedupFile>]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (i, group) in groups.iter().enumerate() {
+        let group_dir = dir.join(format!("group-{:04}", i + 1));
+        fs::create_dir(&group_dir)?;
+        let mut names = HashSet::new();
+        for path in group.iter().flat_map(|df| &df.paths) {
+            let name = unique_name(path.file_name().unwrap_or(path.as_os_str()), &mut names);
+            // The link is somewhere else entirely, so it has to lead to an absolute path.
+            symlink(path::absolute(path)?, group_dir.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+// `name` if it isn't taken yet, or else the first of `name~2`, `name~3` and so on that isn't
+// (keeping any extension at the end, so that `a.jpg` becomes `a~2.jpg`).  Whichever it is gets
+// added to `taken`.
+fn unique_name(name: &OsStr, taken: &mut HashSet<OsString>) -> OsString {
+    let name_path = Path::new(name);
+    let mut candidate = name.to_os_string();
+    let mut n = 1;
+    while taken.contains(&candidate) {
+        n += 1;
+        candidate = name_path.file_stem().unwrap_or(name).to_os_string();
+        candidate.push(format!("~{}", n));
+        if let Some(extension) = name_path.extension() {
+            candidate.push(".");
+            candidate.push(extension);
+        }
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file_record, TempDir};
+    use std::path::PathBuf;
+
+    // The entries of a directory and where each leads, in order of name.
+    fn links_in(dir: &Path) -> Vec<(OsString, PathBuf)> {
+        let mut links: Vec<(OsString, PathBuf)> = fs::read_dir(dir)
+            .expect("Failed to read review directory")
+            .map(|entry| {
+                let entry = entry.expect("Failed to read review directory");
+                let target = fs::read_link(entry.path()).expect("Not a symlink");
+                (entry.file_name(), target)
+            })
+            .collect();
+        links.sort();
+        links
+    }
+
+    #[test]
+    fn each_group_gets_a_directory_of_links_to_its_files() {
+        let dir = TempDir::new();
+        let a = dir.write("x/a.jpg", "one");
+        let a2 = dir.write("y/a.jpg", "one");
+        let a3 = dir.write("z/a.jpg", "one");
+        let b = dir.write("x/b", "two");
+        let c = dir.write("y/c", "two");
+        let groups = vec![
+            vec![file_record(&a), file_record(&a2), file_record(&a3)],
+            vec![file_record(&b), file_record(&c)],
+        ];
+        let review = dir.path().join("review");
+        write_review_dir(&review, &groups).unwrap();
+
+        let mut group_dirs: Vec<OsString> = fs::read_dir(&review)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        group_dirs.sort();
+        assert_eq!(group_dirs, ["group-0001", "group-0002"]);
+        let named = |name: &str, target: &Path| (OsString::from(name), target.to_path_buf());
+        assert_eq!(
+            links_in(&review.join("group-0001")),
+            [
+                named("a.jpg", &a),
+                named("a~2.jpg", &a2),
+                named("a~3.jpg", &a3)
+            ]
+        );
+        assert_eq!(
+            links_in(&review.join("group-0002")),
+            [named("b", &b), named("c", &c)]
+        );
+        // The links lead to the files themselves.
+        assert_eq!(fs::read(review.join("group-0002/c")).unwrap(), b"two");
+
+        // Nothing already there is overwritten.
+        assert!(write_review_dir(&review, &groups).is_err());
+        assert_eq!(links_in(&review.join("group-0002")).len(), 2);
+    }
+}
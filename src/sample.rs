@@ -0,0 +1,98 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hash::Fnv1a;
+
+// Decides which files to include in a random sample of the tree, for `--sample-rate`.
+//
+// Rather than drawing from a generator in traversal order (which depends on the order the
+// filesystem lists directories in, and so may not be the same from one run to the next), each
+// file gets its own draw, seeded by the sampler's seed and the file's identity.  The same seed
+// therefore always picks the same files, and all the hard links to a file are in or out together.
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    rate: f64,
+    seed: u64,
+}
+
+impl Sampler {
+    // Sample roughly `rate` of all files (between 0 and 1).
+    pub fn new(rate: f64, seed: u64) -> Sampler {
+        Sampler { rate, seed }
+    }
+
+    // True iff the file with this identity (see `file_key` and `path_key`) is in the sample.
+    pub fn includes(&self, key: u64) -> bool {
+        let draw = splitmix64(self.seed ^ splitmix64(key));
+        // The top 53 bits make a uniformly distributed float in [0, 1).
+        ((draw >> 11) as f64 / (1u64 << 53) as f64) < self.rate
+    }
+}
+
+// The identity of a file on disk, by device and inode numbers.
+pub fn file_key(device: u64, inode: u64) -> u64 {
+    splitmix64(device) ^ inode
+}
+
+// The identity of a file without a usable inode number, by path (an FNV-1a hash of its bytes).
+pub fn path_key(path: &Path) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.update(path.as_os_str().as_bytes());
+    hash.finish()
+}
+
+// A seed for when we weren't given one, which differs from run to run.
+pub fn fresh_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    splitmix64(nanos ^ u64::from(process::id()))
+}
+
+// One step of the SplitMix64 generator: a cheap, well-mixed function of a 64-bit state.
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(sampler: Sampler) -> Vec<u64> {
+        (0..1000).filter(|&key| sampler.includes(key)).collect()
+    }
+
+    #[test]
+    fn the_same_seed_always_picks_the_same_files() {
+        let picked = sample(Sampler::new(0.25, 42));
+        assert_eq!(picked, sample(Sampler::new(0.25, 42)));
+        assert_ne!(picked, sample(Sampler::new(0.25, 43)));
+        // Roughly the rate asked for.
+        assert!((200..300).contains(&picked.len()), "{}", picked.len());
+    }
+
+    #[test]
+    fn rates_of_zero_and_one_pick_nothing_and_everything() {
+        assert!(sample(Sampler::new(0.0, 7)).is_empty());
+        assert_eq!(sample(Sampler::new(1.0, 7)).len(), 1000);
+    }
+
+    #[test]
+    fn path_keys_are_fnv1a_hashes_of_the_path() {
+        assert_eq!(path_key(Path::new("a")), 0xaf63dc4c8601ec8c);
+        assert_ne!(path_key(Path::new("/x/a")), path_key(Path::new("/x/b")));
+    }
+
+    #[test]
+    fn splitmix64_gives_the_reference_sequence() {
+        // The first two outputs of the generator seeded with zero.
+        assert_eq!(splitmix64(0), 0xe220a8397b1dcdaf);
+        assert_eq!(splitmix64(0x9e3779b97f4a7c15), 0x6e789e6aa1b965f4);
+    }
+}
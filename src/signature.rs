@@ -0,0 +1,88 @@
+// Leaving out files that start with a known signature, for `--exclude-signature`.
+//
+// Some content is copied everywhere on purpose, such as a placeholder that an application writes
+// into every new file, and forms big groups of duplicates that nobody wants to hear about.  Such
+// files are recognised by their first few bytes, so only those are read, before the files are
+// compared with each other at all.
+
+use std::io::Read;
+
+use crate::errors::{ErrorLog, ScanError};
+use crate::open_files::open_limited;
+use crate::progress::Phase;
+use crate::DedupFile;
+
+// Drop the files in a group of candidates whose content starts with `signature`.  Files shorter
+// than the signature can't match, and files only in a dump can't be checked, so those are kept.
+// A file we can't read is dropped, as comparing it would fail too, and noted in `errors`.
+pub fn drop_signature_matches(
+    group: Vec<DedupFile>,
+    signature: &[u8],
+    errors: &ErrorLog,
+) -> Vec<DedupFile> {
+    group
+        .into_iter()
+        .filter(|df| {
+            if df.size < signature.len() as u64 || !df.is_local() {
+                return true;
+            }
+            let mut start = Vec::with_capacity(signature.len());
+            let read = open_limited(&df.paths[0])
+                .and_then(|f| f.take(signature.len() as u64).read_to_end(&mut start));
+            match read {
+                Ok(_) => start != signature,
+                Err(e) => {
+                    errors.record(ScanError::new(&df.paths[0], Phase::Compare, &e));
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group_by_content::group_by_content;
+    use crate::options::parse_hex_bytes;
+    use crate::testing::{file_record, options, TempDir};
+    use std::path::PathBuf;
+
+    #[test]
+    fn files_starting_with_the_signature_are_left_out_and_the_rest_still_group() {
+        let dir = TempDir::new();
+        let mut files: Vec<DedupFile> = [
+            ("template1", "MAGIC-same"),
+            ("template2", "MAGIC-same"),
+            ("copy1", "other-same"),
+            ("copy2", "other-same"),
+            ("different", "MAGIX-same"),
+        ]
+        .iter()
+        .map(|(name, content)| file_record(&dir.write(name, content)))
+        .collect();
+        // Shorter than the signature, so it can't match.
+        files.push(file_record(&dir.write("short", "MAG")));
+        let signature = parse_hex_bytes("4d41474943").unwrap();
+        assert_eq!(signature, b"MAGIC");
+        let errors = ErrorLog::default();
+
+        let kept = drop_signature_matches(files, &signature, &errors);
+        let mut names: Vec<&str> = kept
+            .iter()
+            .map(|df| df.paths[0].file_name().unwrap().to_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["copy1", "copy2", "different", "short"]);
+        // Only files of the same size are ever compared.
+        let same_size: Vec<DedupFile> = kept.into_iter().filter(|df| df.size == 10).collect();
+        let groups: Vec<Vec<DedupFile>> =
+            group_by_content(vec![same_size], &options(&["/"]), &errors).collect();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+        let paths: Vec<&PathBuf> = groups[0].iter().map(|df| &df.paths[0]).collect();
+        assert!(paths.contains(&&dir.path().join("copy1")), "{:?}", paths);
+        assert!(paths.contains(&&dir.path().join("copy2")), "{:?}", paths);
+        assert!(errors.take().is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+// Recognising copies of the same file in filesystem snapshots, for `--snapshot-aware`.
+//
+// Btrfs and ZFS snapshots show the same file under many paths, each with its own inode number, so
+// they look like duplicates of each other and of the live file.  A snapshot root is given as a
+// pattern of `/`-separated wildcards (as for `--exclude`), one per path component, such as
+// `.zfs/snapshot/*`.  An absolute pattern must match from the top of the path; a relative one may
+// match anywhere in it.  The rest of the path below the match is the file's path within the
+// snapshot.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::glob::glob_match;
+use crate::DedupFile;
+
+// Where ZFS, Snapper (for Btrfs) and NetApp put their snapshots, if not told otherwise.
+pub const DEFAULT_SNAPSHOT_ROOTS: [&str; 3] =
+    [".zfs/snapshot/*", ".snapshots/*/snapshot", ".snapshot/*"];
+
+// The part of `path` below a snapshot root matching one of `patterns`, if it's in a snapshot.
+pub fn path_in_snapshot<'a>(path: &'a Path, patterns: &[String]) -> Option<&'a Path> {
+    let components: Vec<Component> = path.components().collect();
+    let first = usize::from(path.has_root());
+    for pattern in patterns {
+        let names: Vec<&str> = pattern.split('/').filter(|name| !name.is_empty()).collect();
+        let last_start = if pattern.starts_with('/') {
+            first
+        } else {
+            components.len()
+        };
+        // There must be something left below the root, or it's not in the snapshot.
+        for start in first..=last_start {
+            let end = start + names.len();
+            if end >= components.len() {
+                break;
+            }
+            let matched = components[start..end].iter().zip(&names).all(|(c, name)| {
+                matches!(c, Component::Normal(c) if glob_match(name, &c.to_string_lossy()))
+            });
+            if matched {
+                let mut rest = path.components();
+                rest.nth(end - 1);
+                return Some(rest.as_path());
+            }
+        }
+    }
+    None
+}
+
+// Drop the files in a group of duplicates that are only snapshot copies of another in the group:
+// those in a snapshot whose path within it is also the end of another file's path, whether that's
+// the live file or an earlier copy in another snapshot.  Returns what's left, in the same order,
+// and how many files were dropped.
+pub fn drop_snapshot_copies(group: Vec<DedupFile>, patterns: &[String]) -> (Vec<DedupFile>, usize) {
+    let in_snapshot: Vec<Option<PathBuf>> = group
+        .iter()
+        .map(|df| path_in_snapshot(&df.paths[0], patterns).map(Path::to_path_buf))
+        .collect();
+    let mut keep = vec![true; group.len()];
+    for (i, rest) in in_snapshot.iter().enumerate() {
+        let Some(rest) = rest else {
+            continue;
+        };
+        keep[i] = !(0..group.len()).any(|j| {
+            j != i
+                && (in_snapshot[j].is_none() || (j < i && keep[j]))
+                && group[j].paths.iter().any(|path| path.ends_with(rest))
+        });
+    }
+    let dropped = keep.iter().filter(|&&k| !k).count();
+    let group = group
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(df, keep)| keep.then_some(df))
+        .collect();
+    (group, dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_file;
+
+    fn snapshot_path<'a>(path: &'a str, patterns: &[&str]) -> Option<&'a Path> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        path_in_snapshot(Path::new(path), &patterns)
+    }
+
+    fn names(group: &[DedupFile]) -> Vec<&Path> {
+        group.iter().map(|df| df.paths[0].as_path()).collect()
+    }
+
+    #[test]
+    fn paths_below_a_snapshot_root_are_found_within_the_snapshot() {
+        let defaults = DEFAULT_SNAPSHOT_ROOTS;
+        assert_eq!(
+            snapshot_path("/tank/.zfs/snapshot/daily-1/docs/a", &defaults),
+            Some(Path::new("docs/a"))
+        );
+        assert_eq!(
+            snapshot_path("/.snapshots/12/snapshot/etc/fstab", &defaults),
+            Some(Path::new("etc/fstab"))
+        );
+        assert_eq!(snapshot_path("/tank/docs/a", &defaults), None);
+        // The snapshot root itself isn't in the snapshot.
+        assert_eq!(
+            snapshot_path("/tank/.zfs/snapshot/daily-1", &defaults),
+            None
+        );
+        // An absolute pattern only matches from the top.
+        assert_eq!(
+            snapshot_path("/backup/snaps/x/a", &["/backup/snaps/*"]),
+            Some(Path::new("a"))
+        );
+        assert_eq!(
+            snapshot_path("/mnt/backup/snaps/x/a", &["/backup/snaps/*"]),
+            None
+        );
+    }
+
+    #[test]
+    fn snapshot_copies_of_the_live_file_are_dropped() {
+        let group = vec![
+            fake_file(&["/tank/.zfs/snapshot/mon/docs/a"], 10, 1),
+            fake_file(&["/tank/docs/a"], 10, 2),
+            fake_file(&["/tank/.zfs/snapshot/tue/docs/a"], 10, 3),
+            fake_file(&["/tank/docs/b"], 10, 4),
+        ];
+        let patterns = [String::from(".zfs/snapshot/*")];
+        let (left, dropped) = drop_snapshot_copies(group, &patterns);
+        assert_eq!(dropped, 2);
+        assert_eq!(
+            names(&left),
+            ["/tank/docs/a", "/tank/docs/b"].map(Path::new)
+        );
+    }
+
+    #[test]
+    fn of_snapshot_copies_with_no_live_file_the_first_stays() {
+        let group = vec![
+            fake_file(&["/tank/.zfs/snapshot/mon/old"], 10, 1),
+            fake_file(&["/tank/.zfs/snapshot/tue/old"], 10, 2),
+            // The same content under another name is a real duplicate.
+            fake_file(&["/tank/.zfs/snapshot/tue/renamed"], 10, 3),
+        ];
+        let patterns = [String::from(".zfs/snapshot/*")];
+        let (left, dropped) = drop_snapshot_copies(group, &patterns);
+        assert_eq!(dropped, 1);
+        assert_eq!(
+            names(&left),
+            [
+                "/tank/.zfs/snapshot/mon/old",
+                "/tank/.zfs/snapshot/tue/renamed"
+            ]
+            .map(Path::new)
+        );
+    }
+}
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::group_by_content::{compared_len, CompareOptions};
+use crate::keep::{select_representative, KeepPolicy};
+use crate::options::{CountStyle, Options};
+use crate::DedupFile;
+
+// How many files we could remove from a group of duplicates, keeping only one, or only the
+// protected ones if there are any.
+pub fn redundant_files(group: &[DedupFile]) -> u64 {
+    let kept = group.iter().filter(|df| df.protected).count().max(1);
+    group.len().saturating_sub(kept) as u64
+}
+
+// True iff removing every path we have to a file would free its space.  Hard links to it from
+// outside the tree (or from anywhere, with `--no-inode`, when we can't tell they're the same file)
+// would keep its content on disk.
+pub fn frees_space(df: &DedupFile) -> bool {
+    df.nlink <= df.paths.len() as u64
+}
+
+// The space we could reclaim by removing the redundant files from a group of duplicates.  Hard
+// links within the tree are already one file, but a file with other links as well doesn't free
+// anything, so it makes the best one to keep (if nothing's protected).  Otherwise one of the rest
+// has to stay, and since the files in a group may differ in size (with `--ignore-whitespace`), we
+// count the biggest as kept, so as not to overstate the saving.
+pub fn reclaimable_bytes(group: &[DedupFile]) -> u64 {
+    let freeable = |df: &DedupFile| !df.protected && frees_space(df);
+    let sizes: Vec<u64> = group
+        .iter()
+        .filter(|df| freeable(df))
+        .map(|df| df.size)
+        .collect();
+    let freed: u64 = sizes.iter().sum();
+    if sizes.len() == group.len() {
+        freed - sizes.iter().max().unwrap_or(&0)
+    } else {
+        freed
+    }
+}
+
+// Sort groups so that the one with the most reclaimable space comes first.  Ties are broken by
+// path, so that the order (and so each group's rank) is the same from one run to the next.
+pub fn sort_by_reclaimable(groups: &mut [Vec<DedupFile>]) {
+    groups.sort_by(|g1, g2| {
+        reclaimable_bytes(g2)
+            .cmp(&reclaimable_bytes(g1))
+            .then_with(|| g1[0].paths.cmp(&g2[0].paths))
+    });
+}
+
+// The mean of `values`, or zero if there are none.
+pub fn mean(values: &[u64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64
+}
+
+// The smallest value that at least `percentile`% of `values` are less than or equal to (the
+// nearest-rank method), or zero if there are no values.
+pub fn percentile(values: &mut [u64], percentile: f64) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+    values.sort_unstable();
+    let rank = (percentile / 100.0 * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+// The binary units sizes are written in, each 1024 times the last.
+const BINARY_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+// Format a number of bytes in binary units to one decimal place, e.g. 1.5 MiB, for
+// `--pretty-sizes`.  Less than a KiB is written exactly, e.g. 512 B.
+pub fn format_bytes(n: u64) -> String {
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64;
+    let mut unit = 0;
+    // Move up a unit whenever rounding would give 1024.0 of this one.
+    while value >= 1023.95 && unit + 1 < BINARY_UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, BINARY_UNITS[unit])
+}
+
+// Format a number with commas between groups of three digits, e.g. 1,200,000.
+pub fn format_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut formatted = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(c);
+    }
+    formatted
+}
+
+// Write the totals over all the groups on a single line, for `--count-only`: the number of groups,
+// of redundant files and of reclaimable bytes, either tab-separated in that order or as a JSON
+// object.  With `WastedBytes`, only the last is written, as a bare integer.  Groups are counted
+// as they arrive, so this works in low-memory mode too.
+pub fn write_counts<G: AsRef<[DedupFile]>>(
+    dest: &mut impl Write,
+    dupes: impl IntoIterator<Item = G>,
+    style: CountStyle,
+) -> io::Result<()> {
+    let (mut groups, mut files, mut bytes) = (0, 0, 0);
+    for group in dupes {
+        groups += 1;
+        files += redundant_files(group.as_ref());
+        bytes += reclaimable_bytes(group.as_ref());
+    }
+    match style {
+        CountStyle::Tsv => writeln!(dest, "{}\t{}\t{}", groups, files, bytes),
+        CountStyle::Json => writeln!(
+            dest,
+            "{{\"groups\": {}, \"redundant_files\": {}, \"reclaimable_bytes\": {}}}",
+            groups, files, bytes
+        ),
+        CountStyle::WastedBytes => writeln!(dest, "{}", bytes),
+    }
+}
+
+// Write a histogram of file sizes, for `--size-histogram`.  Sizes are binned by powers of two, so
+// each row but the first (for empty files) covers sizes from one power up to just below the next.
+// Each row has the number of files and their total size, and a bar scaled to the biggest count.
+// Empty bins between the smallest and biggest files are shown too, to keep the shape honest.
+pub fn write_size_histogram(dest: &mut impl Write, files: &[DedupFile]) -> io::Result<()> {
+    const BAR_WIDTH: u64 = 40;
+    // Bin 0 is for empty files, and bin k > 0 for sizes from 2^(k-1) up to 2^k - 1.
+    let mut bins = [(0u64, 0u64); 65];
+    for df in files {
+        let bin = (u64::BITS - df.size.leading_zeros()) as usize;
+        bins[bin].0 += 1;
+        bins[bin].1 += df.size;
+    }
+
+    writeln!(dest, "{:<20} {:>12} {:>20}", "Size", "Files", "Bytes")?;
+    let Some(first) = bins.iter().position(|&(count, _)| count > 0) else {
+        return Ok(());
+    };
+    let last = bins
+        .iter()
+        .rposition(|&(count, _)| count > 0)
+        .unwrap_or(first);
+    let most = bins.iter().map(|&(count, _)| count).max().unwrap_or(1);
+    for (bin, &(count, bytes)) in bins.iter().enumerate().take(last + 1).skip(first) {
+        let range = match bin {
+            0 => "0 B".to_owned(),
+            _ => format!(
+                "{} - {}",
+                format_power_of_two(bin - 1),
+                format_power_of_two(bin)
+            ),
+        };
+        let row = format!(
+            "{:<20} {:>12} {:>20} {}",
+            range,
+            format_thousands(count),
+            format_thousands(bytes),
+            "#".repeat((count * BAR_WIDTH).div_ceil(most) as usize)
+        );
+        writeln!(dest, "{}", row.trim_end())?;
+    }
+    Ok(())
+}
+
+// Write how much work comparing the shortlisted files would be, for `--probe`: how many files
+// there are in groups of the same size, how many such groups, and how many bytes we'd read at
+// most.  Files usually differ early on, so the real figure is often far lower.
+pub fn write_probe(
+    dest: &mut impl Write,
+    shortlist: &[Vec<DedupFile>],
+    options: &Options,
+) -> io::Result<()> {
+    let compare = CompareOptions::new(options);
+    let files: usize = shortlist.iter().map(Vec::len).sum();
+    let bytes: u64 = shortlist
+        .iter()
+        .flatten()
+        .map(|df| compared_len(df.size, compare))
+        .sum();
+    writeln!(dest, "Files to compare: {}", format_thousands(files as u64))?;
+    writeln!(
+        dest,
+        "Size groups: {}",
+        format_thousands(shortlist.len() as u64)
+    )?;
+    writeln!(dest, "Bytes to read (at most): {}", format_thousands(bytes))
+}
+
+// Format 2^k bytes in binary units, e.g. 4 KiB.
+fn format_power_of_two(k: usize) -> String {
+    format!("{} {}", 1u64 << (k % 10), BINARY_UNITS[k / 10])
+}
+
+// Write a table of how much space is reclaimable by file extension, with the most wasteful first.
+// Each group is counted under the (lowercased) extension of the file we would keep from it, by the
+// `keep` policy if there is one.
+pub fn write_extension_summary(
+    dest: &mut impl Write,
+    groups: &[Vec<DedupFile>],
+    options: &Options,
+) -> io::Result<()> {
+    let keep = options.keep.unwrap_or(KeepPolicy::First);
+    let mut by_extension: HashMap<String, (u64, u64)> = HashMap::new();
+    for group in groups {
+        let representative = &group[select_representative(group, keep)];
+        let extension = representative.paths[0]
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "(none)".to_owned());
+        let totals = by_extension.entry(extension).or_default();
+        totals.0 += 1;
+        totals.1 += reclaimable_bytes(group);
+    }
+
+    let mut rows: Vec<(String, (u64, u64))> = by_extension.into_iter().collect();
+    rows.sort_by(|(ext1, (_, bytes1)), (ext2, (_, bytes2))| {
+        bytes2.cmp(bytes1).then_with(|| ext1.cmp(ext2))
+    });
+
+    writeln!(
+        dest,
+        "{:<16} {:>10} {:>20}",
+        "Extension", "Groups", "Reclaimable bytes"
+    )?;
+    for (extension, (groups, bytes)) in rows {
+        writeln!(
+            dest,
+            "{:<16} {:>10} {:>20}",
+            extension,
+            format_thousands(groups),
+            format_thousands(bytes)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::parse_file_size_spec;
+    use crate::testing::{fake_file, options};
+    use std::path::Path;
+
+    #[test]
+    fn sizes_move_up_a_unit_at_each_power_of_1024() {
+        let kib = 1024;
+        let mib = 1024 * kib;
+        let gib = 1024 * mib;
+        for (n, expected) in [
+            (0, "0 B"),
+            (1, "1 B"),
+            (1023, "1023 B"),
+            (kib, "1.0 KiB"),
+            (kib + 51, "1.0 KiB"),
+            (kib + 52, "1.1 KiB"),
+            (3 * kib / 2, "1.5 KiB"),
+            (mib - 52, "1023.9 KiB"),
+            // Anything that would round to 1024.0 of one unit is 1.0 of the next.
+            (mib - 51, "1.0 MiB"),
+            (mib - 1, "1.0 MiB"),
+            (mib, "1.0 MiB"),
+            (3 * mib / 2, "1.5 MiB"),
+            (gib, "1.0 GiB"),
+            (1024 * gib, "1.0 TiB"),
+            (1024 * 1024 * gib, "1.0 PiB"),
+            (1024 * 1024 * 1024 * gib, "1.0 EiB"),
+            (u64::MAX, "16.0 EiB"),
+        ] {
+            assert_eq!(format_bytes(n), expected, "{}", n);
+        }
+        // Each unit is the one the same suffix means in a size given on the command line.
+        for (spec, expected) in [
+            ("1KiB", "1.0 KiB"),
+            ("2MiB", "2.0 MiB"),
+            ("3GiB", "3.0 GiB"),
+        ] {
+            let n = parse_file_size_spec(spec).unwrap();
+            assert_eq!(format_bytes(n), expected);
+        }
+    }
+
+    #[test]
+    fn reclaimable_bytes_counts_every_file_but_the_one_kept() {
+        let group: Vec<DedupFile> = (1..=3).map(|i| fake_file(&["/f"], 10, i)).collect();
+        assert_eq!(reclaimable_bytes(&group), 20);
+
+        // A file with links outside the tree stays whatever we do, so the rest are all freed.
+        let mut linked = group.clone();
+        linked[1].nlink = 2;
+        assert_eq!(reclaimable_bytes(&linked), 20);
+        let mut protected = group.clone();
+        protected[0].protected = true;
+        protected[2].protected = true;
+        assert_eq!(reclaimable_bytes(&protected), 10);
+    }
+
+    #[test]
+    fn counts_are_totals_over_all_the_groups() {
+        let mut linked = fake_file(&["/d/a", "/d/a2"], 1000, 4);
+        linked.nlink = 3;
+        let groups = vec![
+            (1..=3).map(|i| fake_file(&["/f"], 10, i)).collect(),
+            vec![linked, fake_file(&["/d/b"], 1000, 5)],
+        ];
+        let counts = |style| {
+            let mut dest = Vec::new();
+            write_counts(&mut dest, &groups, style).unwrap();
+            String::from_utf8(dest).unwrap()
+        };
+        // The file with a link we haven't found is the one to keep, so the other's bytes are freed.
+        assert_eq!(counts(CountStyle::Tsv), "2\t3\t1020\n");
+        assert_eq!(
+            counts(CountStyle::Json),
+            "{\"groups\": 2, \"redundant_files\": 3, \"reclaimable_bytes\": 1020}\n"
+        );
+        assert_eq!(counts(CountStyle::WastedBytes), "1020\n");
+        let mut dest = Vec::new();
+        write_counts(&mut dest, Vec::<Vec<DedupFile>>::new(), CountStyle::Tsv).unwrap();
+        assert_eq!(dest, b"0\t0\t0\n");
+    }
+
+    #[test]
+    fn reclaimable_bytes_keeps_the_biggest_of_files_of_different_sizes() {
+        // As with `--ignore-whitespace`, where e.g. CRLF and LF copies match.
+        let group = vec![
+            fake_file(&["/lf"], 100, 1),
+            fake_file(&["/crlf"], 110, 2),
+            fake_file(&["/lf2"], 100, 3),
+        ];
+        assert_eq!(reclaimable_bytes(&group), 200);
+        let mut protected = group.clone();
+        protected[0].protected = true;
+        assert_eq!(reclaimable_bytes(&protected), 210);
+    }
+
+    #[test]
+    fn groups_are_ranked_by_reclaimable_space_then_by_path() {
+        let pair = |path1, path2, size, inode| {
+            vec![
+                fake_file(&[path1], size, inode),
+                fake_file(&[path2], size, inode + 1),
+            ]
+        };
+        let mut groups = vec![
+            pair("/d", "/d2", 10, 1),
+            pair("/c", "/c2", 300, 3),
+            pair("/b", "/b2", 10, 5),
+            vec![
+                fake_file(&["/a"], 100, 7),
+                fake_file(&["/a2"], 100, 8),
+                fake_file(&["/a3"], 100, 9),
+            ],
+        ];
+        sort_by_reclaimable(&mut groups);
+        let order: Vec<(&Path, u64)> = groups
+            .iter()
+            .map(|g| (g[0].paths[0].as_path(), reclaimable_bytes(g)))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (Path::new("/c"), 300),
+                (Path::new("/a"), 200),
+                (Path::new("/b"), 10),
+                (Path::new("/d"), 10)
+            ]
+        );
+    }
+
+    #[test]
+    fn percentile_is_by_nearest_rank() {
+        let mut sizes: Vec<u64> = (1..=10).rev().map(|n| n * 100).collect();
+        assert_eq!(percentile(&mut sizes, 90.0), 900);
+        assert_eq!(percentile(&mut sizes, 91.0), 1000);
+        assert_eq!(percentile(&mut sizes, 50.0), 500);
+        assert_eq!(percentile(&mut sizes, 100.0), 1000);
+        assert_eq!(percentile(&mut sizes, 0.0), 100);
+        assert_eq!(percentile(&mut [7], 99.0), 7);
+        assert_eq!(percentile(&mut [], 90.0), 0);
+    }
+
+    #[test]
+    fn extension_summary_puts_the_most_reclaimable_first() {
+        let groups = vec![
+            vec![
+                fake_file(&["/a.JPG"], 1000, 1),
+                fake_file(&["/b.jpg"], 1000, 2),
+            ],
+            vec![fake_file(&["/c.txt"], 10, 3), fake_file(&["/d.txt"], 10, 4)],
+            vec![
+                fake_file(&["/e.jpg"], 500, 5),
+                fake_file(&["/f.jpeg"], 500, 6),
+            ],
+            vec![
+                fake_file(&["/Makefile"], 20, 7),
+                fake_file(&["/g.txt"], 20, 8),
+            ],
+        ];
+        let mut dest = Vec::new();
+        write_extension_summary(&mut dest, &groups, &options(&["/"])).unwrap();
+        assert_eq!(
+            String::from_utf8(dest).unwrap(),
+            "Extension            Groups    Reclaimable bytes\n\
+             jpg                       2                1,500\n\
+             (none)                    1                   20\n\
+             txt                       1                   10\n"
+        );
+    }
+
+    #[test]
+    fn sizes_are_binned_by_powers_of_two_with_empty_bins_between() {
+        let files: Vec<DedupFile> = [0, 1, 3, 3, 1500, 2047, 5000]
+            .iter()
+            .zip(1..)
+            .map(|(&size, inode)| fake_file(&["/f"], size, inode))
+            .collect();
+        let mut dest = Vec::new();
+        write_size_histogram(&mut dest, &files).unwrap();
+        let histogram = String::from_utf8(dest).unwrap();
+        let rows: Vec<(&str, &str, &str)> = histogram
+            .lines()
+            .skip(1)
+            .map(|line| (line[..20].trim(), line[21..33].trim(), line[34..54].trim()))
+            .collect();
+        let mut expected = vec![
+            ("0 B", "1", "0"),
+            ("1 B - 2 B", "1", "1"),
+            ("2 B - 4 B", "2", "6"),
+        ];
+        let empty = [
+            "4 B - 8 B",
+            "8 B - 16 B",
+            "16 B - 32 B",
+            "32 B - 64 B",
+            "64 B - 128 B",
+            "128 B - 256 B",
+            "256 B - 512 B",
+            "512 B - 1 KiB",
+        ];
+        expected.extend(empty.map(|range| (range, "0", "0")));
+        expected.extend([
+            ("1 KiB - 2 KiB", "2", "3,547"),
+            ("2 KiB - 4 KiB", "0", "0"),
+            ("4 KiB - 8 KiB", "1", "5,000"),
+        ]);
+        assert_eq!(rows, expected, "{}", histogram);
+        // The bars are scaled to the biggest count.
+        assert!(
+            histogram.contains(&format!("6 {}\n", "#".repeat(40))),
+            "{}",
+            histogram
+        );
+        assert!(
+            histogram.contains(&format!("5,000 {}\n", "#".repeat(20))),
+            "{}",
+            histogram
+        );
+
+        let mut dest = Vec::new();
+        write_size_histogram(&mut dest, &[]).unwrap();
+        assert_eq!(String::from_utf8(dest).unwrap().lines().count(), 1);
+    }
+}
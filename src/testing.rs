@@ -0,0 +1,125 @@
+// Helpers shared by the tests: scratch directories to build fixtures in, records of files made up
+// for the occasion, and options parsed as if from the command line.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::options::Options;
+use crate::DedupFile;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A directory of our own under the system's temporary directory, removed (with everything in it)
+// when dropped.  Tests run in parallel, so each gets a different one.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "find_dupes-test-{}-{}",
+            process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).expect("Failed to create test directory");
+        // The paths we find are canonical by default, so the fixtures' paths should be too.
+        TempDir(fs::canonicalize(&path).expect("Failed to canonicalize test directory"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    // Write a file at `name` (which may have directories in it) below this directory, creating
+    // any directories it needs.
+    pub fn write(&self, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("Failed to create test directory");
+        }
+        fs::write(&path, content).expect("Failed to write test file");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// A bind mount of one directory onto another, undone when dropped.
+pub struct Mount(PathBuf);
+
+impl Mount {
+    // Bind-mount `source` onto `target`, if we can.  Only root can, so this is `None` otherwise.
+    pub fn bind(source: &Path, target: &Path) -> Option<Mount> {
+        let status = Command::new("mount")
+            .arg("--bind")
+            .arg(source)
+            .arg(target)
+            .stderr(Stdio::null())
+            .status()
+            .ok()?;
+        status.success().then(|| Mount(target.to_path_buf()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.0).status();
+    }
+}
+
+// The record of a real file, as the traversal would make it.
+pub fn file_record(path: &Path) -> DedupFile {
+    let metadata = fs::metadata(path).expect("Failed to read test file metadata");
+    DedupFile {
+        paths: vec![path.to_path_buf()],
+        size: metadata.len(),
+        alloc_size: metadata.blocks() * 512,
+        device: metadata.dev(),
+        inode: metadata.ino(),
+        nlink: metadata.nlink(),
+        mtime: metadata.mtime(),
+        mode: metadata.mode() & 0o7777,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        hash: None,
+        chunk_hashes: None,
+        protected: false,
+    }
+}
+
+// A made-up record of a file with these paths, size and inode number (on device 1), with one link
+// for each path, for tests that never read the file.
+pub fn fake_file(paths: &[&str], size: u64, inode: u64) -> DedupFile {
+    DedupFile {
+        paths: paths.iter().map(PathBuf::from).collect(),
+        size,
+        alloc_size: size,
+        device: 1,
+        inode,
+        nlink: paths.len() as u64,
+        mtime: 0,
+        mode: 0o644,
+        uid: 0,
+        gid: 0,
+        hash: None,
+        chunk_hashes: None,
+        protected: false,
+    }
+}
+
+// The options we'd get from these arguments (after the program name).
+pub fn options(args: &[&str]) -> Options {
+    Options::parse_from(std::iter::once("find_dupes").chain(args.iter().copied()))
+        .expect("Failed to parse test options")
+}
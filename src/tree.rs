@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::summary::{format_bytes, reclaimable_bytes};
+use crate::DedupFile;
+
+// A directory (or file) in the hierarchy of paths to duplicates.
+#[derive(Debug, Default)]
+struct TreeNode {
+    children: BTreeMap<OsString, TreeNode>,
+    // The group this path belongs to, numbered from 1, if it's a duplicate file.
+    group: Option<usize>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, path: &Path, group: usize) {
+        let mut node = self;
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) => name.to_os_string(),
+                Component::ParentDir => OsString::from(".."),
+                Component::CurDir => continue,
+                Component::RootDir | Component::Prefix(_) => continue,
+            };
+            node = node.children.entry(name).or_default();
+        }
+        node.group = Some(group);
+    }
+}
+
+// Write the report as an indented tree of the directories holding duplicates, like `tree` does,
+// with each duplicate file labelled with its group's number and how much space the group could
+// free.  Only directories leading to duplicates are shown, starting from the deepest directory
+// they all share.  Groups are numbered in the order they arrive (so by rank, with `--rank`), and
+// the whole tree has to be built before any of it can be written.
+pub fn write_dupes_tree<G: AsRef<[DedupFile]>>(
+    dest: &mut impl Write,
+    dupes: impl IntoIterator<Item = G>,
+    pretty_sizes: bool,
+) -> io::Result<()> {
+    let mut root = TreeNode::default();
+    let mut reclaimable = Vec::new();
+    let mut absolute = false;
+    for (i, group) in dupes.into_iter().enumerate() {
+        let group = group.as_ref();
+        let bytes = reclaimable_bytes(group);
+        reclaimable.push(if pretty_sizes {
+            format_bytes(bytes)
+        } else {
+            format!("{} bytes", bytes)
+        });
+        for path in group.iter().flat_map(|df| &df.paths) {
+            absolute |= path.is_absolute();
+            root.insert(path, i + 1);
+        }
+    }
+
+    // Skip down through directories with only one child, so that the tree starts where the paths
+    // first diverge.
+    let mut base = PathBuf::from(if absolute { "/" } else { "." });
+    let mut node = &root;
+    while node.group.is_none() && node.children.len() == 1 {
+        let (name, child) = node
+            .children
+            .iter()
+            .next()
+            .expect("Failed to find only child");
+        if child.children.is_empty() {
+            break;
+        }
+        base.push(name);
+        node = child;
+    }
+
+    writeln!(dest, "{}", base.display())?;
+    write_children(dest, node, "", &reclaimable)
+}
+
+fn write_children(
+    dest: &mut impl Write,
+    node: &TreeNode,
+    indent: &str,
+    reclaimable: &[String],
+) -> io::Result<()> {
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == node.children.len();
+        write!(
+            dest,
+            "{}{}{}",
+            indent,
+            if last { "└── " } else { "├── " },
+            display_name(name)
+        )?;
+        match child.group {
+            Some(group) => writeln!(
+                dest,
+                "  [group {}, {} reclaimable]",
+                group,
+                reclaimable[group - 1]
+            )?,
+            None => writeln!(dest)?,
+        }
+        let child_indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+        write_children(dest, child, &child_indent, reclaimable)?;
+    }
+    Ok(())
+}
+
+fn display_name(name: &OsStr) -> String {
+    name.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_file;
+
+    fn tree_report(groups: &[Vec<DedupFile>], pretty_sizes: bool) -> String {
+        let mut dest = Vec::new();
+        write_dupes_tree(&mut dest, groups, pretty_sizes).unwrap();
+        String::from_utf8(dest).unwrap()
+    }
+
+    #[test]
+    fn duplicates_are_shown_in_their_directories_below_the_deepest_shared_one() {
+        let groups = vec![
+            vec![
+                fake_file(&["/home/me/docs/a.txt"], 10, 1),
+                fake_file(&["/home/me/docs/old/a.txt", "/home/me/link"], 10, 2),
+            ],
+            vec![
+                fake_file(&["/home/me/pics/x.jpg"], 2000, 3),
+                fake_file(&["/home/me/pics/y.jpg"], 2000, 4),
+                fake_file(&["/home/me/docs/old/x.jpg"], 2000, 5),
+            ],
+        ];
+        assert_eq!(
+            tree_report(&groups, false),
+            "/home/me
+├── docs
+│   ├── a.txt  [group 1, 10 bytes reclaimable]
+│   └── old
+│       ├── a.txt  [group 1, 10 bytes reclaimable]
+│       └── x.jpg  [group 2, 4000 bytes reclaimable]
+├── link  [group 1, 10 bytes reclaimable]
+└── pics
+    ├── x.jpg  [group 2, 4000 bytes reclaimable]
+    └── y.jpg  [group 2, 4000 bytes reclaimable]
+"
+        );
+    }
+
+    #[test]
+    fn relative_paths_start_from_here() {
+        let groups = vec![vec![
+            fake_file(&["a"], 1536, 1),
+            fake_file(&["sub/b"], 1536, 2),
+        ]];
+        assert_eq!(
+            tree_report(&groups, true),
+            ".
+├── a  [group 1, 1.5 KiB reclaimable]
+└── sub
+    └── b  [group 1, 1.5 KiB reclaimable]
+"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::PathBuf;
+
+use crate::diff::PathGroup;
+use crate::errors::ScanError;
+use crate::group_by_content::{compare_file_bytes, CompareOptions};
+use crate::progress::Phase;
+
+// Whether a group from a saved report still holds.
+#[derive(Clone, Debug)]
+pub enum GroupStatus {
+    // All the files are still there and still the same.
+    Valid,
+    // All the files are still there, but not all of them are the same any more.
+    Diverged,
+    // Some of the files have gone.
+    Missing(Vec<PathBuf>),
+    // We couldn't read some of the files, so we can't tell.
+    Unreadable(ScanError),
+}
+
+// How every group in a report fared.
+#[derive(Debug, Default)]
+pub struct Verification {
+    pub valid: Vec<PathGroup>,
+    pub diverged: Vec<PathGroup>,
+    pub missing: Vec<(PathGroup, Vec<PathBuf>)>,
+    pub unreadable: Vec<(PathGroup, ScanError)>,
+}
+
+impl Verification {
+    // The number of groups that didn't verify.
+    pub fn failures(&self) -> usize {
+        self.diverged.len() + self.missing.len() + self.unreadable.len()
+    }
+}
+
+// Check that every path in a group still exists, and that each has the same content as the
+// first, comparing byte-by-byte just as when the report was made.
+pub fn verify_group(group: &PathGroup, options: CompareOptions) -> GroupStatus {
+    let mut sizes = Vec::new();
+    let mut missing = Vec::new();
+    for path in group {
+        match fs::metadata(path) {
+            Ok(metadata) => sizes.push((path, metadata.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => missing.push(path.clone()),
+            Err(e) => return GroupStatus::Unreadable(ScanError::new(path, Phase::Compare, &e)),
+        }
+    }
+    if !missing.is_empty() {
+        return GroupStatus::Missing(missing);
+    }
+    let Some(&(first, size)) = sizes.first() else {
+        return GroupStatus::Valid;
+    };
+    for &(path, other_size) in &sizes[1..] {
+        if other_size != size {
+            return GroupStatus::Diverged;
+        }
+        match compare_file_bytes(first, path, size, options) {
+            Ok(true) => {}
+            Ok(false) => return GroupStatus::Diverged,
+            Err(e) => return GroupStatus::Unreadable(e),
+        }
+    }
+    GroupStatus::Valid
+}
+
+// Verify every group in a report.
+pub fn verify_report(groups: Vec<PathGroup>, options: CompareOptions) -> Verification {
+    let mut verification = Verification::default();
+    for group in groups {
+        match verify_group(&group, options) {
+            GroupStatus::Valid => verification.valid.push(group),
+            GroupStatus::Diverged => verification.diverged.push(group),
+            GroupStatus::Missing(paths) => verification.missing.push((group, paths)),
+            GroupStatus::Unreadable(e) => verification.unreadable.push((group, e)),
+        }
+    }
+    verification
+}
+
+// Write a human-readable summary of the verification, in the same style as a report diff.
+pub fn write_verification(dest: &mut impl Write, verification: &Verification) -> io::Result<()> {
+    writeln!(dest, "Valid groups: {}", verification.valid.len())?;
+    writeln!(dest, "Diverged groups: {}", verification.diverged.len())?;
+    for group in &verification.diverged {
+        writeln!(dest, "  ! {}", format_paths(group))?;
+    }
+    writeln!(
+        dest,
+        "Groups with missing files: {}",
+        verification.missing.len()
+    )?;
+    for (group, missing) in &verification.missing {
+        writeln!(dest, "  ? {}", format_paths(group))?;
+        writeln!(dest, "    missing: {}", format_paths(missing))?;
+    }
+    writeln!(
+        dest,
+        "Groups with unreadable files: {}",
+        verification.unreadable.len()
+    )?;
+    for (group, error) in &verification.unreadable {
+        writeln!(dest, "  ? {}", format_paths(group))?;
+        writeln!(dest, "    {}: {}", error.path.display(), error.message)?;
+    }
+    dest.flush()
+}
+
+fn format_paths<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> String {
+    paths
+        .into_iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn groups_whose_files_have_changed_or_gone_do_not_verify() {
+        let dir = TempDir::new();
+        let group = |names: &[&str]| -> PathGroup {
+            names.iter().map(|name| dir.write(name, "same")).collect()
+        };
+        let unchanged = group(&["a1", "a2", "a3"]);
+        let edited = group(&["b1", "b2"]);
+        let grown = group(&["c1", "c2"]);
+        let deleted = group(&["d1", "d2", "d3"]);
+        dir.write("b2", "sane");
+        dir.write("c1", "same, and more");
+        fs::remove_file(dir.path().join("d2")).unwrap();
+
+        let options = CompareOptions::default();
+        let groups = vec![
+            unchanged.clone(),
+            edited.clone(),
+            grown.clone(),
+            deleted.clone(),
+        ];
+        let verification = verify_report(groups, options);
+        assert_eq!(verification.valid, [unchanged]);
+        assert_eq!(verification.diverged, [edited, grown]);
+        assert_eq!(verification.missing.len(), 1);
+        assert_eq!(verification.missing[0].0, deleted);
+        assert_eq!(verification.missing[0].1, [dir.path().join("d2")]);
+        assert!(verification.unreadable.is_empty());
+        assert_eq!(verification.failures(), 3);
+    }
+}
@@ -0,0 +1,721 @@
+// Tests of the program as a whole, run as it would be from the command line, for behaviour that
+// only shows in what it writes (or how it exits).
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, Permissions};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A directory of our own under the system's temporary directory, removed (with everything in it)
+// when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "find_dupes-cli-test-{}-{}",
+            process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).expect("Failed to create test directory");
+        TempDir(fs::canonicalize(&path).expect("Failed to canonicalize test directory"))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    // Write a file at `name` (which may have directories in it) below this directory.
+    fn write(&self, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("Failed to create test directory");
+        }
+        fs::write(&path, content).expect("Failed to write test file");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Run the program with these arguments, from `cwd`.
+fn run_in(cwd: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_find_dupes"))
+        .current_dir(cwd)
+        .args(args)
+        .output()
+        .expect("Failed to run find_dupes")
+}
+
+// Run the program from `cwd`, expecting it to succeed, and return what it wrote to stdout.
+fn stdout_of(cwd: &Path, args: &[&str]) -> String {
+    let output = run_in(cwd, args);
+    assert!(
+        output.status.success(),
+        "find_dupes {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("Output isn't UTF-8")
+}
+
+#[test]
+fn relative_to_strips_the_canonical_base_and_leaves_other_paths_absolute() {
+    let dir = TempDir::new();
+    for name in ["base/x", "base/y", "other/z"] {
+        dir.write(name, "hello there\n");
+    }
+    symlink("base", dir.path().join("link")).unwrap();
+    let json = stdout_of(
+        dir.path(),
+        &[
+            "--min-size",
+            "1",
+            "--format",
+            "json",
+            "--relative-to",
+            "link",
+            ".",
+        ],
+    );
+    let other = dir.path().join("other/z");
+    assert!(json.contains(r#""paths": ["x"]"#), "{}", json);
+    assert!(json.contains(r#""paths": ["y"]"#), "{}", json);
+    assert!(
+        json.contains(&format!(r#""paths": ["{}"]"#, other.display())),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn percentile_min_size_is_taken_over_the_whole_tree() {
+    let dir = TempDir::new();
+    for name in ["small1", "small2"] {
+        dir.write(name, [b's'; 10]);
+    }
+    for name in ["big1", "big2", "big3"] {
+        dir.write(name, [b'b'; 1000]);
+    }
+    // Of the sizes 10, 10, 1000, 1000 and 1000, the 40th percentile is 10 and the 41st is 1000.
+    let json = stdout_of(dir.path(), &["--min-size", "p40", "--format", "json", "."]);
+    assert!(
+        json.contains(r#""size": 10,"#) && json.contains(r#""size": 1000,"#),
+        "{}",
+        json
+    );
+    let json = stdout_of(dir.path(), &["--min-size", "p41", "--format", "json", "."]);
+    assert!(
+        !json.contains(r#""size": 10,"#) && json.contains(r#""size": 1000,"#),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn mean_min_size_is_taken_over_the_whole_tree() {
+    let dir = TempDir::new();
+    for name in ["small1", "small2"] {
+        dir.write(name, [b's'; 10]);
+    }
+    for name in ["big1", "big2", "big3"] {
+        dir.write(name, [b'b'; 1000]);
+    }
+    // The sizes are 10, 10, 1000, 1000 and 1000, so the mean is 604.
+    let json = stdout_of(dir.path(), &["--min-size", "mean", "--format", "json", "."]);
+    assert!(
+        !json.contains(r#""size": 10,"#) && json.contains(r#""size": 1000,"#),
+        "{}",
+        json
+    );
+    let json = stdout_of(
+        dir.path(),
+        &["--min-size", "0.01x-mean", "--format", "json", "."],
+    );
+    assert!(
+        json.contains(r#""size": 10,"#) && json.contains(r#""size": 1000,"#),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn io_limit_slows_reading_to_the_rate_given() {
+    let dir = TempDir::new();
+    dir.write("a", vec![b'x'; 40_000]);
+    dir.write("b", vec![b'x'; 40_000]);
+    // Comparing the copies reads 80 kB, of which the first 50 kB are allowed straight away.
+    let start = Instant::now();
+    stdout_of(dir.path(), &["--min-size", "1", "--io-limit", "50kB", "."]);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(550), "{:?}", elapsed);
+}
+
+#[test]
+fn unreadable_subtrees_are_skipped_with_a_warning_after_the_report() {
+    let dir = TempDir::new();
+    dir.write("a", "same");
+    dir.write("b", "same");
+    dir.write("locked/c", "same");
+    let locked = dir.path().join("locked");
+    fs::set_permissions(&locked, Permissions::from_mode(0o000)).unwrap();
+    if fs::read_dir(&locked).is_ok() {
+        fs::set_permissions(&locked, Permissions::from_mode(0o755)).unwrap();
+        eprintln!("Skipping test: permissions aren't enforced for this user");
+        return;
+    }
+    for low_memory in [false, true] {
+        let mut args = vec!["--min-size", "1", "--format", "json", "."];
+        if low_memory {
+            args.insert(0, "--low-memory");
+        }
+        let output = run_in(dir.path(), &args);
+        assert!(output.status.success(), "{:?}", output);
+        let json = String::from_utf8(output.stdout).unwrap();
+        assert!(json.contains(r#""reclaimable_bytes": 5"#), "{}", json);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains(&format!("Warning: skipped {:?}: permission denied", locked)),
+            "{}",
+            stderr
+        );
+    }
+    fs::set_permissions(&locked, Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn missing_dumps_and_devices_stop_the_run_unless_keep_going() {
+    let dir = TempDir::new();
+    dir.write("tree/a", "same");
+    dir.write("tree/b", "same");
+    stdout_of(
+        dir.path(),
+        &["--min-size", "1", "--dump-files", "dump.jsonl", "tree"],
+    );
+
+    let merge = [
+        "--min-size",
+        "1",
+        "--merge-indexes",
+        "dump.jsonl",
+        "missing.jsonl",
+    ];
+    let exclude = ["--min-size", "1", "--exclude-device", "missing", "tree"];
+    for args in [&merge[..], &exclude[..]] {
+        let output = run_in(dir.path(), args);
+        assert!(!output.status.success(), "{:?}", output);
+        assert!(output.stdout.is_empty());
+
+        let output = run_in(dir.path(), &[&["--keep-going"], args].concat());
+        assert!(output.status.success(), "{:?}", output);
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("Warning: Failed to "));
+        assert!(String::from_utf8_lossy(&output.stdout).contains("tree/b"));
+    }
+}
+
+#[test]
+fn progress_json_goes_to_stderr_and_leaves_the_report_alone() {
+    let dir = TempDir::new();
+    dir.write("a", "same");
+    dir.write("b", "same");
+    let args = ["--min-size", "1", "--format", "json", "."];
+    let plain = stdout_of(dir.path(), &args);
+    let output = run_in(dir.path(), &[&["--progress-json"], &args[..]].concat());
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), plain);
+
+    let events = String::from_utf8(output.stderr).unwrap();
+    assert!(!events.is_empty());
+    for event in events.lines() {
+        assert!(
+            event.starts_with(r#"{"dirs":"#) && event.ends_with('}'),
+            "{}",
+            event
+        );
+        assert!(
+            event.contains(r#","files":"#) && event.contains(r#","phase":""#),
+            "{}",
+            event
+        );
+    }
+    // The last event accounts for everything read in comparing the two files.
+    let last = events.lines().last().unwrap();
+    assert!(
+        last.contains(r#""files":2,"bytes_read":8,"phase":"compare""#),
+        "{}",
+        last
+    );
+}
+
+#[test]
+fn paths_that_are_not_utf8_are_reported_without_panicking() {
+    let dir = TempDir::new();
+    for name in [&b"caf\xe9"[..], &b"\xff\xfe"[..]] {
+        fs::write(dir.path().join(OsStr::from_bytes(name)), "same").unwrap();
+    }
+    for format in ["json", "html", "dot", "tree"] {
+        let output = run_in(dir.path(), &["--min-size", "1", "--format", format, "."]);
+        assert!(output.status.success(), "{}: {:?}", format, output);
+    }
+    let json = stdout_of(dir.path(), &["--min-size", "1", "--format", "json", "."]);
+    assert!(
+        json.contains(r#"/caf\udce9""#) && json.contains(r#"/\udcff\udcfe""#),
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn listing_by_directory_does_not_depend_on_the_order_files_were_made() {
+    let listings: Vec<String> = [["x/1", "y/1", "y/2"], ["y/2", "y/1", "x/1"]]
+        .iter()
+        .map(|names| {
+            let dir = TempDir::new();
+            for name in names {
+                dir.write(name, "same");
+            }
+            stdout_of(
+                dir.path(),
+                &[
+                    "--min-size",
+                    "1",
+                    "--group-output-by-dir",
+                    "--format",
+                    "json",
+                    "--cwd-relative",
+                    ".",
+                ],
+            )
+        })
+        .collect();
+    assert_eq!(listings[0], listings[1]);
+    assert!(listings[0].contains(r#""kept": "x/1""#), "{}", listings[0]);
+}
+
+#[test]
+fn paths_are_all_canonical_or_all_as_given_through_symlinks() {
+    let dir = TempDir::new();
+    dir.write("real/a", "same");
+    dir.write("real/sub/b", "same");
+    dir.write("other/c", "same");
+    symlink("real", dir.path().join("link")).unwrap();
+    symlink("../other", dir.path().join("real/elsewhere")).unwrap();
+    let paths_found = |style: &str| {
+        let json = stdout_of(
+            dir.path(),
+            &[
+                "--min-size",
+                "1",
+                "--format",
+                "json",
+                "--symlinks",
+                "follow",
+                "--paths",
+                style,
+                "link",
+            ],
+        );
+        json.split(r#""paths": [""#)
+            .skip(1)
+            .map(|rest| rest.split('"').next().unwrap().to_string())
+            .collect::<Vec<String>>()
+    };
+    // A symlinked root is resolved for canonical paths, but a symlink below it is followed
+    // rather than resolved, either way.
+    let real = dir.path().join("real");
+    let canonical = ["a", "elsewhere/c", "sub/b"].map(|name| real.join(name).display().to_string());
+    assert_eq!(paths_found("canonical"), canonical);
+    assert_eq!(
+        paths_found("as-given"),
+        ["link/a", "link/elsewhere/c", "link/sub/b"]
+    );
+
+    // A root we can't canonicalize is an error, rather than left as it is.
+    symlink("nowhere", dir.path().join("dangling")).unwrap();
+    let output = run_in(dir.path(), &["--min-size", "1", "dangling"]);
+    assert!(!output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to canonicalize PATH"));
+}
+
+#[test]
+fn verify_fails_once_a_reported_file_has_changed() {
+    let dir = TempDir::new();
+    dir.write("a", "same");
+    dir.write("b", "same");
+    let report = stdout_of(dir.path(), &["--min-size", "1", "--format", "json", "."]);
+    dir.write("report.json", report);
+    let verified = stdout_of(dir.path(), &["--verify", "report.json"]);
+    assert!(
+        verified.starts_with("Valid groups: 1\nDiverged groups: 0\n"),
+        "{}",
+        verified
+    );
+
+    dir.write("b", "sane");
+    let output = run_in(dir.path(), &["--verify", "report.json"]);
+    assert!(!output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!(
+            "Valid groups: 0\nDiverged groups: 1\n  ! {}, {}\n",
+            dir.path().join("a").display(),
+            dir.path().join("b").display()
+        )),
+        "{}",
+        stdout
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("1 group(s) in \"report.json\" failed to verify"));
+}
+
+#[test]
+fn count_only_and_wasted_bytes_give_the_totals_for_the_tree() {
+    let dir = TempDir::new();
+    for name in ["a1", "a2", "sub/a3"] {
+        dir.write(name, [b'a'; 10]);
+    }
+    for name in ["b1", "sub/b2"] {
+        dir.write(name, [b'b'; 1000]);
+    }
+    dir.write("unique", [b'c'; 1000]);
+    let counts = |style: &str| stdout_of(dir.path(), &["--min-size", "1", style, "."]);
+    assert_eq!(counts("--count-only"), "2\t3\t1020\n");
+    assert_eq!(counts("--count-only=tsv"), "2\t3\t1020\n");
+    assert_eq!(
+        counts("--count-only=json"),
+        "{\"groups\": 2, \"redundant_files\": 3, \"reclaimable_bytes\": 1020}\n"
+    );
+    assert_eq!(counts("--wasted-bytes"), "1020\n");
+}
+
+#[test]
+fn a_spent_time_budget_gives_the_groups_found_so_far_marked_truncated() {
+    let dir = TempDir::new();
+    for i in 0..2000 {
+        dir.write(&format!("{}/{}", i % 20, i), [0; 4096]);
+    }
+    let args = ["--min-size", "1", "--format", "json", "."];
+    let complete = stdout_of(dir.path(), &args);
+    assert!(!complete.contains("truncated"), "{}", complete);
+    assert_eq!(complete.matches(r#""inode": "#).count(), 2000);
+
+    let output = run_in(
+        dir.path(),
+        &[&["--max-runtime", "0.001s"], &args[..]].concat(),
+    );
+    assert!(output.status.success());
+    let partial = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        partial.contains(r#""truncated": "time limit""#),
+        "{}",
+        partial
+    );
+    assert!(
+        partial.matches(r#""inode": "#).count() < 2000,
+        "{}",
+        partial
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("truncated due to time limit"));
+}
+
+#[test]
+fn savings_by_directory_do_not_depend_on_the_order_files_were_made() {
+    let tables: Vec<String> = [
+        ["a/1/x", "a/2/x", "b/x", "b/y"],
+        ["b/y", "b/x", "a/2/x", "a/1/x"],
+    ]
+    .iter()
+    .map(|names| {
+        let dir = TempDir::new();
+        for name in names {
+            dir.write(name, "same");
+        }
+        let args = [
+            "--min-size",
+            "1",
+            "--savings-by-dir",
+            "1",
+            "--cwd-relative",
+            ".",
+        ];
+        let output = run_in(dir.path(), &args);
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    })
+    .collect();
+    assert_eq!(tables[0], tables[1]);
+    assert!(tables[0].ends_with("b\t2\t8\na\t1\t4\n"), "{}", tables[0]);
+}
+
+#[test]
+fn diff_offsets_say_where_two_files_first_differ() {
+    let dir = TempDir::new();
+    dir.write("a", "hello, world");
+    dir.write("b", "hello, world");
+    dir.write("c", "hello, World");
+    dir.write("d", "hello");
+    let diff = |file1, file2| stdout_of(dir.path(), &["--diff-offsets", file1, file2]);
+    assert_eq!(diff("a", "b"), "Identical\n");
+    assert_eq!(diff("a", "c"), "First difference at byte 7\n");
+    assert_eq!(
+        diff("a", "d"),
+        "First difference at byte 5 (sizes differ: 12 and 5 bytes)\n"
+    );
+    let output = run_in(dir.path(), &["--diff-offsets", "a", "missing"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn files_open_for_writing_elsewhere_are_left_out_and_listed() {
+    let dir = TempDir::new();
+    let elsewhere = TempDir::new();
+    for name in ["a", "b", "held"] {
+        dir.write(name, "same");
+    }
+    let ready = elsewhere.path().join("ready");
+    let mut holder = Command::new("sh")
+        .args(["-c", "exec 3>>held; touch \"$1\"; exec sleep 30", "sh"])
+        .arg(&ready)
+        .current_dir(dir.path())
+        .spawn()
+        .expect("Failed to run sh");
+    while !ready.exists() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let args = ["--min-size", "1", "--format", "json", "--cwd-relative"];
+    let output = run_in(
+        dir.path(),
+        &[&args[..], &["--skip-open-files", "."]].concat(),
+    );
+    let everything = stdout_of(dir.path(), &[&args[..], &["."]].concat());
+    holder.kill().unwrap();
+    holder.wait().unwrap();
+    assert!(output.status.success());
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        report.contains(r#"["a"]"#) && report.contains(r#"["b"]"#),
+        "{}",
+        report
+    );
+    assert!(!report.contains("held"), "{}", report);
+    assert!(everything.contains(r#"["held"]"#), "{}", everything);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("left out 1 file(s) open for writing by other processes:\n  held\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn explain_traces_each_comparison_in_a_bucket() {
+    let dir = TempDir::new();
+    for (name, content) in [("a", "aaaa"), ("b", "bbbb"), ("c", "cccc"), ("d", "dddd")] {
+        dir.write(name, content);
+    }
+    for name in ["x", "y", "z"] {
+        dir.write(name, "same!");
+    }
+    dir.write("lonely", "1234567");
+    let output = run_in(
+        dir.path(),
+        &["--min-size", "1", "--explain", "--format", "json", "."],
+    );
+    assert!(output.status.success());
+    let trace = String::from_utf8(output.stderr).unwrap();
+    let events = |prefix: &str| {
+        trace
+            .lines()
+            .filter(|line| line.starts_with(prefix))
+            .count()
+    };
+
+    // Four different files are each compared with every one before: 0 + 1 + 2 + 3 times.
+    assert!(
+        trace.contains(r#"{"event":"bucket","size":4,"files":4}"#),
+        "{}",
+        trace
+    );
+    assert_eq!(events(r#"{"event":"compare","size":4,"#), 6, "{}", trace);
+    assert!(
+        trace.contains(r#"{"event":"bucket_done","size":4,"comparisons":6,"groups":0}"#),
+        "{}",
+        trace
+    );
+    assert_eq!(
+        events(r#"{"event":"singleton","stage":"content","#),
+        4,
+        "{}",
+        trace
+    );
+    // Three the same are each compared with the first only.
+    assert_eq!(events(r#"{"event":"compare","size":5,"#), 2, "{}", trace);
+    assert!(
+        trace.contains(r#"{"event":"bucket_done","size":5,"comparisons":2,"groups":1}"#),
+        "{}",
+        trace
+    );
+    assert_eq!(
+        events(r#"{"event":"singleton","stage":"size","#),
+        1,
+        "{}",
+        trace
+    );
+    assert!(
+        trace.lines().any(
+            |line| line.starts_with(r#"{"event":"singleton","stage":"size","#)
+                && line.ends_with(r#"/lonely"}"#)
+        ),
+        "{}",
+        trace
+    );
+}
+
+#[test]
+fn probe_counts_the_files_and_bytes_that_would_be_compared() {
+    let dir = TempDir::new();
+    for (name, content) in [
+        ("a", "0123456789"),
+        ("b", "9876543210"),
+        ("c", "0123456789"),
+    ] {
+        dir.write(name, content);
+    }
+    fs::hard_link(dir.path().join("a"), dir.path().join("a-link")).unwrap();
+    dir.write("big1", [1; 1000]);
+    dir.write("big2", [2; 1000]);
+    dir.write("unique", [3; 50]);
+    assert_eq!(
+        stdout_of(dir.path(), &["--min-size", "1", "--probe", "."]),
+        "Files to compare: 5\nSize groups: 2\nBytes to read (at most): 2,030\n"
+    );
+    assert_eq!(
+        stdout_of(
+            dir.path(),
+            &["--min-size", "1", "--probe", "--compare-limit", "100", "."]
+        ),
+        "Files to compare: 5\nSize groups: 2\nBytes to read (at most): 230\n"
+    );
+}
+
+#[test]
+fn broken_symlinks_are_listed_after_the_report() {
+    let dir = TempDir::new();
+    dir.write("a", "same content");
+    dir.write("b", "same content");
+    symlink("nowhere", dir.path().join("broken")).unwrap();
+    dir.write("sub/c", "other");
+    symlink("../gone", dir.path().join("sub/also-broken")).unwrap();
+    symlink("a", dir.path().join("fine")).unwrap();
+    let expected = format!(
+        "Broken symlinks: 2\n  {0}/broken -> nowhere\n  {0}/sub/also-broken -> ../gone\n",
+        dir.path().display()
+    );
+    for symlinks in ["skip", "follow"] {
+        let args = ["--min-size", "1", "--symlinks", symlinks, "."];
+        let output = run_in(
+            dir.path(),
+            &[&["--find-broken-symlinks"], &args[..]].concat(),
+        );
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.ends_with(&expected), "{}", stderr);
+        // The duplicates are still reported as ever.
+        let report = String::from_utf8(output.stdout).unwrap();
+        assert!(report.contains("/b"), "{}", report);
+        assert_eq!(report, stdout_of(dir.path(), &args));
+
+        let output = run_in(dir.path(), &args);
+        assert!(!String::from_utf8(output.stderr).unwrap().contains("Broken"));
+    }
+}
+
+#[test]
+fn the_index_is_the_same_every_time_it_is_built() {
+    let dir = TempDir::new();
+    for i in 0..20 {
+        dir.write(&format!("tree/unique-{}", i), format!("content {}", i));
+        dir.write(&format!("tree/copy-{}", i % 4), "copied");
+    }
+    let index = |name: &str| {
+        let index_path = dir.path().join(name);
+        let args = ["--emit-index", index_path.to_str().unwrap()];
+        let rest = ["--include-unique", "--min-size", "1", "tree"];
+        stdout_of(dir.path(), &[&args[..], &rest].concat());
+        fs::read(index_path).unwrap()
+    };
+    let first = index("index-1.json");
+    assert!(first.windows(8).any(|w| w == b"unique-7"));
+    for i in 2..=4 {
+        assert_eq!(index(&format!("index-{}.json", i)), first);
+    }
+}
+
+#[test]
+fn dumps_of_an_unchanged_tree_are_the_same() {
+    let dir = TempDir::new();
+    for i in 0..20 {
+        dir.write(&format!("tree/file-{}", i), format!("content {}", i % 5));
+    }
+    let dump = |name: &str| {
+        stdout_of(
+            dir.path(),
+            &["--min-size", "1", "--dump-files", name, "tree"],
+        );
+        fs::read_to_string(dir.path().join(name)).unwrap()
+    };
+    let first = dump("dump-1.jsonl");
+    assert_eq!(first.lines().count(), 20);
+    for i in 2..=4 {
+        assert_eq!(dump(&format!("dump-{}.jsonl", i)), first);
+    }
+}
+
+#[test]
+fn the_explain_trace_is_the_same_sequence_every_run() {
+    let dir = TempDir::new();
+    for (name, content) in [
+        ("a", "aa"),
+        ("b", "bb"),
+        ("c", "aa"),
+        ("lonely", "xyz"),
+        ("p", "12345"),
+        ("q", "12345"),
+    ] {
+        dir.write(name, content);
+    }
+    let expected = [
+        r#"{"event":"singleton","stage":"size","path":"D/lonely"}"#,
+        r#"{"event":"bucket","size":2,"files":3}"#,
+        r#"{"event":"compare","size":2,"files":["D/b","D/a"],"equal":false}"#,
+        r#"{"event":"compare","size":2,"files":["D/c","D/a"],"equal":true}"#,
+        r#"{"event":"singleton","stage":"content","path":"D/b"}"#,
+        r#"{"event":"bucket_done","size":2,"comparisons":2,"groups":1}"#,
+        r#"{"event":"bucket","size":5,"files":2}"#,
+        r#"{"event":"compare","size":5,"files":["D/q","D/p"],"equal":true}"#,
+        r#"{"event":"bucket_done","size":5,"comparisons":1,"groups":1}"#,
+    ]
+    .map(|line| line.replace("D/", &format!("{}/", dir.path().display())))
+    .join("\n")
+        + "\n";
+    for _ in 0..3 {
+        let output = run_in(
+            dir.path(),
+            &["--min-size", "1", "--explain", "--format", "json", "."],
+        );
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), expected);
+    }
+}